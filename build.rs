@@ -0,0 +1,54 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Reruns the build script when `path` changes. A missing path would make it
+/// rerun on every build, e.g. outside of a git checkout.
+fn rerun_if_changed(path: &str) {
+    if Path::new(path).exists() {
+        println!("cargo:rerun-if-changed={}", path);
+    }
+}
+
+/// Records the git commit and the genai version for `ait --build-info`.
+fn main() {
+    let git_sha = Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|sha| sha.trim().to_string())
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=AIT_GIT_SHA={}", git_sha);
+    rerun_if_changed(".git/HEAD");
+    // HEAD only changes when switching branches, commits change the branch,
+    // which may also be packed
+    if let Ok(head) = std::fs::read_to_string(".git/HEAD") {
+        if let Some(branch) = head.trim().strip_prefix("ref: ") {
+            rerun_if_changed(&format!(".git/{}", branch));
+        }
+    }
+    rerun_if_changed(".git/packed-refs");
+
+    // The resolved version, the manifest only gives the requirement
+    let genai_version = std::fs::read_to_string("Cargo.lock")
+        .ok()
+        .and_then(|lock| {
+            lock.split("[[package]]")
+                .find(|package| package.contains("name = \"genai\""))
+                .and_then(|package| {
+                    package
+                        .lines()
+                        .find_map(|line| line.strip_prefix("version = "))
+                        .map(|version| version.trim_matches('"').to_string())
+                })
+        })
+        .unwrap_or_else(|| "unknown".to_string());
+    println!("cargo:rustc-env=AIT_GENAI_VERSION={}", genai_version);
+    rerun_if_changed("Cargo.lock");
+
+    println!(
+        "cargo:rustc-env=AIT_TARGET={}",
+        std::env::var("TARGET").unwrap_or_default()
+    );
+}
@@ -1,17 +1,153 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use fancy_regex::Regex;
+use futures::future::join_all;
+use futures::StreamExt;
 use genai::adapter::AdapterKind;
-use genai::chat::{ChatMessage, ChatOptions, ChatRequest};
-use genai::{Client, ClientBuilder, ClientConfig};
+use genai::chat::{ChatMessage, ChatOptions, ChatRequest, ChatStreamEvent};
+use genai::resolver::{AuthData, Endpoint, ServiceTargetResolver};
+use genai::{webc, Client, ModelIden, ServiceTarget};
+use reqwest::StatusCode;
+use tokio::sync::mpsc;
 
-use crate::app::{AppResult, Message};
+use crate::app::{AppResult, Message, ResponseInfo};
 
-pub const MODELS: [(&str, &str); 5] = [
+pub const MODELS: [(&str, &str); 7] = [
     ("OpenAI", "gpt-4o-mini"),
     ("OpenAI", "gpt-4o"),
     ("Anthropic", "claude-3-5-sonnet-latest"),
     ("Anthropic", "claude-3-haiku-20240307"),
     ("Ollama", "gemma:2b"),
+    ("OpenRouter", "openrouter/meta-llama/llama-3.1-70b-instruct"),
+    ("OpenRouter", "openrouter/mistralai/mistral-large"),
 ];
 
+/// Parses `provider:model` entries, e.g. `Ollama:gemma:2b`, into the models
+/// listed when providers don't list them themselves.
+pub fn parse_fallback_models(entries: &[String]) -> AppResult<Vec<(String, String)>> {
+    entries
+        .iter()
+        .map(|entry| {
+            let (provider, model) = entry
+                .split_once(':')
+                .filter(|(_, model)| !model.is_empty())
+                .with_context(|| {
+                    format!(
+                        "Invalid fallback model `{}`, expected `provider:model`",
+                        entry
+                    )
+                })?;
+            let provider = KINDS
+                .iter()
+                .map(|kind| kind.as_str())
+                .chain([OPENROUTER])
+                .find(|name| name.eq_ignore_ascii_case(provider.trim()))
+                .with_context(|| {
+                    format!(
+                        "Unknown provider `{}` of fallback model `{}`",
+                        provider, entry
+                    )
+                })?;
+            Ok((provider.to_string(), model.trim().to_string()))
+        })
+        .collect()
+}
+
+/// The built-in fallback models, used when none are configured.
+pub fn default_fallback_models() -> Vec<(String, String)> {
+    MODELS
+        .iter()
+        .map(|(provider, model)| (provider.to_string(), model.to_string()))
+        .collect()
+}
+
+/// Model selected when the application starts.
+pub const DEFAULT_MODEL: &str = "claude-3-5-sonnet-latest";
+
+/// Model selected when the remembered model isn't available anymore.
+pub const FALLBACK_MODEL: &str = "gpt-4o-mini";
+
+const OLLAMA_URL: &str = "http://localhost:11434";
+
+/// OpenRouter isn't a genai adapter, its models are named `openrouter/<id>`
+/// and sent to its OpenAI compatible API.
+const OPENROUTER: &str = "OpenRouter";
+const OPENROUTER_PREFIX: &str = "openrouter/";
+const OPENROUTER_URL: &str = "https://openrouter.ai/api/v1/";
+const OPENROUTER_API_KEY: &str = "OPENROUTER_API_KEY";
+
+/// Creates a client that sends `openrouter/` models to OpenRouter.
+fn client() -> Client {
+    Client::builder()
+        .with_service_target_resolver(ServiceTargetResolver::from_resolver_fn(
+            |target: ServiceTarget| -> genai::resolver::Result<ServiceTarget> {
+                let Some(id) = target.model.model_name.strip_prefix(OPENROUTER_PREFIX) else {
+                    return Ok(target);
+                };
+                Ok(ServiceTarget {
+                    endpoint: Endpoint::from_static(OPENROUTER_URL),
+                    auth: AuthData::from_env(OPENROUTER_API_KEY),
+                    model: ModelIden::new(AdapterKind::OpenAI, id),
+                })
+            },
+        ))
+        .build()
+}
+
+/// Name of the provider serving `model`.
+fn provider_name(model: &str) -> Option<String> {
+    if model.starts_with(OPENROUTER_PREFIX) {
+        return Some(OPENROUTER.to_string());
+    }
+    AdapterKind::from_model(model)
+        .ok()
+        .map(|kind| kind.as_str().to_string())
+}
+
+/// Lists the models available from OpenRouter, prefixed with `openrouter/`.
+async fn openrouter_models() -> AppResult<Vec<String>> {
+    let key = std::env::var(OPENROUTER_API_KEY).context("OPENROUTER_API_KEY isn't set")?;
+    let res: serde_json::Value = reqwest::Client::new()
+        .get(format!("{OPENROUTER_URL}models"))
+        .bearer_auth(key)
+        .send()
+        .await
+        .and_then(|res| res.error_for_status())
+        .context("Failed to request the models")?
+        .json()
+        .await
+        .context("Failed to parse the models")?;
+    let models = res["data"]
+        .as_array()
+        .context("The models response has no data")?
+        .iter()
+        .filter_map(|model| model["id"].as_str())
+        .map(|id| format!("{OPENROUTER_PREFIX}{id}"))
+        .collect();
+    Ok(models)
+}
+
+/// Checks whether an Ollama server is reachable, using a short timeout so that
+/// startup isn't delayed when it isn't running.
+async fn ollama_is_available() -> bool {
+    let client = match reqwest::Client::builder()
+        .timeout(Duration::from_millis(500))
+        .build()
+    {
+        Ok(client) => client,
+        Err(_) => return false,
+    };
+    client
+        .get(OLLAMA_URL)
+        .send()
+        .await
+        .is_ok_and(|res| res.status().is_success())
+}
+
 fn get_api_key_name(kind: &AdapterKind) -> &'static str {
     match kind {
         AdapterKind::OpenAI => "OPENAI_API_KEY",
@@ -24,52 +160,353 @@ fn get_api_key_name(kind: &AdapterKind) -> &'static str {
     }
 }
 
-pub async fn get_models() -> AppResult<Vec<(String, String)>> {
-    const KINDS: &[AdapterKind] = &[
-        AdapterKind::OpenAI,
-        AdapterKind::Ollama,
-        AdapterKind::Gemini,
-        AdapterKind::Anthropic,
-        AdapterKind::Groq,
-        AdapterKind::Cohere,
-        AdapterKind::Xai,
-    ];
+/// Providers models are discovered from.
+const KINDS: &[AdapterKind] = &[
+    AdapterKind::OpenAI,
+    AdapterKind::Ollama,
+    AdapterKind::Gemini,
+    AdapterKind::Anthropic,
+    AdapterKind::Groq,
+    AdapterKind::Cohere,
+    AdapterKind::Xai,
+];
+
+/// Makes API keys given by provider name, e.g. `openai`, available to the
+/// providers. Keys already set in the environment are kept.
+///
+/// The keys are set as environment variables, so this has to be called before
+/// any other thread is started.
+///
+/// Returns a warning for each key of an unknown provider.
+pub fn set_api_keys(keys: &BTreeMap<String, String>) -> Vec<String> {
+    let mut warnings = Vec::new();
+    for (provider, key) in keys {
+        let kind = KINDS
+            .iter()
+            .find(|kind| kind.as_str().eq_ignore_ascii_case(provider));
+        let env_name = match kind {
+            _ if provider.eq_ignore_ascii_case(OPENROUTER) => OPENROUTER_API_KEY,
+            Some(kind) if !get_api_key_name(kind).is_empty() => get_api_key_name(kind),
+            _ => {
+                warnings.push(format!(
+                    "Ignoring the API key of unknown provider `{}`",
+                    provider
+                ));
+                continue;
+            }
+        };
+        if std::env::var_os(env_name).is_none() {
+            std::env::set_var(env_name, key);
+        }
+    }
+    warnings
+}
 
-    let client = Client::default();
+/// Discovers the models available from all configured providers, merged with
+/// the `fallback_models` of those providers that weren't discovered.
+///
+/// Returns the discovered `(provider, model)` pairs together with a list of
+/// warnings for providers that are configured but failed to list their models.
+pub async fn get_models(
+    fallback_models: &[(String, String)],
+) -> AppResult<(Vec<(String, String)>, Vec<String>)> {
+    let client = client();
     let mut models = Vec::new();
+    let mut warnings = Vec::new();
+    let mut configured = Vec::new();
     for &kind in KINDS {
         let env_name = get_api_key_name(&kind);
         if !env_name.is_empty() && std::env::var(env_name).is_err() {
             continue;
         }
+        if kind == AdapterKind::Ollama && !ollama_is_available().await {
+            continue;
+        }
+        configured.push(kind.as_str());
         let models_provider_res = client.all_model_names(kind).await;
-        let mut models_provider = match models_provider_res {
+        let models_provider = match models_provider_res {
             Ok(m) => m
                 .into_iter()
                 .map(|m| (kind.as_str().to_string(), m))
                 .collect::<Vec<(String, String)>>(),
-            Err(_) => Vec::new(),
+            Err(e) => {
+                warnings.push(format!("{}: failed to list models ({})", kind.as_str(), e));
+                Vec::new()
+            }
         };
-        if kind == AdapterKind::Anthropic {
-            models_provider.push((kind.as_str().into(), "claude-3-5-sonnet-latest".to_string()))
-        }
         models.extend(models_provider);
     }
-    Ok(models)
+    if std::env::var(OPENROUTER_API_KEY).is_ok() {
+        configured.push(OPENROUTER);
+        match openrouter_models().await {
+            Ok(openrouter) => models.extend(
+                openrouter
+                    .into_iter()
+                    .map(|model| (OPENROUTER.to_string(), model)),
+            ),
+            Err(e) => warnings.push(format!("{OPENROUTER}: failed to list models ({e:#})")),
+        }
+    }
+    merge_fallback_models(&mut models, fallback_models, &configured);
+    Ok((models, warnings))
 }
 
-pub async fn assistant_response(
+/// Adds the fallback models of the `configured` providers that aren't in
+/// `models` yet.
+fn merge_fallback_models(
+    models: &mut Vec<(String, String)>,
+    fallback_models: &[(String, String)],
+    configured: &[&str],
+) {
+    for fallback in fallback_models {
+        if configured.contains(&fallback.0.as_str()) && !models.contains(fallback) {
+            models.push(fallback.clone());
+        }
+    }
+}
+
+/// Why a response couldn't be generated.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AiError {
+    /// The API key is missing or was rejected
+    Auth(String),
+    /// Too many requests were made to the provider
+    RateLimit(String),
+    /// The provider couldn't be reached
+    Network(String),
+    /// The provider took too long to respond
+    Timeout(String),
+    /// The model can't answer this kind of request
+    Unsupported(String),
+    Other(String),
+}
+
+impl AiError {
+    fn from_status(status: StatusCode, detail: String) -> Self {
+        match status {
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Self::Auth(detail),
+            StatusCode::TOO_MANY_REQUESTS => Self::RateLimit(detail),
+            StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => Self::Timeout(detail),
+            _ => Self::Other(detail),
+        }
+    }
+
+    fn from_reqwest(e: &reqwest::Error) -> Self {
+        if e.is_timeout() {
+            Self::Timeout(e.to_string())
+        } else {
+            Self::Network(e.to_string())
+        }
+    }
+
+    /// Could sending the same request again succeed?
+    pub fn is_retryable(&self) -> bool {
+        !matches!(self, Self::Auth(_) | Self::Unsupported(_))
+    }
+
+    fn from_webc(e: &webc::Error) -> Self {
+        match e {
+            webc::Error::ResponseFailedStatus { status, body } => {
+                Self::from_status(*status, format!("{} {}", status, body))
+            }
+            webc::Error::Reqwest(e) => Self::from_reqwest(e),
+            e => Self::Other(e.to_string()),
+        }
+    }
+}
+
+impl From<genai::Error> for AiError {
+    fn from(e: genai::Error) -> Self {
+        use genai::Error;
+        match &e {
+            Error::RequiresApiKey { .. }
+            | Error::NoAuthResolver { .. }
+            | Error::NoAuthData { .. } => Self::Auth(e.to_string()),
+            Error::Resolver {
+                resolver_error: genai::resolver::Error::ApiKeyEnvNotFound { env_name },
+                ..
+            } => Self::Auth(format!("{} is not set", env_name)),
+            Error::ChatReqHasNoMessages { .. }
+            | Error::LastChatMessageIsNotUser { .. }
+            | Error::MessageRoleNotSupported { .. }
+            | Error::MessageContentTypeNotSupported { .. }
+            | Error::JsonModeWithoutInstruction => Self::Unsupported(e.to_string()),
+            Error::WebAdapterCall { webc_error, .. } | Error::WebModelCall { webc_error, .. } => {
+                Self::from_webc(webc_error)
+            }
+            Error::ReqwestEventSource(reqwest_eventsource::Error::InvalidStatusCode(status, _)) => {
+                Self::from_status(*status, e.to_string())
+            }
+            Error::ReqwestEventSource(reqwest_eventsource::Error::Transport(e)) => {
+                Self::from_reqwest(e)
+            }
+            _ => Self::Other(e.to_string()),
+        }
+    }
+}
+
+impl fmt::Display for AiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Auth(detail) => {
+                write!(f, "Authentication failed, check the API key ({})", detail)
+            }
+            Self::RateLimit(detail) => write!(f, "Rate limited by the provider ({})", detail),
+            Self::Network(detail) => write!(f, "Unable to reach the provider ({})", detail),
+            Self::Timeout(detail) => {
+                write!(f, "The provider took too long to respond ({})", detail)
+            }
+            Self::Unsupported(detail) => write!(f, "Not supported by the model ({})", detail),
+            Self::Other(detail) => write!(f, "{}", detail),
+        }
+    }
+}
+
+impl std::error::Error for AiError {}
+
+impl From<AiError> for Message {
+    fn from(e: AiError) -> Self {
+        Message::Error(format!("Error: {}", e))
+    }
+}
+
+/// Rough number of tokens of a text with `n_characters` characters, using the
+/// common estimate of four characters per token.
+pub fn estimate_tokens(n_characters: usize) -> usize {
+    n_characters.div_ceil(4)
+}
+
+/// Context windows in tokens of known models, by prefix of the model name.
+const CONTEXT_LIMITS: [(&str, usize); 5] = [
+    ("gpt-4o", 128_000),
+    ("gpt-4-turbo", 128_000),
+    ("claude-3", 200_000),
+    ("gemini-1.5-pro", 2_000_000),
+    ("gemini-1.5-flash", 1_000_000),
+];
+
+/// Number of tokens fitting in the context window of a model, if known.
+///
+/// Routed models are looked up by the name after their route, e.g. `gpt-4o`
+/// for `openrouter/openai/gpt-4o`.
+pub fn context_limit(model: &str) -> Option<usize> {
+    let name = model.rsplit('/').next().unwrap_or(model);
+    CONTEXT_LIMITS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, limit)| *limit)
+}
+
+/// Compiles patterns matching boilerplate at the start of responses.
+pub fn compile_prefix_patterns(patterns: &[String]) -> AppResult<Vec<Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| {
+            // Only the start of a response is trimmed, along with the
+            // whitespace after the boilerplate
+            Regex::new(&format!(r"\A(?:{})\s*", pattern))
+                .with_context(|| format!("Invalid response prefix pattern `{}`", pattern))
+        })
+        .collect()
+}
+
+/// Removes the text matched by each of `patterns` from the start of a
+/// response, in order.
+pub fn trim_response_prefixes<'a>(mut text: &'a str, patterns: &[Regex]) -> &'a str {
+    for pattern in patterns {
+        if let Ok(Some(m)) = pattern.find(text) {
+            text = &text[m.end()..];
+        }
+    }
+    text
+}
+
+/// Describes a response of `model` generated with `chat_opts`.
+fn response_info(model: &str, chat_opts: &ChatOptions) -> ResponseInfo {
+    ResponseInfo {
+        temperature: chat_opts.temperature,
+        provider: provider_name(model),
+        model: Some(model.to_string()),
+    }
+}
+
+/// Returns the messages of the most recent turns to send, at most
+/// `history_limit` of them. A turn is a user message and everything after it
+/// until the next one.
+///
+/// The window never starts with an assistant message, so the history sent to
+/// the provider always begins with a user turn.
+fn history_window(messages: &[Message], history_limit: Option<usize>) -> &[Message] {
+    let Some(limit) = history_limit else {
+        return messages;
+    };
+    let mut user_messages = messages
+        .iter()
+        .enumerate()
+        .filter(|(_, m)| matches!(m, Message::User(_)));
+    let start = user_messages
+        .clone()
+        .rev()
+        .nth(limit.saturating_sub(1))
+        // There are fewer turns than the limit
+        .or_else(|| user_messages.next())
+        .map_or(messages.len(), |(i, _)| i);
+    &messages[start..]
+}
+
+/// Rough number of tokens of a request with the system prompt and the
+/// turns within the history limit. Errors and notes aren't sent.
+pub fn estimate_request_tokens(
+    messages: &[Message],
+    system_prompt: &str,
+    history_limit: Option<usize>,
+) -> usize {
+    let n_characters = history_window(messages, history_limit)
+        .iter()
+        .filter(|m| matches!(m, Message::User(_) | Message::Assistant(..)))
+        .map(|m| m.as_str().chars().count())
+        .sum::<usize>();
+    estimate_tokens(n_characters + system_prompt.chars().count())
+}
+
+/// Settings of a request besides the conversation and the sampling options.
+#[derive(Debug, Clone, Copy)]
+pub struct RequestOptions<'a> {
+    pub model: &'a str,
+    /// Maximum number of most recent turns sent
+    pub history_limit: Option<usize>,
+}
+
+/// Reasoning models reject requests that set a temperature.
+fn supports_temperature(model: &str) -> bool {
+    !["o1", "o3", "o4"]
+        .iter()
+        .any(|prefix| model.starts_with(prefix))
+}
+
+/// Assembles the request sent for a response to `messages` together with its
+/// options.
+pub fn build_chat_request(
     messages: &[Message],
-    model: &str,
     system_prompt: &str,
     temperature: &f64,
-) -> AppResult<Message> {
-    let chat_messages = messages
+    opts: &RequestOptions,
+) -> (ChatRequest, ChatOptions) {
+    // Errors and notes are only shown to the user, the model never sees them
+    let turns = history_window(messages, opts.history_limit)
+        .iter()
+        .filter(|m| matches!(m, Message::User(_) | Message::Assistant(..)))
+        .collect::<Vec<&Message>>();
+    let chat_messages = turns
         .iter()
-        .map(|m| match m {
-            Message::User(m) => ChatMessage::user(m),
-            Message::Assistant(m) => ChatMessage::assistant(m),
-            _ => ChatMessage::assistant(""),
+        .enumerate()
+        .filter_map(|(i, m)| match (m, turns.get(i + 1)) {
+            (Message::User(m), _) => Some(ChatMessage::user(m)),
+            // Of several answers to a message, e.g. by models compared with
+            // each other, the conversation goes on from the last one
+            (Message::Assistant(..), Some(Message::Assistant(..))) => None,
+            (Message::Assistant(m, _), _) => Some(ChatMessage::assistant(m)),
+            _ => None,
         })
         .collect::<Vec<ChatMessage>>();
     let mut chat_req = ChatRequest::new(vec![ChatMessage::system(system_prompt)]);
@@ -77,21 +514,522 @@ pub async fn assistant_response(
     for chat_message in chat_messages {
         chat_req = chat_req.append_message(chat_message);
     }
+    let mut chat_opts = ChatOptions::default();
+    if supports_temperature(opts.model) {
+        chat_opts = chat_opts.with_temperature(*temperature);
+    }
+    (chat_req, chat_opts)
+}
 
-    let chat_opts = ChatOptions::default().with_temperature(*temperature);
-    let client_config = ClientConfig::default().with_chat_options(chat_opts);
+/// Describes the request that would be sent to `model` as pretty-printed
+/// JSON, without sending it.
+pub fn dry_run_request(
+    messages: &[Message],
+    model: &str,
+    system_prompt: &str,
+    temperature: &f64,
+    history_limit: Option<usize>,
+) -> AppResult<String> {
+    let opts = RequestOptions {
+        model,
+        history_limit,
+    };
+    let (chat_req, chat_opts) = build_chat_request(messages, system_prompt, temperature, &opts);
+    let request = serde_json::json!({
+        "model": model,
+        "provider": provider_name(model),
+        "options": chat_opts,
+        "request": chat_req,
+    });
+    serde_json::to_string_pretty(&request).context("Failed to serialize the request")
+}
+
+/// Token bucket spacing out requests to at most `per_minute` a minute, after
+/// an initial burst of as many requests.
+#[derive(Debug)]
+struct RateLimiter {
+    /// Requests allowed per second
+    rate: f64,
+    /// Most requests sent at once
+    capacity: f64,
+    /// Available requests and when they were counted. Requests waiting for
+    /// their turn make the count negative.
+    bucket: Mutex<(f64, Instant)>,
+}
+
+impl RateLimiter {
+    fn new(per_minute: u32) -> Self {
+        Self {
+            rate: per_minute as f64 / 60.0,
+            capacity: per_minute as f64,
+            bucket: Mutex::new((per_minute as f64, Instant::now())),
+        }
+    }
+
+    /// Takes a request from the bucket at `now`, returning how long to wait
+    /// before sending it.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().unwrap_or_else(|e| e.into_inner());
+        let (tokens, counted_at) = *bucket;
+        let elapsed = now.saturating_duration_since(counted_at).as_secs_f64();
+        let tokens = (tokens + elapsed * self.rate).min(self.capacity) - 1.0;
+        *bucket = (tokens, now);
+        if tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-tokens / self.rate)
+        }
+    }
+}
+
+static RATE_LIMITER: OnceLock<RateLimiter> = OnceLock::new();
+
+/// Limits the requests to providers to `per_minute` a minute, across all
+/// concurrent requests. Only the first limit set is used.
+pub fn set_rate_limit(per_minute: u32) {
+    if per_minute > 0 {
+        let _ = RATE_LIMITER.set(RateLimiter::new(per_minute));
+    }
+}
+
+/// Waits until the rate limit allows another request.
+pub async fn throttle() {
+    if let Some(limiter) = RATE_LIMITER.get() {
+        tokio::time::sleep(limiter.reserve(Instant::now())).await;
+    }
+}
+
+pub async fn assistant_response(
+    messages: &[Message],
+    model: &str,
+    system_prompt: &str,
+    temperature: &f64,
+    history_limit: Option<usize>,
+) -> Result<Message, AiError> {
+    throttle().await;
+    unthrottled_assistant_response(messages, model, system_prompt, temperature, history_limit).await
+}
 
-    let client = ClientBuilder::default().with_config(client_config).build();
-    let chat_res = match client.exec_chat(model, chat_req, None).await {
-        Ok(res) => {
-            if let Some(m) = res.content_text_into_string() {
-                Message::Assistant(m)
-            } else {
-                Message::Assistant("NO RESPONSE".to_string())
+/// Same as `assistant_response`, for callers that wait for the rate limit
+/// with `throttle` themselves.
+pub async fn unthrottled_assistant_response(
+    messages: &[Message],
+    model: &str,
+    system_prompt: &str,
+    temperature: &f64,
+    history_limit: Option<usize>,
+) -> Result<Message, AiError> {
+    let opts = RequestOptions {
+        model,
+        history_limit,
+    };
+    let (chat_req, chat_opts) = build_chat_request(messages, system_prompt, temperature, &opts);
+
+    let client = client();
+    let response_info = response_info(model, &chat_opts);
+    let res = client.exec_chat(model, chat_req, Some(&chat_opts)).await?;
+    let content = res
+        .content_text_into_string()
+        .unwrap_or_else(|| "NO RESPONSE".to_string());
+    Ok(Message::Assistant(content, response_info))
+}
+
+/// Progress of a streamed response.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    /// The response started streaming, its chunks follow.
+    Started,
+    /// A chunk of the response.
+    Chunk(String),
+    /// The model couldn't stream, the complete response is requested instead.
+    Fallback,
+}
+
+/// How chunks of a streamed response are merged before they are displayed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ChunkCoalescing {
+    /// Longest time a chunk is held back to be merged with the next ones
+    pub interval: Duration,
+    /// Merged chunks are passed on as soon as they reach this many bytes
+    pub max_len: usize,
+}
+
+/// Forwards stream events from `rx` to `tx`, merging chunks received in
+/// quick succession so that fast streams don't redraw for every chunk.
+///
+/// Returns when `rx` is closed, after passing on the remaining chunks.
+pub async fn coalesce_stream_events(
+    mut rx: mpsc::Receiver<StreamEvent>,
+    tx: mpsc::Sender<StreamEvent>,
+    coalescing: ChunkCoalescing,
+) {
+    let mut buffer = String::new();
+    let mut deadline = None;
+    loop {
+        let event = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline, rx.recv()).await,
+            None => Ok(rx.recv().await),
+        };
+        let flush = match &event {
+            Ok(Some(StreamEvent::Chunk(chunk))) => {
+                buffer.push_str(chunk);
+                deadline.get_or_insert_with(|| tokio::time::Instant::now() + coalescing.interval);
+                buffer.len() >= coalescing.max_len
+            }
+            // Other events and the deadline pass on the merged chunks
+            _ => true,
+        };
+        if flush && !buffer.is_empty() {
+            deadline = None;
+            if tx
+                .send(StreamEvent::Chunk(std::mem::take(&mut buffer)))
+                .await
+                .is_err()
+            {
+                return;
+            }
+        }
+        match event {
+            Ok(Some(StreamEvent::Chunk(_))) | Err(_) => {}
+            Ok(Some(event)) => {
+                if tx.send(event).await.is_err() {
+                    return;
+                }
             }
+            Ok(None) => return,
         }
-        Err(e) => Message::Error(format!("Error: {}", e)),
+    }
+}
+
+/// Errors from the stream itself, as opposed to errors making the request.
+fn is_stream_error(e: &genai::Error) -> bool {
+    matches!(
+        e,
+        genai::Error::StreamParse { .. }
+            | genai::Error::StreamEventError { .. }
+            | genai::Error::WebStream { .. }
+    )
+}
+
+/// Requests a response and streams it as it is generated.
+///
+/// The start of the stream and each chunk of the response are sent on
+/// `event_tx` as they arrive, the complete response is returned at the end of
+/// the stream. If streaming fails before anything was received, e.g. because
+/// the model doesn't support streaming, the complete response is requested
+/// without streaming instead.
+pub async fn assistant_response_streaming(
+    messages: &[Message],
+    model: &str,
+    system_prompt: &str,
+    temperature: &f64,
+    history_limit: Option<usize>,
+    event_tx: mpsc::Sender<StreamEvent>,
+) -> Result<Message, AiError> {
+    let opts = RequestOptions {
+        model,
+        history_limit,
     };
+    let (chat_req, chat_opts) = build_chat_request(messages, system_prompt, temperature, &opts);
 
-    Ok(chat_res)
+    let client = client();
+    let response_info = response_info(model, &chat_opts);
+    throttle().await;
+    let mut stream = client
+        .exec_chat_stream(model, chat_req, Some(&chat_opts))
+        .await?
+        .stream;
+    let _ = event_tx.send(StreamEvent::Started).await;
+    let mut content = String::new();
+    while let Some(event) = stream.next().await {
+        match event {
+            Ok(ChatStreamEvent::Chunk(chunk)) if !chunk.content.is_empty() => {
+                content.push_str(&chunk.content);
+                let _ = event_tx.send(StreamEvent::Chunk(chunk.content)).await;
+            }
+            Ok(ChatStreamEvent::End(_)) => break,
+            Ok(_) => {}
+            Err(e) if content.is_empty() && is_stream_error(&e) => {
+                let _ = event_tx.send(StreamEvent::Fallback).await;
+                return assistant_response(
+                    messages,
+                    model,
+                    system_prompt,
+                    temperature,
+                    history_limit,
+                )
+                .await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+    if content.is_empty() {
+        content = "NO RESPONSE".to_string();
+    }
+    Ok(Message::Assistant(content, response_info))
+}
+
+/// Requests `n` responses to the same conversation concurrently, failed
+/// requests are returned as error messages.
+pub async fn assistant_response_variations(
+    messages: &[Message],
+    model: &str,
+    system_prompt: &str,
+    temperature: &f64,
+    history_limit: Option<usize>,
+    n: usize,
+) -> AppResult<Vec<Message>> {
+    let responses =
+        join_all((0..n).map(|_| {
+            assistant_response(messages, model, system_prompt, temperature, history_limit)
+        }))
+        .await;
+    Ok(responses
+        .into_iter()
+        .map(|response| response.unwrap_or_else(Message::from))
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use genai::chat::ChatRole;
+
+    use super::*;
+
+    fn conversation() -> Vec<Message> {
+        vec![
+            Message::User("one".to_string()),
+            Message::Assistant("two".to_string(), ResponseInfo::default()),
+            Message::User("three".to_string()),
+            Message::Assistant("four".to_string(), ResponseInfo::default()),
+            Message::User("five".to_string()),
+        ]
+    }
+
+    fn request(history_limit: Option<usize>) -> ChatRequest {
+        let opts = RequestOptions {
+            model: "gpt-4o",
+            history_limit,
+        };
+        build_chat_request(&conversation(), "system", &0.5, &opts).0
+    }
+
+    fn contents(chat_req: &ChatRequest) -> Vec<&str> {
+        chat_req
+            .messages
+            .iter()
+            .filter_map(|m| m.content.text_as_str())
+            .collect()
+    }
+
+    #[test]
+    fn test_context_limit() {
+        assert_eq!(context_limit("gpt-4o-mini"), Some(128_000));
+        assert_eq!(context_limit("claude-3-5-sonnet-latest"), Some(200_000));
+        assert_eq!(context_limit("openrouter/openai/gpt-4o"), Some(128_000));
+        assert_eq!(context_limit("gemma:2b"), None);
+    }
+
+    #[test]
+    fn test_estimate_request_tokens_within_history_limit() {
+        let messages = [
+            Message::User("a".repeat(40)),
+            Message::Assistant("b".repeat(40), Default::default()),
+            Message::User("c".repeat(8)),
+            Message::Error("d".repeat(40)),
+        ];
+        assert_eq!(estimate_request_tokens(&messages, "", None), 22);
+        assert_eq!(estimate_request_tokens(&messages, "system", Some(1)), 4);
+    }
+
+    #[test]
+    fn test_history_limit_keeps_system_prompt() {
+        let chat_req = request(Some(1));
+        assert!(matches!(chat_req.messages[0].role, ChatRole::System));
+        assert_eq!(contents(&chat_req), vec!["system", "five"]);
+    }
+
+    #[test]
+    fn test_history_limit_counts_turns() {
+        let chat_req = request(Some(2));
+        assert_eq!(contents(&chat_req), vec!["system", "three", "four", "five"]);
+        // Errors and notes don't take the place of sent messages
+        let messages = [
+            Message::User("one".to_string()),
+            Message::Error("failed".to_string()),
+            Message::Note("Switched models".to_string()),
+            Message::User("two".to_string()),
+        ];
+        let window = history_window(&messages, Some(2));
+        assert_eq!(window.len(), 4);
+        assert_eq!(history_window(&messages, Some(10)).len(), 4);
+    }
+
+    #[test]
+    fn test_no_history_limit_sends_everything() {
+        let chat_req = request(None);
+        assert_eq!(contents(&chat_req).len(), 6);
+    }
+
+    #[test]
+    fn test_error_messages_are_not_sent() {
+        let messages = vec![
+            Message::User("one".to_string()),
+            Message::Error("Error: rate limited".to_string()),
+            Message::User("one".to_string()),
+            Message::Assistant("two".to_string(), ResponseInfo::default()),
+        ];
+        let opts = RequestOptions {
+            model: "gpt-4o",
+            history_limit: None,
+        };
+        let chat_req = build_chat_request(&messages, "system", &0.5, &opts).0;
+        assert_eq!(contents(&chat_req), vec!["system", "one", "one", "two"]);
+    }
+
+    #[test]
+    fn test_only_the_last_of_several_answers_is_sent() {
+        let messages = vec![
+            Message::User("one".to_string()),
+            Message::Assistant("two".to_string(), ResponseInfo::default()),
+            Message::Note("Answer of gpt-4o to the same message".to_string()),
+            Message::Assistant("three".to_string(), ResponseInfo::default()),
+            Message::User("four".to_string()),
+        ];
+        let opts = RequestOptions {
+            model: "gpt-4o",
+            history_limit: None,
+        };
+        let chat_req = build_chat_request(&messages, "system", &0.5, &opts).0;
+        assert_eq!(contents(&chat_req), vec!["system", "one", "three", "four"]);
+    }
+
+    #[test]
+    fn test_reasoning_models_omit_temperature() {
+        let options = |model| {
+            let opts = RequestOptions {
+                model,
+                history_limit: None,
+            };
+            build_chat_request(&conversation(), "system", &0.7, &opts).1
+        };
+        assert_eq!(options("gpt-4o").temperature, Some(0.7));
+        assert_eq!(options("o1-mini").temperature, None);
+    }
+
+    #[test]
+    fn test_rate_limiter_spaces_out_requests() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+        assert_eq!(limiter.reserve(start), Duration::ZERO);
+        assert_eq!(limiter.reserve(start), Duration::ZERO);
+        // The bucket is empty, a request is allowed every 30 seconds
+        assert_eq!(limiter.reserve(start), Duration::from_secs(30));
+        assert_eq!(limiter.reserve(start), Duration::from_secs(60));
+        let later = start + Duration::from_secs(120);
+        assert_eq!(limiter.reserve(later), Duration::ZERO);
+    }
+
+    #[test]
+    fn test_fallback_models() {
+        let entries = ["ollama:gemma:2b".to_string(), "OpenAI:gpt-4o".to_string()];
+        let fallback_models = parse_fallback_models(&entries).unwrap();
+        assert_eq!(
+            fallback_models,
+            vec![
+                ("Ollama".to_string(), "gemma:2b".to_string()),
+                ("OpenAI".to_string(), "gpt-4o".to_string()),
+            ]
+        );
+        assert!(parse_fallback_models(&["gpt-4o".to_string()]).is_err());
+        assert!(parse_fallback_models(&["nope:model".to_string()]).is_err());
+
+        let mut models = vec![("OpenAI".to_string(), "gpt-4o".to_string())];
+        merge_fallback_models(&mut models, &fallback_models, &["OpenAI", "Ollama"]);
+        assert_eq!(models.len(), 2);
+        merge_fallback_models(&mut models, &default_fallback_models(), &["OpenAI"]);
+        assert_eq!(models.len(), 3);
+    }
+
+    #[test]
+    fn test_openrouter_models_use_openai_adapter() {
+        let target = client()
+            .resolve_service_target("openrouter/mistralai/mistral-large")
+            .unwrap();
+        assert_eq!(target.model.adapter_kind, AdapterKind::OpenAI);
+        assert_eq!(&*target.model.model_name, "mistralai/mistral-large");
+        assert_eq!(target.endpoint.base_url(), OPENROUTER_URL);
+        let target = client().resolve_service_target("gpt-4o").unwrap();
+        assert_eq!(target.model.adapter_kind, AdapterKind::OpenAI);
+        assert_eq!(&*target.model.model_name, "gpt-4o");
+    }
+
+    #[test]
+    fn test_trim_response_prefixes() {
+        let patterns = compile_prefix_patterns(&[
+            "Sure! Here's[^:]*:".to_string(),
+            "Certainly[.!]".to_string(),
+        ])
+        .unwrap();
+        assert_eq!(
+            trim_response_prefixes("Sure! Here's the code:\n\nfn main() {}", &patterns),
+            "fn main() {}"
+        );
+        assert_eq!(
+            trim_response_prefixes("Certainly! Sure! Here's a list: a, b", &patterns),
+            "Sure! Here's a list: a, b"
+        );
+        // Matches later in the response are kept
+        assert_eq!(
+            trim_response_prefixes("Yes. Certainly.", &patterns),
+            "Yes. Certainly."
+        );
+        assert!(compile_prefix_patterns(&["(".to_string()]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_coalesce_stream_events() {
+        let (chunk_tx, chunk_rx) = mpsc::channel(16);
+        let (event_tx, mut event_rx) = mpsc::channel(16);
+        for event in [
+            StreamEvent::Started,
+            StreamEvent::Chunk("ab".to_string()),
+            StreamEvent::Chunk("cd".to_string()),
+            StreamEvent::Chunk("e".to_string()),
+        ] {
+            chunk_tx.send(event).await.unwrap();
+        }
+        drop(chunk_tx);
+        let coalescing = ChunkCoalescing {
+            interval: Duration::from_secs(3600),
+            max_len: 4,
+        };
+        coalesce_stream_events(chunk_rx, event_tx, coalescing).await;
+        let mut chunks = Vec::new();
+        while let Some(event) = event_rx.recv().await {
+            if let StreamEvent::Chunk(chunk) = event {
+                chunks.push(chunk);
+            }
+        }
+        // Merged up to the size limit, the rest when the stream ends
+        assert_eq!(chunks, ["abcd", "e"]);
+    }
+
+    #[test]
+    fn test_error_from_status() {
+        let error = |status| AiError::from_status(status, String::new());
+        assert!(matches!(error(StatusCode::UNAUTHORIZED), AiError::Auth(_)));
+        assert!(matches!(
+            error(StatusCode::TOO_MANY_REQUESTS),
+            AiError::RateLimit(_)
+        ));
+        assert!(matches!(
+            error(StatusCode::GATEWAY_TIMEOUT),
+            AiError::Timeout(_)
+        ));
+        assert!(matches!(
+            error(StatusCode::INTERNAL_SERVER_ERROR),
+            AiError::Other(_)
+        ));
+    }
 }
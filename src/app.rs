@@ -1,90 +1,62 @@
 use ::dirs::home_dir;
 use anyhow::{Context, Result};
-#[cfg(not(target_os = "linux"))]
-use arboard::Clipboard;
 
-use std::fs;
+use std::{borrow::Cow, collections::HashSet, fs, path::Path};
 
-use ratatui::{
-    buffer::Buffer,
-    style::{Color, Style},
-    widgets::Block,
-};
+use fancy_regex::Regex;
+use ratatui::{text::Line, widgets::Block};
+use syntect::highlighting;
 use tui_textarea::TextArea;
 
 use crate::{
-    ai::MODELS,
-    chats::ChatList,
-    snippets::{find_fenced_code_snippets, SnippetItem},
+    ai::{
+        estimate_request_tokens, trim_response_prefixes, AiError, StreamEvent, DEFAULT_MODEL,
+        MODELS,
+    },
+    chats::{ChatList, ChatSwitcher},
+    cli::validate_temperature,
+    clipboard::{self, sanitize_paste, Clipboard, LARGE_PASTE_CHARS},
+    config::{
+        load_config, save_config, save_last_model, save_templates, InputKeys, QuitKey, WrapIndent,
+    },
+    export::{export_snippets, format_markdown_message, FileExists, DEFAULT_SNIPPETS_DIR},
+    highlight::{create_highlighted_code, load_theme, DEFAULT_THEME},
+    selection::Selection,
+    snippets::{find_fenced_code_blocks, find_partial_code_blocks, SnippetItem},
     storage::{
-        create_db_conversation, delete_conversation, delete_message, insert_message,
-        list_all_conversations, list_all_messages,
+        count_messages, create_db_conversation, delete_conversation, delete_message,
+        get_conversation_system_prompt, insert_message, list_all_conversations, list_all_messages,
+        list_conversation_summaries, list_favorite_conversations, list_messages_page,
+        merge_conversations, search_conversations, set_conversation_favorite, usage_stats,
+        UsageStats,
     },
+    theme::Theme,
+    ui::{message_line_count, message_text_width, LineCache},
+    vim::Vim,
+};
+use crate::{
+    models::ModelList, snippets::SnippetList, templates::TemplateList, variations::VariationList,
 };
-use crate::{models::ModelList, snippets::SnippetList};
-
-#[derive(Debug, Clone, Default)]
-pub struct Selection {
-    pub start: Option<(u16, u16)>, // (column, row)
-    pub end: Option<(u16, u16)>,
-}
-
-impl Selection {
-    pub fn get_selected_text(&self, buffer: &Buffer) -> Option<String> {
-        // Need both start and end points to make a selection
-        let (start, end) = match (self.start, self.end) {
-            (Some(start), Some(end)) => (start, end),
-            _ => return None,
-        };
-
-        // Calculate bounds (handles selection in any direction)
-        let start_row = start.1.min(end.1);
-        let end_row = start.1.max(end.1);
-        let start_col = start.0.min(end.0);
-        let end_col = start.0.max(end.0);
-
-        let mut selected_text = String::new();
-
-        for row in start_row..=end_row {
-            // Add newline between rows, but not before first row
-            if row > start_row {
-                selected_text.push('\n');
-            }
-
-            for col in start_col..=end_col {
-                let cell = buffer.cell((col, row));
-                if let Some(cell) = cell {
-                    selected_text.push_str(cell.symbol());
-                }
-            }
-        }
-
-        Some(selected_text)
-    }
-
-    pub fn iter_selected_cells(&self) -> Option<impl Iterator<Item = (u16, u16)>> {
-        let (start, end) = match (self.start, self.end) {
-            (Some(start), Some(end)) => (start, end),
-            _ => return None,
-        };
-
-        let start_row = start.1.min(end.1);
-        let end_row = start.1.max(end.1);
-        let start_col = start.0.min(end.0);
-        let end_col = start.0.max(end.0);
-
-        Some(
-            (start_row..=end_row)
-                .flat_map(move |row| (start_col..=end_col).map(move |col| (col, row))),
-        )
-    }
-}
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Message {
     User(String),
-    Assistant(String),
+    Assistant(String, ResponseInfo),
     Error(String),
+    /// Shown in the conversation, e.g. when the model is switched, but never
+    /// sent to the model
+    Note(String),
+}
+
+/// Information about how an assistant response was generated.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ResponseInfo {
+    /// Temperature the response was generated at, if known.
+    pub temperature: Option<f64>,
+    /// Provider of the model that generated the response, if known.
+    pub provider: Option<String>,
+    /// Model that generated the response, if known.
+    pub model: Option<String>,
 }
 
 impl From<String> for Message {
@@ -99,12 +71,24 @@ impl From<&str> for Message {
     }
 }
 
+impl Message {
+    pub fn as_str(&self) -> &str {
+        self.as_ref()
+    }
+
+    /// Is the message saved with the conversation? Errors are only shown.
+    pub fn is_saved(&self) -> bool {
+        !matches!(self, Message::Error(_))
+    }
+}
+
 impl AsRef<str> for Message {
     fn as_ref(&self) -> &str {
         match self {
             Message::User(message) => message.as_str(),
-            Message::Assistant(message) => message.as_str(),
+            Message::Assistant(message, _) => message.as_str(),
             Message::Error(message) => message.as_str(),
+            Message::Note(message) => message.as_str(),
         }
     }
 }
@@ -119,8 +103,21 @@ pub enum AppMode {
     SnippetSelection,
     ShowHistory,
     Help,
+    SystemPromptEditing,
+    CustomModelEntry,
+    TemperatureEntry,
+    VariationSelection,
+    Dashboard,
+    ChatSwitcher,
+    TemplateSelection,
+    TemplateNameEntry,
+    HistoryFilter,
 }
 
+/// Conversation, numbers of unloaded and loaded messages and length of the
+/// system prompt that a context estimate was made for.
+pub type ContextKey = (Option<i64>, usize, usize, usize);
+
 /// App holds the state of the application
 pub struct App<'a> {
     /// Input text area
@@ -129,105 +126,460 @@ pub struct App<'a> {
     pub app_mode: AppMode,
     /// Conversation ID for chat database.
     pub conversation_id: Option<i64>,
-    /// System prompt
-    pub system_prompt: &'a str,
+    /// System prompt used for the current conversation
+    pub system_prompt: String,
+    /// System prompt used when a new conversation is started
+    pub default_system_prompt: String,
+    /// System prompt editor text area
+    pub system_prompt_textarea: TextArea<'a>,
+    /// Boilerplate removed from the start of responses
+    pub response_prefix_patterns: Vec<Regex>,
+    /// Statistics shown in the dashboard
+    pub usage_stats: UsageStats,
+    /// Error of the last response, if the request failed
+    pub last_error: Option<AiError>,
     /// Has unprocessed messages
     pub has_unprocessed_messages: bool,
+    /// A request was sent and its response wasn't received yet
+    pub awaiting_response: bool,
+    /// Stream responses as they are generated
+    pub streaming_enabled: bool,
+    /// Nothing is written to the database or the chat log, new messages are
+    /// only kept for the session
+    pub read_only: bool,
+    /// Name of the project whose `.ait` directory holds the config and chats
+    pub project: Option<String>,
+    /// Describes the context added to the default system prompt, shown while
+    /// it is in use
+    pub context_label: Option<String>,
+    /// Is a response currently being streamed?
+    pub is_streaming: bool,
+    /// Content of the response being streamed so far
+    pub streaming_response: String,
+    /// Number of snippets at the end of the snippet list that belong to the
+    /// response being streamed
+    pub n_streaming_snippets: usize,
+    /// Models that failed to stream, their responses are not streamed
+    pub non_streaming_models: HashSet<String>,
+    /// Has a pending request to discover the available models
+    pub has_unprocessed_model_refresh: bool,
+    /// Has a pending request for response variations
+    pub has_unprocessed_variations: bool,
+    /// Response variations to choose from
+    pub variation_list: VariationList,
     /// History of recorded messages
     pub messages: Vec<Message>,
+    /// Maximum number of messages kept in memory, older messages are loaded
+    /// from the database when scrolling to the top
+    pub scrollback_limit: Option<usize>,
+    /// Number of older messages of the conversation not loaded from the database
+    pub unloaded_messages: usize,
+    /// Maximum number of most recent turns sent to the model
+    pub history_limit: Option<usize>,
+    /// Estimated tokens of the next request, with what they were estimated for
+    pub context_estimate: Option<(ContextKey, usize)>,
     /// Vertical scroll
     pub vertical_scroll: usize,
+    /// Show how each response was generated
+    pub show_response_info: bool,
+    /// Show the role in front of each message instead of headers and rules
+    pub compact: bool,
+    /// Render the conversation as labelled plain text without borders,
+    /// scrollbars or rules, for screen readers
+    pub plain: bool,
+    /// Mouse events are handled by the app, disabled to use the text
+    /// selection of the terminal
+    pub mouse_capture: bool,
+    /// Indent of wrapped continuation lines
+    pub wrap_indent: WrapIndent,
+    /// Number of the latest messages shown in the chat history preview
+    pub history_preview_messages: usize,
+    /// Switching the model mid-conversation adds a note to the conversation
+    pub model_switch_notes: bool,
+    /// Show the newest messages at the top, the scroll is then counted from
+    /// the newest message
+    pub newest_first: bool,
+    /// Number of lines of the conversation when it was last rendered, to keep
+    /// the view in place when lines are added above it in newest first order
+    pub rendered_lines: usize,
+    /// Rendered lines of the messages
+    pub line_cache: LineCache,
     /// Is the application running?
     pub running: bool,
-    /// System clipboard.
-    /// Not enabled on Linux because of an issue with the `arboard` crate,
-    /// see <https://github.com/1Password/arboard/issues/153>
-    #[cfg(not(target_os = "linux"))]
-    pub clipboard: Clipboard,
+    /// Does the terminal have focus?
+    pub has_focus: bool,
+    /// System clipboard, `None` when it couldn't be accessed
+    pub clipboard: Option<Clipboard>,
     /// List of models
     pub model_list: ModelList,
     /// Selected model name
     pub selected_model_name: String,
+    /// Custom model name text area
+    pub custom_model_textarea: TextArea<'a>,
+    /// Temperature used for new requests
+    pub temperature: f64,
+    /// Temperature text area
+    pub temperature_textarea: TextArea<'a>,
+    /// Saved message templates
+    pub template_list: TemplateList,
+    /// Template name text area
+    pub template_name_textarea: TextArea<'a>,
     /// Discovered snippets
     pub snippet_list: SnippetList,
+    /// Exporting snippets again overwrites existing files
+    pub confirm_snippet_overwrite: bool,
+    /// Shell snippets can be printed when exiting
+    pub shell_snippets_enabled: bool,
+    /// Snippet that is printed when exiting if it is chosen again
+    pub confirm_shell_snippet: Option<String>,
+    /// The model chosen next answers the last message again, for comparing
+    /// the answers of two models
+    pub compare_pending: bool,
+    /// Chat that the current chat is merged into if it is chosen again
+    pub confirm_merge: Option<i64>,
+    /// Command printed after the user interface is closed, never run
+    pub exit_command: Option<String>,
+    /// Snippet to open in the external editor
+    pub snippet_to_edit: Option<SnippetItem>,
+    /// Horizontal scroll of the snippet preview
+    pub snippet_preview_hscroll: u16,
+    /// Vertical scroll of the snippet preview
+    pub snippet_preview_vscroll: u16,
+    /// Vertical scroll of the help, kept when the help is closed
+    pub help_scroll: u16,
+    /// Code highlighting theme
+    pub code_theme: highlighting::Theme,
+    /// Styles of the user interface
+    pub theme: Theme,
     /// List of chats
     pub chat_list: ChatList,
+    /// Conversations found by the quick switcher
+    pub chat_switcher: ChatSwitcher,
+    /// Search text area of the quick switcher
+    pub chat_switcher_textarea: TextArea<'a>,
+    /// Chat history is filtered to chats with a message containing this
+    pub history_filter: String,
+    /// Text area of the chat history filter
+    pub history_filter_textarea: TextArea<'a>,
     /// Selected text
     pub selection: Selection,
+    /// Transient status message shown in the status bar
+    pub status: Option<StatusMessage>,
+    /// Problems encountered while discovering models
+    pub model_warnings: Vec<String>,
+    /// Ticks left to highlight the input area after a rejected submission
+    pub rejected_submission_ticks: u16,
+    /// What `q` does in normal mode
+    pub quit_key: QuitKey,
+    /// Key bindings of the input area
+    pub input_keys: InputKeys,
+    /// Submode of the input area with the vim key bindings
+    pub vim: Vim,
+    /// Ticks left to press `q` again to quit
+    pub quit_pending_ticks: u16,
 }
 
-fn styled_input_textarea() -> TextArea<'static> {
+/// A message shown in the status bar for a limited number of ticks.
+#[derive(Debug, Clone)]
+pub struct StatusMessage {
+    pub text: String,
+    pub is_error: bool,
+    pub ticks_left: u16,
+}
+
+/// Number of ticks a status message stays visible.
+const STATUS_TICKS: u16 = 20;
+
+/// Number of ticks the input area is highlighted after a rejected submission.
+const REJECTED_SUBMISSION_TICKS: u16 = 4;
+
+/// Number of ticks within which `q` has to be pressed again to quit.
+const QUIT_PENDING_TICKS: u16 = 8;
+
+/// Number of columns the snippet preview scrolls horizontally per key press.
+const SNIPPET_PREVIEW_HSCROLL_STEP: u16 = 4;
+
+fn input_block() -> Block<'static> {
+    Block::bordered().title("Input")
+}
+
+fn styled_input_textarea(theme: &Theme) -> TextArea<'static> {
     let mut input_textarea = TextArea::default();
-    input_textarea.set_block(Block::bordered().title("Input"));
-    input_textarea.set_style(Style::default().fg(Color::Yellow));
+    input_textarea.set_block(input_block());
+    input_textarea.set_style(theme.input);
     input_textarea
 }
 
+fn styled_system_prompt_textarea(theme: &Theme) -> TextArea<'static> {
+    let mut system_prompt_textarea = TextArea::default();
+    system_prompt_textarea.set_block(Block::bordered().title("System Prompt"));
+    system_prompt_textarea.set_style(theme.system_prompt);
+    system_prompt_textarea
+}
+
+fn styled_custom_model_textarea(theme: &Theme) -> TextArea<'static> {
+    let mut custom_model_textarea = TextArea::default();
+    custom_model_textarea.set_block(Block::bordered().title("Custom Model Name"));
+    custom_model_textarea.set_style(theme.input);
+    custom_model_textarea
+}
+
+fn styled_chat_switcher_textarea(theme: &Theme) -> TextArea<'static> {
+    let mut chat_switcher_textarea = TextArea::default();
+    chat_switcher_textarea.set_block(Block::bordered().title("Switch to Chat"));
+    chat_switcher_textarea.set_style(theme.input);
+    chat_switcher_textarea
+}
+
+fn styled_history_filter_textarea(theme: &Theme) -> TextArea<'static> {
+    let mut history_filter_textarea = TextArea::default();
+    history_filter_textarea.set_block(Block::bordered().title("Filter Chats"));
+    history_filter_textarea.set_style(theme.input);
+    history_filter_textarea
+}
+
+fn styled_template_name_textarea(theme: &Theme) -> TextArea<'static> {
+    let mut template_name_textarea = TextArea::default();
+    template_name_textarea.set_block(Block::bordered().title("Template Name"));
+    template_name_textarea.set_style(theme.input);
+    template_name_textarea
+}
+
+fn styled_temperature_textarea(theme: &Theme) -> TextArea<'static> {
+    let mut temperature_textarea = TextArea::default();
+    temperature_textarea.set_block(Block::bordered().title("Temperature (0.0 - 2.0)"));
+    temperature_textarea.set_style(theme.input);
+    temperature_textarea
+}
+
+/// Finds the fenced code snippets in a message.
+fn extract_snippets(message: &Message) -> Vec<SnippetItem> {
+    find_fenced_code_blocks(
+        message
+            .as_ref()
+            .split('\n')
+            .map(|s| s.to_string())
+            .collect(),
+    )
+    .into_iter()
+    .map(|(language, snippet)| SnippetItem::from(snippet).with_language(language))
+    .collect()
+}
+
 impl Default for App<'_> {
     fn default() -> Self {
+        let theme = Theme::default();
         Self {
-            input_textarea: styled_input_textarea(),
+            input_textarea: styled_input_textarea(&theme),
             app_mode: AppMode::Normal,
-            system_prompt: "You are a helpful, friendly assistant.",
+            system_prompt: "You are a helpful, friendly assistant.".to_string(),
+            default_system_prompt: "You are a helpful, friendly assistant.".to_string(),
+            system_prompt_textarea: styled_system_prompt_textarea(&theme),
             conversation_id: None,
+            usage_stats: UsageStats::default(),
+            response_prefix_patterns: Vec::new(),
+            last_error: None,
             has_unprocessed_messages: false,
+            awaiting_response: false,
+            has_unprocessed_model_refresh: false,
+            has_unprocessed_variations: false,
+            streaming_enabled: true,
+            read_only: false,
+            project: None,
+            context_label: None,
+            is_streaming: false,
+            streaming_response: String::new(),
+            n_streaming_snippets: 0,
+            non_streaming_models: HashSet::new(),
+            variation_list: VariationList::default(),
             messages: Vec::new(),
+            scrollback_limit: None,
+            unloaded_messages: 0,
+            history_limit: None,
+            context_estimate: None,
             // user_messages: Vec::new(),
             // assistant_messages: Vec::new(),
             vertical_scroll: 0,
+            show_response_info: false,
+            compact: false,
+            plain: false,
+            mouse_capture: true,
+            wrap_indent: WrapIndent::default(),
+            history_preview_messages: 50,
+            model_switch_notes: true,
+            newest_first: false,
+            rendered_lines: 0,
+            line_cache: LineCache::default(),
             running: true,
-            #[cfg(not(target_os = "linux"))]
-            clipboard: Clipboard::new().unwrap(),
+            has_focus: true,
+            clipboard: Clipboard::new().ok(),
             model_list: ModelList::from_iter(MODELS.map(|(provider, model)| {
-                if model == "claude-3-5-sonnet-latest" {
+                if model == DEFAULT_MODEL {
                     (provider, model, true)
                 } else {
                     (provider, model, false)
                 }
             })),
-            selected_model_name: "claude-3-5-sonnet-latest".to_string(),
+            selected_model_name: DEFAULT_MODEL.to_string(),
+            custom_model_textarea: styled_custom_model_textarea(&theme),
+            temperature: 0.5,
+            temperature_textarea: styled_temperature_textarea(&theme),
+            template_list: TemplateList::default(),
+            template_name_textarea: styled_template_name_textarea(&theme),
             snippet_list: SnippetList::from_iter([].iter().map(|&snippet| (snippet, false))),
+            confirm_snippet_overwrite: false,
+            shell_snippets_enabled: false,
+            confirm_shell_snippet: None,
+            compare_pending: false,
+            confirm_merge: None,
+            exit_command: None,
+            snippet_to_edit: None,
+            snippet_preview_hscroll: 0,
+            snippet_preview_vscroll: 0,
+            help_scroll: 0,
+            code_theme: load_theme(DEFAULT_THEME).unwrap(),
             chat_list: ChatList::from_iter([].iter().map(|&chat| (chat, "".to_string(), false))),
+            chat_switcher: ChatSwitcher::default(),
+            chat_switcher_textarea: styled_chat_switcher_textarea(&theme),
+            history_filter: String::new(),
+            history_filter_textarea: styled_history_filter_textarea(&theme),
             selection: Selection::default(),
+            status: None,
+            model_warnings: Vec::new(),
+            rejected_submission_ticks: 0,
+            quit_key: QuitKey::default(),
+            input_keys: InputKeys::default(),
+            vim: Vim::default(),
+            quit_pending_ticks: 0,
+            theme,
         }
     }
 }
 
 impl<'a> App<'a> {
-    pub fn new(system_prompt: &'a str) -> Self {
+    pub fn new(system_prompt: &str) -> Self {
         Self {
-            system_prompt,
+            system_prompt: system_prompt.to_string(),
+            default_system_prompt: system_prompt.to_string(),
             ..Default::default()
         }
     }
 
+    /// Switches to `theme`, restyling the text areas that are already shown.
+    pub fn set_theme(&mut self, theme: Theme) {
+        self.input_textarea.set_style(theme.input);
+        self.system_prompt_textarea.set_style(theme.system_prompt);
+        for textarea in [
+            &mut self.custom_model_textarea,
+            &mut self.chat_switcher_textarea,
+            &mut self.history_filter_textarea,
+            &mut self.template_name_textarea,
+            &mut self.temperature_textarea,
+        ] {
+            textarea.set_style(theme.input);
+        }
+        self.theme = theme;
+    }
+
     /// Handles the tick event of the terminal.
-    pub fn tick(&self) {}
+    pub fn tick(&mut self) {
+        if self.rejected_submission_ticks > 0 {
+            self.rejected_submission_ticks -= 1;
+            if self.rejected_submission_ticks == 0 {
+                self.input_textarea.set_block(input_block());
+            }
+        }
+        self.quit_pending_ticks = self.quit_pending_ticks.saturating_sub(1);
+        if let Some(status) = self.status.as_mut() {
+            status.ticks_left = status.ticks_left.saturating_sub(1);
+            if status.ticks_left == 0 {
+                self.status = None;
+            }
+        }
+    }
+
+    /// Shows a transient message in the status bar.
+    pub fn set_status(&mut self, text: impl Into<String>) {
+        self.status = Some(StatusMessage {
+            text: text.into(),
+            is_error: false,
+            ticks_left: STATUS_TICKS,
+        });
+    }
+
+    /// Shows a transient error message in the status bar.
+    pub fn set_error_status(&mut self, text: impl Into<String>) {
+        self.status = Some(StatusMessage {
+            text: text.into(),
+            is_error: true,
+            ticks_left: STATUS_TICKS,
+        });
+    }
 
     pub fn set_app_mode(&mut self, new_app_mode: AppMode) {
+        self.confirm_snippet_overwrite = false;
+        self.confirm_shell_snippet = None;
+        self.confirm_merge = None;
+        // Editing starts typing, also with the vim bindings
+        if matches!(new_app_mode, AppMode::Editing) {
+            self.vim = Vim::default();
+        }
+        if !matches!(
+            new_app_mode,
+            AppMode::ModelSelection | AppMode::CustomModelEntry
+        ) {
+            self.compare_pending = false;
+        }
         self.app_mode = new_app_mode;
     }
 
     pub fn create_conversation(&mut self) -> AppResult<i64> {
-        let conv_id = create_db_conversation(self.system_prompt)
+        let conv_id = create_db_conversation(&self.system_prompt)
             .context("Failed to create conversation in db")?;
         self.conversation_id = Some(conv_id);
         Ok(conv_id)
     }
 
+    /// Saves a message of the current conversation, creating the conversation
+    /// if it isn't saved yet.
+    fn save_message(&mut self, message: &Message) -> AppResult<()> {
+        if self.read_only {
+            return Ok(());
+        }
+        let id = match self.conversation_id {
+            Some(id) => id,
+            None => self.create_conversation()?,
+        };
+        insert_message(id, message)
+    }
+
+    /// Deletes a saved message of the current conversation.
+    fn unsave_message(&self, message: &Message) -> AppResult<()> {
+        match self.conversation_id {
+            Some(id) if !self.read_only => delete_message(id, message),
+            _ => Ok(()),
+        }
+    }
+
     fn write_chat_log(&self) -> AppResult<()> {
+        if self.read_only {
+            return Ok(());
+        }
         let mut chat_log = String::new();
         for message in self.messages.iter() {
             match message {
                 Message::User(message) => {
                     chat_log.push_str(&format!("User: {}\n", message));
                 }
-                Message::Assistant(message) => {
+                Message::Assistant(message, _) => {
                     chat_log.push_str(&format!("Assistant: {}\n", message));
                 }
                 Message::Error(message) => {
                     chat_log.push_str(&format!("Error: {}\n", message));
                 }
+                Message::Note(message) => {
+                    chat_log.push_str(&format!("Note: {}\n", message));
+                }
             }
         }
         let mut path = home_dir().context("Cannot find home directory")?;
@@ -238,34 +590,202 @@ impl<'a> App<'a> {
         Ok(())
     }
 
+    /// Width of the message text in the terminal.
+    fn message_text_width(&self) -> usize {
+        let (width, _) = crossterm::terminal::size().unwrap_or((80, 24));
+        // The messages area is inset by the outer border on each side.
+        message_text_width(width.saturating_sub(2))
+    }
+
     fn get_max_scroll(&self) -> AppResult<usize> {
-        let (width, _) =
-            crossterm::terminal::size().context("Could not get terminal size from crossterm")?;
+        let text_width = self.message_text_width();
         let max_scroll = self
-            .messages
-            .iter()
-            .map(|m| textwrap::wrap(m.as_ref(), width as usize - 5).join("\n"))
-            .collect::<Vec<String>>()
-            .join("\n")
-            .split('\n')
-            .collect::<Vec<&str>>()
-            .len()
-            + 3 * (self.messages.len())
-            - 2;
+            .displayed_messages()
+            .map(|m| {
+                message_line_count(&m, text_width, self.compact, self.plain, &self.wrap_indent)
+            })
+            .sum::<usize>()
+            .saturating_sub(2);
 
         Ok(max_scroll)
     }
 
+    /// The response being streamed so far.
+    pub fn streaming_message(&self) -> Option<Message> {
+        self.is_streaming.then(|| {
+            let text =
+                trim_response_prefixes(&self.streaming_response, &self.response_prefix_patterns);
+            Message::Assistant(text.to_string(), ResponseInfo::default())
+        })
+    }
+
+    /// The messages to display, including the response being streamed.
+    pub fn displayed_messages(&self) -> impl Iterator<Item = Cow<'_, Message>> {
+        self.messages
+            .iter()
+            .map(Cow::Borrowed)
+            .chain(self.streaming_message().map(Cow::Owned))
+    }
+
     pub fn increment_vertical_scroll(&mut self) -> AppResult<()> {
-        let max_scroll = self.get_max_scroll().context("Unable to get max scroll")?;
+        let mut max_scroll = self.get_max_scroll().context("Unable to get max scroll")?;
+        // The older messages are at the bottom in newest first order
+        if self.newest_first && self.vertical_scroll >= max_scroll && self.unloaded_messages > 0 {
+            self.load_older_messages()?;
+            // They are added below the view, which stays in place.
+            let new_max_scroll = self.get_max_scroll().context("Unable to get max scroll")?;
+            self.rendered_lines += new_max_scroll - max_scroll;
+            max_scroll = new_max_scroll;
+        }
         if self.vertical_scroll < max_scroll {
             self.vertical_scroll += 1;
         }
         Ok(())
     }
 
-    pub fn decrement_vertical_scroll(&mut self) {
+    pub fn decrement_vertical_scroll(&mut self) -> AppResult<()> {
+        if self.vertical_scroll == 0 && self.unloaded_messages > 0 && !self.newest_first {
+            let max_scroll = self.get_max_scroll().context("Unable to get max scroll")?;
+            self.load_older_messages()?;
+            // Keep the view in place above the messages that were displayed.
+            let new_max_scroll = self.get_max_scroll().context("Unable to get max scroll")?;
+            self.vertical_scroll = new_max_scroll - max_scroll;
+        }
         self.vertical_scroll = self.vertical_scroll.saturating_sub(1);
+        Ok(())
+    }
+
+    /// Loads the messages of the conversation, only the most recent ones if
+    /// there is a scrollback limit.
+    fn load_messages(&mut self, conversation_id: i64) -> AppResult<()> {
+        match self.scrollback_limit {
+            Some(limit) => {
+                let total = count_messages(conversation_id)?;
+                let offset = total.saturating_sub(limit);
+                self.messages = list_messages_page(conversation_id, offset, total - offset)?;
+                self.unloaded_messages = offset;
+                self.drop_leading_responses();
+            }
+            None => {
+                self.messages = list_all_messages(conversation_id)?;
+                self.unloaded_messages = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads the previous page of messages of the conversation from the database.
+    pub fn load_older_messages(&mut self) -> AppResult<()> {
+        let (Some(id), Some(limit)) = (self.conversation_id, self.scrollback_limit) else {
+            return Ok(());
+        };
+        let offset = self.unloaded_messages.saturating_sub(limit);
+        let mut older = list_messages_page(id, offset, self.unloaded_messages - offset)?;
+        older.append(&mut self.messages);
+        self.messages = older;
+        self.unloaded_messages = offset;
+        self.drop_leading_responses();
+        Ok(())
+    }
+
+    /// Unloads the oldest messages above the scrollback limit.
+    fn trim_scrollback(&mut self) {
+        let Some(limit) = self.scrollback_limit else {
+            return;
+        };
+        // Messages that aren't saved cannot be loaded again
+        if self.messages.len() <= limit || self.conversation_id.is_none() || self.read_only {
+            return;
+        }
+        let n_trimmed = self.messages.len() - limit;
+        let trimmed_lines = self
+            .messages
+            .iter()
+            .take(n_trimmed)
+            .map(|m| {
+                message_line_count(
+                    m,
+                    self.message_text_width(),
+                    self.compact,
+                    self.plain,
+                    &self.wrap_indent,
+                )
+            })
+            .sum::<usize>();
+        // Only saved messages can be loaded again
+        self.unloaded_messages += self
+            .messages
+            .drain(..n_trimmed)
+            .filter(Message::is_saved)
+            .count();
+        self.drop_leading_responses();
+        if self.newest_first {
+            // The trimmed lines were below the view
+            self.rendered_lines = self.rendered_lines.saturating_sub(trimmed_lines);
+        } else {
+            self.vertical_scroll = self.vertical_scroll.saturating_sub(trimmed_lines);
+        }
+    }
+
+    /// Keeps the loaded messages starting with a user message, so that every
+    /// loaded question is paired with its response.
+    fn drop_leading_responses(&mut self) {
+        if self.unloaded_messages == 0 {
+            return;
+        }
+        while let Some(Message::Assistant(..)) = self.messages.first() {
+            self.messages.remove(0);
+            self.unloaded_messages += 1;
+        }
+    }
+
+    /// The complete history of the conversation to send to the model,
+    /// including messages that are not loaded, up to the last user message
+    /// that is answered.
+    pub fn request_messages(&self) -> AppResult<Vec<Message>> {
+        let mut messages = self.all_messages()?;
+        // The answers to compare with are left out
+        if let Some(i) = messages.iter().rposition(|m| matches!(m, Message::User(_))) {
+            messages.truncate(i + 1);
+        }
+        Ok(messages)
+    }
+
+    /// The messages of the conversation, including those that are not loaded.
+    fn all_messages(&self) -> AppResult<Vec<Message>> {
+        match self.conversation_id {
+            Some(id) if self.unloaded_messages > 0 => {
+                let mut messages = list_messages_page(id, 0, self.unloaded_messages)?;
+                messages.extend(self.messages.iter().cloned());
+                Ok(messages)
+            }
+            _ => Ok(self.messages.clone()),
+        }
+    }
+
+    /// Estimated number of tokens of the next request, including the messages
+    /// that are not loaded.
+    ///
+    /// It is only estimated again when the messages or the system prompt
+    /// change, as the messages that are not loaded are read from the database.
+    pub fn context_tokens(&mut self) -> usize {
+        let key = (
+            self.conversation_id,
+            self.unloaded_messages,
+            self.messages.len(),
+            self.system_prompt.len(),
+        );
+        if let Some((cached_key, tokens)) = self.context_estimate {
+            if cached_key == key {
+                return tokens;
+            }
+        }
+        let messages = self
+            .all_messages()
+            .unwrap_or_else(|_| self.messages.clone());
+        let tokens = estimate_request_tokens(&messages, &self.system_prompt, self.history_limit);
+        self.context_estimate = Some((key, tokens));
+        tokens
     }
 
     pub fn scroll_to_top(&mut self) {
@@ -277,88 +797,409 @@ impl<'a> App<'a> {
         Ok(())
     }
 
+    /// Flashes the input area and explains why a message wasn't submitted.
+    fn reject_submission(&mut self) {
+        self.rejected_submission_ticks = REJECTED_SUBMISSION_TICKS;
+        self.input_textarea.set_block(
+            Block::bordered()
+                .title("Input (waiting for response)")
+                .border_style(self.theme.error),
+        );
+        self.set_error_status(
+            "The previous message has not been answered yet, your message was not sent",
+        );
+    }
+
     pub fn submit_message(&mut self) -> AppResult<()> {
         let text = self.input_textarea.lines().join("\n");
         if text.is_empty() {
             return Ok(());
         }
-        let n_user_messages = self
-            .messages
-            .iter()
-            .filter(|m| matches!(m, Message::User(_)))
-            .count();
-        let n_assistant_messages = self
-            .messages
-            .iter()
-            .filter(|m| matches!(m, Message::Assistant(_)))
-            .count();
-        if n_user_messages != n_assistant_messages {
+        // Answers of other models to the same message may still be on their
+        // way after the first answer
+        if self.compare_pending
+            || self.has_unprocessed_messages
+            || self.has_unprocessed_variations
+            || self.awaiting_response
+        {
+            self.reject_submission();
             return Ok(());
         }
 
         self.has_unprocessed_messages = true;
-        self.input_textarea = styled_input_textarea();
+        self.input_textarea = styled_input_textarea(&self.theme);
         self.set_app_mode(AppMode::Normal);
         self.write_chat_log()
             .context("Unable to write submitted message to chat log")?;
         let message = Message::User(text);
-        if let Some(id) = self.conversation_id {
-            insert_message(id, &message)?;
-        } else {
-            let id = self.create_conversation()?;
-            insert_message(id, &message)?;
-        }
+        self.save_message(&message)?;
         self.messages.push(message);
+        self.trim_scrollback();
         Ok(())
     }
 
+    /// Opens the system prompt editor.
+    ///
+    /// The system prompt can only be changed before the first message of a
+    /// conversation has been sent.
+    pub fn open_system_prompt_editor(&mut self) {
+        if !self.messages.is_empty() {
+            return;
+        }
+        self.system_prompt_textarea = styled_system_prompt_textarea(&self.theme);
+        self.system_prompt_textarea
+            .insert_str(self.system_prompt.as_str());
+        self.set_app_mode(AppMode::SystemPromptEditing);
+    }
+
+    /// Uses the edited system prompt for the current conversation.
+    pub fn confirm_system_prompt(&mut self) {
+        let text = self.system_prompt_textarea.lines().join("\n");
+        if !text.trim().is_empty() {
+            self.system_prompt = text;
+        }
+        self.set_app_mode(AppMode::Editing);
+    }
+
+    /// Records model discovery problems and surfaces them in the status bar.
+    pub fn set_model_warnings(&mut self, warnings: Vec<String>) {
+        if !warnings.is_empty() {
+            self.set_error_status(format!(
+                "Model discovery failed for {} provider(s), press ? for details",
+                warnings.len()
+            ));
+        }
+        self.model_warnings = warnings;
+    }
+
     pub fn set_models(&mut self, models: Vec<(String, String)>) {
         self.model_list = ModelList::from_iter(models.into_iter().map(|(provider, model)| {
-            if model == "gpt-4o-mini" {
-                (provider, model, true)
-            } else {
-                (provider, model, false)
-            }
+            let selected = model == self.selected_model_name;
+            (provider, model, selected)
         }));
     }
 
+    /// Discovers the available models again.
+    pub fn request_model_refresh(&mut self) {
+        self.has_unprocessed_model_refresh = true;
+        self.set_status("Refreshing models...");
+    }
+
+    /// Replaces the model list with newly discovered models, keeping the
+    /// selected model.
+    pub fn receive_models(&mut self, models: Vec<(String, String)>, warnings: Vec<String>) {
+        let n_models = models.len();
+        self.set_models(models);
+        self.set_model_warnings(warnings);
+        self.set_status(format!("Found {} models", n_models));
+    }
+
+    /// Selects the model with the given name if it is in the model list.
+    ///
+    /// Returns whether the model was found.
+    pub fn select_model_by_name(&mut self, name: &str) -> bool {
+        if !self.model_list.items.iter().any(|item| item.name == name) {
+            return false;
+        }
+        for item in self.model_list.items.iter_mut() {
+            item.selected = item.name == name;
+        }
+        self.selected_model_name = name.to_string();
+        true
+    }
+
+    /// Remembers the selected model for the next session.
+    pub fn save_selected_model(&self) -> AppResult<()> {
+        save_last_model(&self.selected_model_name)
+    }
+
+    /// Seeds a new conversation with prefilled messages.
+    ///
+    /// If the last seeded message is a user message it is sent to the model
+    /// right away.
+    pub fn seed_messages(&mut self, messages: Vec<Message>) -> AppResult<()> {
+        if messages.is_empty() {
+            return Ok(());
+        }
+        for message in messages {
+            self.snippet_list.extend_unique(extract_snippets(&message));
+            self.save_message(&message)?;
+            self.messages.push(message);
+        }
+        self.has_unprocessed_messages = matches!(self.messages.last(), Some(Message::User(_)));
+        Ok(())
+    }
+
+    /// Starts displaying a streamed response.
+    pub fn start_streaming(&mut self) {
+        self.is_streaming = true;
+        self.streaming_response.clear();
+        self.n_streaming_snippets = 0;
+    }
+
+    /// Appends a chunk to the response being streamed.
+    pub fn receive_incomplete_message(&mut self, chunk: &str) {
+        if self.is_streaming {
+            self.streaming_response.push_str(chunk);
+            if self.streaming_response.contains("```") {
+                self.update_streaming_snippets();
+            }
+        }
+    }
+
+    /// Makes the snippets of the response being streamed, including a snippet
+    /// that is still being received, available in the snippet list.
+    fn update_streaming_snippets(&mut self) {
+        self.remove_streaming_snippets();
+        let blocks = find_partial_code_blocks(
+            self.streaming_response
+                .split('\n')
+                .map(|s| s.to_string())
+                .collect(),
+        );
+        self.n_streaming_snippets = blocks.len();
+        self.snippet_list.items.extend(
+            blocks
+                .into_iter()
+                .map(|(language, snippet)| SnippetItem::from(snippet).with_language(language)),
+        );
+    }
+
+    fn remove_streaming_snippets(&mut self) {
+        let n_items = self.snippet_list.items.len();
+        self.snippet_list
+            .items
+            .truncate(n_items.saturating_sub(self.n_streaming_snippets));
+        self.n_streaming_snippets = 0;
+    }
+
+    /// Should the response of the selected model be streamed?
+    pub fn should_stream(&self) -> bool {
+        self.streaming_enabled
+            && !self
+                .non_streaming_models
+                .contains(&self.selected_model_name)
+    }
+
+    /// Handles progress of a streamed response.
+    pub fn receive_stream_event(&mut self, event: StreamEvent) {
+        match event {
+            StreamEvent::Started => self.start_streaming(),
+            StreamEvent::Chunk(chunk) => self.receive_incomplete_message(&chunk),
+            StreamEvent::Fallback => {
+                // The complete response is shown when it is received
+                self.finish_streaming();
+                if self
+                    .non_streaming_models
+                    .insert(self.selected_model_name.clone())
+                {
+                    self.set_status(format!(
+                        "{} does not support streaming, showing complete answers instead",
+                        self.selected_model_name
+                    ));
+                }
+            }
+        }
+    }
+
+    /// Stops displaying the streamed response, the complete response is
+    /// received as a regular message.
+    pub fn finish_streaming(&mut self) {
+        self.is_streaming = false;
+        self.streaming_response.clear();
+        self.remove_streaming_snippets();
+    }
+
+    /// Switches between streamed and complete responses, and remembers the
+    /// choice in the config file.
+    pub fn toggle_streaming(&mut self) {
+        self.streaming_enabled = !self.streaming_enabled;
+        let saved = load_config().and_then(|mut config| {
+            config.streaming = self.streaming_enabled;
+            save_config(&config)
+        });
+        match saved {
+            Ok(()) if self.streaming_enabled => self.set_status("Streaming responses"),
+            Ok(()) => self.set_status("Not streaming responses"),
+            Err(e) => self.set_error_status(format!("Unable to save config: {}", e)),
+        }
+    }
+
+    /// Receives the response to the last request, failed requests are shown
+    /// as an error message.
+    pub async fn receive_response(&mut self, response: Result<Message, AiError>) -> AppResult<()> {
+        let message = match response {
+            Ok(message) => {
+                self.last_error = None;
+                message
+            }
+            Err(e) => {
+                self.last_error = Some(e.clone());
+                Message::from(e)
+            }
+        };
+        self.receive_message(message).await
+    }
+
+    /// Can the request that ended with the last error message be sent again?
+    pub fn can_retry(&self) -> bool {
+        matches!(self.messages.last(), Some(Message::Error(_)))
+            && self.last_error.as_ref().is_some_and(AiError::is_retryable)
+    }
+
+    /// Sends the last user message again, replacing the error message that
+    /// its request ended with.
+    pub fn retry_last_message(&mut self) -> AppResult<()> {
+        if !self.can_retry() {
+            return Ok(());
+        }
+        if let Some(error) = self.messages.pop() {
+            self.unsave_message(&error)?;
+        }
+        self.last_error = None;
+        self.has_unprocessed_messages = true;
+        Ok(())
+    }
+
     pub async fn receive_message(&mut self, message: Message) -> AppResult<()> {
-        let message_content = message.as_ref();
-        let discovered_snippets =
-            find_fenced_code_snippets(message_content.split('\n').map(|s| s.to_string()).collect());
-        let snippet_items: Vec<SnippetItem> = discovered_snippets
-            .iter()
-            .map(|snippet| snippet.to_string().into())
-            .collect();
-        self.snippet_list.items.extend(snippet_items);
+        let message = match message {
+            Message::Assistant(text, info) => Message::Assistant(
+                trim_response_prefixes(&text, &self.response_prefix_patterns).to_string(),
+                info,
+            ),
+            message => message,
+        };
+        self.snippet_list.extend_unique(extract_snippets(&message));
         self.has_unprocessed_messages = false;
+        self.awaiting_response = false;
         self.write_chat_log()
             .context("Unable to write received message to chat log")?;
-        if let Some(id) = self.conversation_id {
-            insert_message(id, &message)?;
-        } else {
-            let id = self.create_conversation()?;
-            insert_message(id, &message)?;
-        }
+        self.save_message(&message)?;
         self.messages.push(message);
+        self.trim_scrollback();
         Ok(())
     }
 
-    #[cfg(not(target_os = "linux"))]
+    /// Inserts pasted text into the text area of the current mode.
+    ///
+    /// Pastes are ignored in modes without a text area.
+    pub fn paste_text(&mut self, text: &str) {
+        let text = sanitize_paste(text);
+        let n_chars = text.chars().count();
+        let textarea = match self.app_mode {
+            AppMode::Editing => &mut self.input_textarea,
+            AppMode::SystemPromptEditing => &mut self.system_prompt_textarea,
+            // The custom model name is a single line.
+            AppMode::CustomModelEntry => {
+                self.custom_model_textarea
+                    .insert_str(text.lines().next().unwrap_or_default());
+                return;
+            }
+            AppMode::TemperatureEntry => {
+                self.temperature_textarea
+                    .insert_str(text.lines().next().unwrap_or_default());
+                return;
+            }
+            AppMode::TemplateNameEntry => {
+                self.template_name_textarea
+                    .insert_str(text.lines().next().unwrap_or_default());
+                return;
+            }
+            AppMode::ChatSwitcher => {
+                self.chat_switcher_textarea
+                    .insert_str(text.lines().next().unwrap_or_default());
+                self.update_chat_switcher();
+                return;
+            }
+            AppMode::HistoryFilter => {
+                self.history_filter_textarea
+                    .insert_str(text.lines().next().unwrap_or_default());
+                if let Err(e) = self.update_history_filter() {
+                    self.set_error_status(format!("{:#}", e));
+                }
+                return;
+            }
+            _ => return,
+        };
+        textarea.insert_str(text);
+        if n_chars > LARGE_PASTE_CHARS {
+            self.set_status(format!("Pasted a large text ({} characters)", n_chars));
+        }
+    }
+
     pub fn paste_to_input_textarea(&mut self) {
-        if let Ok(clipboard_content) = self.clipboard.get_text() {
-            self.input_textarea.insert_str(clipboard_content);
+        match clipboard::available(&mut self.clipboard).and_then(|c| c.get_text()) {
+            Ok(clipboard_content) => self.paste_text(&clipboard_content),
+            Err(e) => self.set_error_status(e.to_string()),
         }
     }
 
-    #[cfg(not(target_os = "linux"))]
     pub fn yank_latest_assistant_message(&mut self) {
-        let assistant_messages = self.messages.iter().filter_map(|m| match m {
-            Message::Assistant(message) => Some(message),
+        let latest_assistant_message = self.messages.iter().rev().find_map(|m| match m {
+            Message::Assistant(message, _) => Some(message),
             _ => None,
         });
-        if let Some(message) = assistant_messages.last() {
-            self.clipboard.set_text(message).unwrap();
+        let Some(message) = latest_assistant_message else {
+            self.set_status("No answer to copy");
+            return;
+        };
+        match clipboard::available(&mut self.clipboard).and_then(|c| c.set_text(message)) {
+            Ok(()) => self.set_status("Copied the last answer"),
+            Err(e) => self.set_error_status(format!("{:#}", e)),
+        }
+    }
+
+    /// Copies the latest answer formatted as markdown, with a heading for the
+    /// role and its code blocks kept fenced.
+    pub fn copy_latest_assistant_message_as_markdown(&mut self) {
+        let Some(message) = self
+            .messages
+            .iter()
+            .rev()
+            .find(|m| matches!(m, Message::Assistant(..)))
+        else {
+            self.set_status("No answer to copy");
+            return;
+        };
+        match clipboard::available(&mut self.clipboard)
+            .and_then(|c| c.set_text(&format_markdown_message(message)))
+        {
+            Ok(()) => self.set_status("Copied the last answer as markdown"),
+            Err(e) => self.set_error_status(format!("{:#}", e)),
+        }
+    }
+
+    pub fn toggle_response_info(&mut self) {
+        self.show_response_info = !self.show_response_info;
+    }
+
+    /// Keeps the scroll position within the conversation after the terminal
+    /// was resized, since messages wrap onto fewer lines in a wider terminal.
+    ///
+    /// A response being streamed is rendered from the text received so far
+    /// on every draw, so it is wrapped to the new width without losing text.
+    pub fn handle_resize(&mut self) {
+        self.selection = Selection::default();
+        self.vertical_scroll = self
+            .vertical_scroll
+            .min(self.get_max_scroll().unwrap_or_default());
+    }
+
+    pub fn toggle_compact(&mut self) {
+        self.compact = !self.compact;
+        // Keep the scroll position within the conversation
+        self.vertical_scroll = self
+            .vertical_scroll
+            .min(self.get_max_scroll().unwrap_or_default());
+    }
+
+    pub fn toggle_mouse_capture(&mut self) {
+        self.mouse_capture = !self.mouse_capture;
+        self.selection = Selection::default();
+        if self.mouse_capture {
+            self.set_status("Mouse capture enabled, select text with the mouse to copy it");
+        } else {
+            self.set_status("Mouse capture disabled, the terminal selects text, press M to undo");
         }
     }
 
@@ -366,6 +1207,32 @@ impl<'a> App<'a> {
         self.running = false;
     }
 
+    /// Quits unless a response is being streamed, which is only abandoned
+    /// when `key` is pressed again shortly after.
+    pub fn request_quit(&mut self, key: &str) {
+        if self.is_streaming && self.quit_pending_ticks == 0 {
+            self.quit_pending_ticks = QUIT_PENDING_TICKS;
+            self.set_error_status(format!(
+                "A response is in progress, press {} again to quit",
+                key
+            ));
+        } else {
+            self.quit();
+        }
+    }
+
+    /// Handles `q` in normal mode according to the configured [`QuitKey`].
+    pub fn press_quit_key(&mut self) {
+        match self.quit_key {
+            QuitKey::DoublePress if self.quit_pending_ticks == 0 && !self.is_streaming => {
+                self.quit_pending_ticks = QUIT_PENDING_TICKS;
+                self.set_status("Press q again to quit");
+            }
+            QuitKey::Instant | QuitKey::DoublePress => self.request_quit("q"),
+            QuitKey::Disabled => self.set_status("Press Esc to quit"),
+        }
+    }
+
     pub fn select_no_model(&mut self) {
         self.model_list.state.select(None);
     }
@@ -392,49 +1259,447 @@ impl<'a> App<'a> {
                 item.selected = false;
             }
             self.model_list.items[i].selected = true;
-            self.selected_model_name = self.model_list.items[i].name.to_string();
+            let previous = std::mem::replace(
+                &mut self.selected_model_name,
+                self.model_list.items[i].name.to_string(),
+            );
+            self.note_model_switch(&previous);
+        }
+    }
+
+    /// Notes a switch of the model in the conversation, so that it's visible
+    /// which model answered which message. Switching again before the next
+    /// message replaces the note.
+    fn note_model_switch(&mut self, previous: &str) {
+        if !self.model_switch_notes
+            || self.messages.is_empty()
+            || previous == self.selected_model_name
+        {
+            return;
+        }
+        let note = format!("Switched to {}", self.selected_model_name);
+        if let Err(e) = self.replace_trailing_note(note) {
+            self.set_error_status(format!("Unable to save the note: {:#}", e));
+        }
+    }
+
+    /// Adds a note to the conversation, in place of a note it ends with.
+    fn replace_trailing_note(&mut self, text: String) -> AppResult<()> {
+        if let Some(Message::Note(_)) = self.messages.last() {
+            if let Some(note) = self.messages.pop() {
+                self.unsave_message(&note)?;
+            }
+        }
+        let note = Message::Note(text);
+        self.save_message(&note)?;
+        self.messages.push(note);
+        Ok(())
+    }
+
+    /// Opens the model list to answer the last message again with another
+    /// model, the answer is added after the current one.
+    pub fn open_model_comparison(&mut self) {
+        let last_message = self
+            .messages
+            .iter()
+            .rev()
+            .find(|m| !matches!(m, Message::Note(_)));
+        if self.is_streaming
+            || self.has_unprocessed_messages
+            || self.awaiting_response
+            || !matches!(last_message, Some(Message::Assistant(..)))
+        {
+            self.set_status("Wait for an answer before asking another model");
+            return;
+        }
+        self.set_app_mode(AppMode::ModelSelection);
+        self.compare_pending = true;
+        self.set_status("Choose the model that answers the last message again");
+    }
+
+    /// Sends the last user message again to the model that was just chosen,
+    /// with a note naming it above its answer.
+    pub fn compare_with_selected_model(&mut self) {
+        self.compare_pending = false;
+        let note = format!("Answer of {} to the same message", self.selected_model_name);
+        match self.replace_trailing_note(note) {
+            Ok(()) => {
+                self.has_unprocessed_messages = true;
+                self.status = None;
+            }
+            Err(e) => self.set_error_status(format!("Unable to save the note: {:#}", e)),
+        }
+        self.set_app_mode(AppMode::Normal);
+    }
+
+    /// Opens the input for a model name that isn't in the model list.
+    pub fn open_custom_model_entry(&mut self) {
+        self.custom_model_textarea = styled_custom_model_textarea(&self.theme);
+        self.set_app_mode(AppMode::CustomModelEntry);
+    }
+
+    /// Uses the entered model name as is.
+    ///
+    /// The model name is not validated, an unknown model results in an error
+    /// message when the next message is sent.
+    pub fn set_custom_model(&mut self) {
+        let name = self
+            .custom_model_textarea
+            .lines()
+            .join("")
+            .trim()
+            .to_string();
+        if name.is_empty() {
+            self.set_app_mode(AppMode::ModelSelection);
+            return;
+        }
+        for item in self.model_list.items.iter_mut() {
+            item.selected = item.name == name;
+        }
+        let previous = std::mem::replace(&mut self.selected_model_name, name);
+        self.note_model_switch(&previous);
+        if self.compare_pending {
+            self.compare_with_selected_model();
+        } else {
+            self.set_app_mode(AppMode::Editing);
+        }
+    }
+
+    /// Shows statistics across all saved conversations.
+    pub fn open_dashboard(&mut self) -> AppResult<()> {
+        self.usage_stats = usage_stats().context("Failed to collect statistics")?;
+        self.set_app_mode(AppMode::Dashboard);
+        Ok(())
+    }
+
+    /// Opens the input for the temperature, prefilled with the current value.
+    pub fn open_temperature_entry(&mut self) {
+        self.temperature_textarea = styled_temperature_textarea(&self.theme);
+        self.temperature_textarea
+            .insert_str(self.temperature.to_string());
+        self.set_app_mode(AppMode::TemperatureEntry);
+    }
+
+    /// Uses the entered temperature for new requests.
+    ///
+    /// Invalid values are rejected and the input stays open.
+    pub fn set_temperature(&mut self) {
+        let text = self.temperature_textarea.lines().join("");
+        match validate_temperature(text.trim()) {
+            Ok(temperature) => {
+                self.temperature = temperature;
+                self.set_status(format!("Temperature set to {}", temperature));
+                self.set_app_mode(AppMode::Normal);
+            }
+            Err(e) => self.set_error_status(e),
+        }
+    }
+
+    pub fn select_next_template(&mut self) {
+        self.template_list.state.select_next();
+    }
+
+    pub fn select_previous_template(&mut self) {
+        self.template_list.state.select_previous();
+    }
+
+    /// Inserts the selected template into the input at the cursor.
+    pub fn insert_template(&mut self) {
+        if let Some(template) = self.template_list.selected() {
+            self.input_textarea.insert_str(&template.text);
+        }
+        self.set_app_mode(AppMode::Editing);
+    }
+
+    /// Opens the input for the name the current input is saved under.
+    pub fn open_template_name_entry(&mut self) {
+        if self.input_textarea.is_empty() {
+            self.set_status("Write the template in the input first");
+            return;
+        }
+        self.template_name_textarea = styled_template_name_textarea(&self.theme);
+        self.set_app_mode(AppMode::TemplateNameEntry);
+    }
+
+    /// Saves the current input as a template under the entered name,
+    /// replacing a template with the same name.
+    pub fn save_template(&mut self) {
+        let name = self.template_name_textarea.lines().join("");
+        let name = name.trim();
+        if name.is_empty() {
+            self.set_app_mode(AppMode::TemplateSelection);
+            return;
+        }
+        let text = self.input_textarea.lines().join("\n");
+        self.template_list.insert(name, &text);
+        self.store_templates();
+        self.set_app_mode(AppMode::TemplateSelection);
+    }
+
+    pub fn delete_selected_template(&mut self) {
+        if self.template_list.remove_selected().is_some() {
+            self.store_templates();
+        }
+    }
+
+    /// Writes the templates to the templates file, unless in read-only mode.
+    fn store_templates(&mut self) {
+        if self.read_only {
+            self.set_status("Read-only, templates are kept until ait exits");
+            return;
+        }
+        if let Err(e) = save_templates(&self.template_list.to_map()) {
+            self.set_error_status(format!("{:#}", e));
+        }
+    }
+
+    /// Pins or unpins the selected snippet, pinned snippets are kept when
+    /// switching conversations.
+    pub fn toggle_snippet_pin(&mut self) {
+        if let Some(item) = self
+            .snippet_list
+            .state
+            .selected()
+            .and_then(|i| self.snippet_list.items.get_mut(i))
+        {
+            item.pinned = !item.pinned;
         }
     }
 
     pub fn select_no_snippet(&mut self) {
         self.snippet_list.state.select(None);
+        self.reset_snippet_preview_scroll();
     }
 
     pub fn select_next_snippet(&mut self) {
         self.snippet_list.state.select_next();
+        self.reset_snippet_preview_scroll();
     }
     pub fn select_previous_snippet(&mut self) {
         self.snippet_list.state.select_previous();
+        self.reset_snippet_preview_scroll();
     }
 
     pub fn select_first_snippet(&mut self) {
         self.snippet_list.state.select_first();
+        self.reset_snippet_preview_scroll();
     }
 
     pub fn select_last_snippet(&mut self) {
         self.snippet_list.state.select_last();
+        self.reset_snippet_preview_scroll();
+    }
+
+    fn reset_snippet_preview_scroll(&mut self) {
+        self.snippet_preview_hscroll = 0;
+        self.snippet_preview_vscroll = 0;
+    }
+
+    /// Scrolls the help up.
+    pub fn scroll_help_up(&mut self) {
+        self.help_scroll = self.help_scroll.saturating_sub(1);
+    }
+
+    /// Scrolls the help down, the scroll is kept within the help when it is
+    /// rendered.
+    pub fn scroll_help_down(&mut self) {
+        self.help_scroll = self.help_scroll.saturating_add(1);
+    }
+
+    /// Scrolls the snippet preview up.
+    pub fn scroll_snippet_preview_up(&mut self) {
+        self.snippet_preview_vscroll = self.snippet_preview_vscroll.saturating_sub(1);
+    }
+
+    /// Scrolls the snippet preview down, up to the last line of the snippet.
+    pub fn scroll_snippet_preview_down(&mut self) {
+        let n_lines = self
+            .get_snippet_text()
+            .map_or(0, |text| text.lines().count());
+        let max_scroll = u16::try_from(n_lines.saturating_sub(1)).unwrap_or(u16::MAX);
+        self.snippet_preview_vscroll = self
+            .snippet_preview_vscroll
+            .saturating_add(1)
+            .min(max_scroll);
+    }
+
+    /// Scrolls the snippet preview to the left.
+    pub fn scroll_snippet_preview_left(&mut self) {
+        self.snippet_preview_hscroll = self
+            .snippet_preview_hscroll
+            .saturating_sub(SNIPPET_PREVIEW_HSCROLL_STEP);
+    }
+
+    /// Scrolls the snippet preview to the right, up to the end of the longest
+    /// line of the snippet.
+    pub fn scroll_snippet_preview_right(&mut self) {
+        let max_width = self
+            .get_snippet_text()
+            .and_then(|text| text.lines().map(|l| l.chars().count()).max())
+            .unwrap_or(0);
+        let max_scroll = u16::try_from(max_width.saturating_sub(1)).unwrap_or(u16::MAX);
+        self.snippet_preview_hscroll = self
+            .snippet_preview_hscroll
+            .saturating_add(SNIPPET_PREVIEW_HSCROLL_STEP)
+            .min(max_scroll);
+    }
+
+    /// The selected snippet, highlighted with the theme.
+    pub fn get_highlighted_snippet(&self) -> Option<Vec<Line<'static>>> {
+        self.snippet_list
+            .state
+            .selected()
+            .and_then(|i| self.snippet_list.items.get(i))
+            .map(|item| {
+                if self.theme.syntax_highlighting {
+                    create_highlighted_code(&item.text, item.language.as_deref(), &self.code_theme)
+                } else {
+                    item.text
+                        .lines()
+                        .map(|l| Line::from(l.to_string()))
+                        .collect()
+                }
+            })
     }
 
     pub fn get_snippet_text(&self) -> Option<&String> {
         self.snippet_list
             .state
             .selected()
-            .map(|i| &self.snippet_list.items[i].text)
+            .and_then(|i| self.snippet_list.items.get(i))
+            .map(|item| &item.text)
     }
 
-    #[cfg(not(target_os = "linux"))]
-    /// Copy the selected snippet to the clipboard.
-    pub fn copy_snippet(&mut self) -> AppResult<()> {
-        if let Some(i) = self.snippet_list.state.selected() {
+    /// Copy the selected snippet to the clipboard, failures are shown in the
+    /// status line.
+    pub fn copy_snippet(&mut self) {
+        // Snippets of a streamed response are replaced as it is received.
+        if let Some(i) = self
+            .snippet_list
+            .state
+            .selected()
+            .filter(|i| *i < self.snippet_list.items.len())
+        {
             for item in self.snippet_list.items.iter_mut() {
                 item.selected = false;
             }
             self.snippet_list.items[i].selected = true;
-            self.clipboard
-                .set_text(&self.snippet_list.items[i].text)
-                .context("Unable to copy snippet to clipboard")?;
+            if let Err(e) = clipboard::available(&mut self.clipboard)
+                .and_then(|c| c.set_text(&self.snippet_list.items[i].text))
+            {
+                self.set_error_status(format!("{:#}", e));
+            }
+        }
+    }
+
+    /// Quits and prints the selected shell snippet, so that it can be run from
+    /// the shell. The snippet is never run by `ait` itself.
+    ///
+    /// Has to be enabled in the config and confirmed by choosing the same
+    /// snippet twice.
+    pub fn print_snippet_on_exit(&mut self) {
+        if !self.shell_snippets_enabled {
+            self.set_status("Set shell_snippets = true in the config to print commands on exit");
+            return;
+        }
+        let Some(i) = self
+            .snippet_list
+            .state
+            .selected()
+            .filter(|i| *i < self.snippet_list.items.len())
+        else {
+            return;
+        };
+        let item = &self.snippet_list.items[i];
+        if !item.is_shell() {
+            self.set_status("Only shell snippets can be printed on exit");
+            return;
+        }
+        if self.confirm_shell_snippet.as_ref() != Some(&item.text) {
+            self.confirm_shell_snippet = Some(item.text.clone());
+            self.set_error_status(
+                "Check the command, press x again to quit and print it without running it",
+            );
+            return;
+        }
+        self.exit_command = Some(item.text.trim_end().to_string());
+        self.quit();
+    }
+
+    /// Copies the text of a finished mouse selection to the clipboard.
+    ///
+    /// The selection stays highlighted until the next click or `Esc`. A click
+    /// without dragging clears the selection.
+    pub fn copy_selection(&mut self) {
+        if self.selection.start == self.selection.end {
+            self.selection = Selection::default();
+            return;
+        }
+        self.selection.is_finished = true;
+        let Some(text) = self.selection.text.clone() else {
+            return;
+        };
+        match clipboard::available(&mut self.clipboard).and_then(|c| c.set_text(&text)) {
+            Ok(()) => self.set_status(format!("Copied {} characters", text.chars().count())),
+            Err(e) => self.set_error_status(format!("{:#}", e)),
+        }
+    }
+
+    /// Last code block of the newest answer.
+    pub fn last_snippet(&self) -> Option<SnippetItem> {
+        self.messages
+            .iter()
+            .rev()
+            .find(|m| matches!(m, Message::Assistant(..)))
+            .and_then(|m| extract_snippets(m).pop())
+    }
+
+    /// Copies the most recent snippet of the conversation to the clipboard.
+    pub fn copy_last_snippet(&mut self) {
+        let Some(snippet) = self.last_snippet() else {
+            self.set_status("No code blocks to copy");
+            return;
+        };
+        match clipboard::available(&mut self.clipboard).and_then(|c| c.set_text(&snippet.text)) {
+            Ok(()) => self.set_status("Copied the last code block"),
+            Err(e) => self.set_error_status(format!("{:#}", e)),
+        }
+    }
+
+    /// Copies the last code block and opens it in the external editor, to
+    /// keep working on it.
+    pub fn edit_last_snippet(&mut self) {
+        let Some(snippet) = self.last_snippet() else {
+            self.set_status("No code blocks to edit");
+            return;
+        };
+        if let Err(e) =
+            clipboard::available(&mut self.clipboard).and_then(|c| c.set_text(&snippet.text))
+        {
+            self.set_error_status(format!("{:#}", e));
+        }
+        self.snippet_to_edit = Some(snippet);
+    }
+
+    /// Writes all snippets of the conversation to files.
+    ///
+    /// Existing files are only overwritten when the export is confirmed by
+    /// exporting again.
+    pub fn export_snippets(&mut self) {
+        let overwrite = std::mem::take(&mut self.confirm_snippet_overwrite);
+        match export_snippets(&self.messages, Path::new(DEFAULT_SNIPPETS_DIR), overwrite) {
+            Ok(paths) if paths.is_empty() => self.set_status("No snippets to export"),
+            Ok(paths) => self.set_status(format!(
+                "Exported {} snippet(s) to {}",
+                paths.len(),
+                DEFAULT_SNIPPETS_DIR
+            )),
+            Err(e) if e.is::<FileExists>() => {
+                self.confirm_snippet_overwrite = true;
+                self.set_error_status(format!("{}, press E again to overwrite", e));
+            }
+            Err(e) => self.set_error_status(format!("{:#}", e)),
         }
-        Ok(())
     }
 
     pub fn select_no_chat(&mut self) {
@@ -457,49 +1722,212 @@ impl<'a> App<'a> {
     }
 
     pub fn set_chat_list(&mut self) -> AppResult<()> {
-        let chats = list_all_conversations()?;
+        let chats = if self.history_filter.is_empty() {
+            list_all_conversations()?
+        } else {
+            search_conversations(&self.history_filter)?
+        };
         let chats = chats
             .into_iter()
             .map(|(id, started_at)| (id, started_at, false))
             .collect::<Vec<(i64, String, bool)>>();
         self.chat_list = ChatList::from_iter(chats);
+        self.chat_list
+            .mark_favorites(&list_favorite_conversations()?);
+        Ok(())
+    }
+
+    /// Stars or unstars the selected chat, keeping it selected as it moves.
+    pub fn toggle_selected_chat_favorite(&mut self) -> AppResult<()> {
+        if self.read_only {
+            self.set_error_status("Chats cannot be starred in read-only mode");
+            return Ok(());
+        }
+        let Some(item) = self
+            .chat_list
+            .state
+            .selected()
+            .and_then(|i| self.chat_list.items.get(i))
+        else {
+            return Ok(());
+        };
+        let chat_id = item.chat_id;
+        set_conversation_favorite(chat_id, !item.favorite)?;
+        self.set_chat_list()?;
+        let i = self
+            .chat_list
+            .items
+            .iter()
+            .position(|item| item.chat_id == chat_id);
+        self.chat_list.state.select(i);
+        Ok(())
+    }
+
+    /// Opens the chat history with all saved conversations.
+    pub fn open_chat_history(&mut self) -> AppResult<()> {
+        self.history_filter.clear();
+        self.history_filter_textarea = styled_history_filter_textarea(&self.theme);
+        self.set_chat_list()?;
+        self.set_app_mode(AppMode::ShowHistory);
+        Ok(())
+    }
+
+    /// Filters the chat history with the text of the filter as it is typed.
+    pub fn update_history_filter(&mut self) -> AppResult<()> {
+        self.history_filter = self.history_filter_textarea.lines().join("");
+        self.set_chat_list()
+    }
+
+    /// Removes the filter of the chat history.
+    pub fn clear_history_filter(&mut self) -> AppResult<()> {
+        self.history_filter_textarea = styled_history_filter_textarea(&self.theme);
+        self.update_history_filter()?;
+        self.set_app_mode(AppMode::ShowHistory);
+        Ok(())
+    }
+
+    /// Opens the quick switcher with all saved conversations.
+    pub fn open_chat_switcher(&mut self) -> AppResult<()> {
+        self.chat_switcher = ChatSwitcher::new(list_conversation_summaries()?);
+        self.chat_switcher_textarea = styled_chat_switcher_textarea(&self.theme);
+        self.set_app_mode(AppMode::ChatSwitcher);
+        Ok(())
+    }
+
+    /// Filters the conversations of the quick switcher with the search text.
+    pub fn update_chat_switcher(&mut self) {
+        let query = self.chat_switcher_textarea.lines().join("");
+        self.chat_switcher.set_query(&query);
+    }
+
+    pub fn select_next_switcher_chat(&mut self) {
+        self.chat_switcher.state.select_next();
+    }
+
+    pub fn select_previous_switcher_chat(&mut self) {
+        self.chat_switcher.state.select_previous();
+    }
+
+    /// Opens the conversation chosen in the quick switcher.
+    pub fn switch_to_selected_chat(&mut self) -> AppResult<()> {
+        let Some(chat_id) = self
+            .chat_switcher
+            .selected()
+            .map(|summary| summary.conversation_id)
+        else {
+            return Ok(());
+        };
+        self.history_filter.clear();
+        self.set_chat_list()?;
+        let i = self
+            .chat_list
+            .items
+            .iter()
+            .position(|item| item.chat_id == chat_id);
+        self.chat_list.state.select(i);
+        self.set_chat()?;
+        self.set_app_mode(AppMode::Normal);
         Ok(())
     }
 
     pub fn delete_selected_chat(&mut self) -> AppResult<()> {
-        if let Some(i) = self.chat_list.state.selected() {
+        if self.read_only {
+            self.set_error_status("Chats cannot be deleted in read-only mode");
+            return Ok(());
+        }
+        if let Some(i) = self
+            .chat_list
+            .state
+            .selected()
+            .filter(|&i| i < self.chat_list.items.len())
+        {
             let chat_id = self.chat_list.items[i].chat_id;
             delete_conversation(chat_id)?;
             self.chat_list.items.remove(i);
             self.messages.clear();
-            self.messages = list_all_messages(chat_id)?;
+            self.unloaded_messages = 0;
             self.conversation_id = None;
         }
         Ok(())
     }
 
+    /// Appends the messages of the current chat to the selected chat, when
+    /// confirmed by choosing the same chat twice, and continues the merged
+    /// chat. The current chat is deleted.
+    pub fn merge_current_into_selected_chat(&mut self) -> AppResult<()> {
+        if self.read_only {
+            self.set_error_status("Chats cannot be merged in read-only mode");
+            return Ok(());
+        }
+        let Some(from) = self.conversation_id else {
+            self.set_status("The current chat isn't saved, there is nothing to merge");
+            return Ok(());
+        };
+        let Some(&into) = self.get_selected_chat_id() else {
+            return Ok(());
+        };
+        if into == from {
+            self.set_status("Select another chat to merge the current chat into");
+            return Ok(());
+        }
+        if self.is_streaming || self.has_unprocessed_messages || self.awaiting_response {
+            self.set_status("Wait for the answer before merging the chat");
+            return Ok(());
+        }
+        if self.confirm_merge != Some(into) {
+            self.confirm_merge = Some(into);
+            self.set_error_status(format!(
+                "Press m again to append the messages of chat #{} to chat #{} and delete #{}",
+                from, into, from
+            ));
+            return Ok(());
+        }
+        merge_conversations(into, from).context("Failed to merge the chats")?;
+        self.history_filter.clear();
+        self.set_chat_list()?;
+        let i = self
+            .chat_list
+            .items
+            .iter()
+            .position(|item| item.chat_id == into);
+        self.chat_list.state.select(i);
+        // Reloads the messages and finds their snippets again
+        self.set_chat()?;
+        self.set_app_mode(AppMode::Normal);
+        self.set_status(format!("Merged chat #{} into chat #{}", from, into));
+        Ok(())
+    }
+
     pub fn delete_chat_by_id(&mut self, id: i64) -> AppResult<()> {
+        if self.read_only {
+            return Ok(());
+        }
         delete_conversation(id)?;
         Ok(())
     }
 
     pub fn new_chat(&mut self) {
+        self.finish_streaming();
         if !self.messages.is_empty() {
             self.messages = Vec::new();
+            self.unloaded_messages = 0;
             self.conversation_id = None;
             self.has_unprocessed_messages = false;
+            self.awaiting_response = false;
+            self.last_error = None;
+            self.system_prompt = self.default_system_prompt.clone();
+            self.snippet_list.clear_unpinned();
         }
     }
 
     pub fn redo_last_message(&mut self) -> AppResult<()> {
         self.has_unprocessed_messages = false;
+        self.finish_streaming();
         while let Some(m) = self.messages.pop() {
-            if let Some(chat_id) = self.conversation_id {
-                delete_message(chat_id, &m)?;
-            }
+            self.unsave_message(&m)?;
             match m {
                 Message::User(s) => {
-                    self.input_textarea = styled_input_textarea();
+                    self.input_textarea = styled_input_textarea(&self.theme);
                     self.input_textarea.insert_str(s);
                     break;
                 }
@@ -510,21 +1938,107 @@ impl<'a> App<'a> {
         }
 
         // Clear snippet list and find fenced code snippets
-        self.snippet_list.clear();
+        self.snippet_list.clear_unpinned();
         for message in self.messages.iter() {
-            let message_content = message.as_ref();
-            let discovered_snippets = find_fenced_code_snippets(
-                message_content.split('\n').map(|s| s.to_string()).collect(),
-            );
-            let snippet_items: Vec<SnippetItem> = discovered_snippets
-                .iter()
-                .map(|snippet| snippet.to_string().into())
-                .collect();
-            self.snippet_list.items.extend(snippet_items);
+            self.snippet_list.extend_unique(extract_snippets(message));
         }
         Ok(())
     }
 
+    /// Requests several alternative responses to the last user message,
+    /// replacing the current response if there is one.
+    pub fn request_variations(&mut self) -> AppResult<()> {
+        // The current answer is only replaced once a variation is chosen
+        let Some(i) = self.last_user_message_index() else {
+            return Ok(());
+        };
+        self.variation_list.previous = self.messages[i + 1..].iter().rev().find_map(|m| match m {
+            Message::Assistant(text, _) => Some(text.clone()),
+            _ => None,
+        });
+        self.has_unprocessed_variations = true;
+        self.set_status("Generating variations...");
+        Ok(())
+    }
+
+    fn last_user_message_index(&self) -> Option<usize> {
+        self.messages
+            .iter()
+            .rposition(|m| matches!(m, Message::User(_)))
+    }
+
+    /// Receives the variations requested with `v`, or the error of their
+    /// request.
+    pub fn receive_variations_response(&mut self, variations: AppResult<Vec<Message>>) {
+        self.awaiting_response = false;
+        match variations {
+            Ok(variations) => self.receive_variations(variations),
+            Err(e) => self.set_error_status(format!("Error receiving variations: {}", e)),
+        }
+    }
+
+    /// Shows the received variations for the user to choose from.
+    pub fn receive_variations(&mut self, variations: Vec<Message>) {
+        let previous = self.variation_list.previous.take();
+        let show_diff = self.variation_list.show_diff;
+        self.variation_list = VariationList::from_iter(variations);
+        self.variation_list.previous = previous;
+        self.variation_list.show_diff = show_diff;
+        self.status = None;
+        self.set_app_mode(AppMode::VariationSelection);
+    }
+
+    pub fn select_next_variation(&mut self) {
+        self.variation_list.state.select_next();
+    }
+
+    pub fn select_previous_variation(&mut self) {
+        self.variation_list.state.select_previous();
+    }
+
+    /// Switches the preview between the variation and its diff against the
+    /// answer it replaces.
+    pub fn toggle_variation_diff(&mut self) {
+        if self.variation_list.previous.is_none() {
+            self.set_status("No previous answer to compare with");
+            return;
+        }
+        self.variation_list.show_diff = !self.variation_list.show_diff;
+    }
+
+    pub fn get_variation_text(&self) -> Option<&str> {
+        self.variation_list
+            .state
+            .selected()
+            .and_then(|i| self.variation_list.items.get(i))
+            .map(|item| item.message.as_ref())
+    }
+
+    /// Keeps the selected variation as the response, the others are discarded.
+    pub async fn choose_variation(&mut self) -> AppResult<()> {
+        let Some(i) = self.variation_list.state.selected() else {
+            return Ok(());
+        };
+        if i >= self.variation_list.items.len() {
+            return Ok(());
+        }
+        let item = self.variation_list.items.remove(i);
+        self.variation_list.clear();
+        // The answers to the prompt of the variations are replaced
+        if let Some(i) = self.last_user_message_index() {
+            for m in self.messages.split_off(i + 1) {
+                self.unsave_message(&m)?;
+            }
+        }
+        self.snippet_list.clear_unpinned();
+        for message in self.messages.iter() {
+            self.snippet_list.extend_unique(extract_snippets(message));
+        }
+        self.receive_message(item.message).await?;
+        self.set_app_mode(AppMode::Normal);
+        Ok(())
+    }
+
     pub fn get_selected_chat_id(&self) -> Option<&i64> {
         if self.chat_list.items.is_empty() {
             return None;
@@ -541,23 +2055,42 @@ impl<'a> App<'a> {
                 item.selected = false;
             }
             self.chat_list.items[i].selected = true;
+            self.finish_streaming();
+            self.last_error = None;
             self.conversation_id = Some(self.chat_list.items[i].chat_id);
-            self.messages.clear();
-            self.messages = list_all_messages(self.chat_list.items[i].chat_id)?;
-            self.snippet_list.clear();
+            self.system_prompt = get_conversation_system_prompt(self.chat_list.items[i].chat_id)?;
+            self.load_messages(self.chat_list.items[i].chat_id)?;
+            self.snippet_list.clear_unpinned();
             for message in self.messages.iter() {
-                let message_content = message.as_ref();
-                let discovered_snippets = find_fenced_code_snippets(
-                    message_content.split('\n').map(|s| s.to_string()).collect(),
-                );
-                let snippet_items: Vec<SnippetItem> = discovered_snippets
-                    .iter()
-                    .map(|snippet| snippet.to_string().into())
-                    .collect();
-                self.snippet_list.items.extend(snippet_items);
+                self.snippet_list.extend_unique(extract_snippets(message));
             }
             self.vertical_scroll = 0;
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trimmed_errors_are_not_unloaded_messages() {
+        let mut app = App {
+            conversation_id: Some(1),
+            scrollback_limit: Some(1),
+            ..Default::default()
+        };
+        app.messages = vec![
+            Message::User("one".to_string()),
+            Message::Error("failed".to_string()),
+            Message::User("two".to_string()),
+            Message::Assistant("answer".to_string(), Default::default()),
+            Message::User("three".to_string()),
+        ];
+        app.trim_scrollback();
+        assert_eq!(app.messages, vec![Message::User("three".to_string())]);
+        // The database holds the two questions and the answer before it
+        assert_eq!(app.unloaded_messages, 3);
+    }
+}
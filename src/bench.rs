@@ -0,0 +1,84 @@
+use std::time::{Duration, Instant};
+
+use futures::future::join_all;
+
+use crate::{
+    ai::{throttle, unthrottled_assistant_response},
+    app::{AppResult, Message},
+    cli::BenchArgs,
+};
+
+/// Returns the `p`th percentile of sorted durations, using the nearest rank.
+fn percentile(sorted: &[Duration], p: f64) -> Duration {
+    if sorted.is_empty() {
+        return Duration::ZERO;
+    }
+    let rank = (p / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+/// Runs the `bench` subcommand: sends the prompt `args.n` times concurrently
+/// and reports the latency percentiles and the error rate of the responses.
+pub async fn run_bench(args: &BenchArgs, system_prompt: &str, temperature: f64) -> AppResult<()> {
+    let messages = [Message::User(args.prompt.clone())];
+    eprintln!(
+        "Sending {} concurrent requests to {}...",
+        args.n, args.model
+    );
+    let requests = (0..args.n).map(|_| async {
+        // Waiting for the rate limit isn't part of the latency
+        throttle().await;
+        let start = Instant::now();
+        let response = unthrottled_assistant_response(
+            &messages,
+            &args.model,
+            system_prompt,
+            &temperature,
+            None,
+        )
+        .await;
+        (start.elapsed(), response)
+    });
+    let results = join_all(requests).await;
+
+    let mut latencies = Vec::new();
+    let mut n_errors = 0;
+    for (latency, response) in results {
+        match response {
+            Ok(Message::Assistant(..)) => latencies.push(latency),
+            Ok(_) | Err(_) => n_errors += 1,
+        }
+    }
+    latencies.sort();
+
+    println!("model:      {}", args.model);
+    println!("requests:   {}", args.n);
+    println!(
+        "errors:     {} ({:.1}%)",
+        n_errors,
+        100.0 * n_errors as f64 / args.n.max(1) as f64
+    );
+    for p in [50.0, 90.0, 99.0] {
+        println!(
+            "p{:<9} {:.3}s",
+            format!("{}:", p),
+            percentile(&latencies, p).as_secs_f64()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile() {
+        let sorted = (1..=10).map(Duration::from_secs).collect::<Vec<_>>();
+        assert_eq!(percentile(&sorted, 50.0), Duration::from_secs(5));
+        assert_eq!(percentile(&sorted, 90.0), Duration::from_secs(9));
+        assert_eq!(percentile(&sorted, 99.0), Duration::from_secs(10));
+        assert_eq!(percentile(&sorted, 0.0), Duration::from_secs(1));
+        assert_eq!(percentile(&[], 50.0), Duration::ZERO);
+    }
+}
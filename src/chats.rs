@@ -1,5 +1,8 @@
 use ratatui::widgets::ListState;
 
+use crate::fuzzy::fuzzy_filter;
+use crate::storage::ConversationSummary;
+
 #[derive(Debug)]
 pub struct ChatList {
     pub items: Vec<ChatItem>,
@@ -11,6 +14,8 @@ pub struct ChatItem {
     pub chat_id: i64,
     pub started_at: String,
     pub selected: bool,
+    /// Starred chats are listed first
+    pub favorite: bool,
 }
 
 impl FromIterator<(i64, String, bool)> for ChatList {
@@ -31,6 +36,62 @@ impl ChatItem {
             chat_id,
             started_at,
             selected,
+            favorite: false,
+        }
+    }
+}
+
+impl ChatList {
+    /// Marks the chats with the given IDs as favorites and lists them first,
+    /// keeping the order otherwise.
+    pub fn mark_favorites(&mut self, favorite_ids: &[i64]) {
+        for item in self.items.iter_mut() {
+            item.favorite = favorite_ids.contains(&item.chat_id);
         }
+        self.items.sort_by_key(|item| !item.favorite);
     }
 }
+
+/// Saved conversations filtered with a fuzzy search, for the quick switcher.
+#[derive(Debug, Default)]
+pub struct ChatSwitcher {
+    pub summaries: Vec<ConversationSummary>,
+    /// Indices of the summaries matching the query, best matches first
+    pub matches: Vec<usize>,
+    pub state: ListState,
+}
+
+impl ChatSwitcher {
+    pub fn new(summaries: Vec<ConversationSummary>) -> Self {
+        let mut switcher = Self {
+            summaries,
+            ..Default::default()
+        };
+        switcher.set_query("");
+        switcher
+    }
+
+    /// Keeps the conversations matching `query` and selects the best match.
+    pub fn set_query(&mut self, query: &str) {
+        self.matches = fuzzy_filter(query, &self.summaries, |summary| {
+            format!("{} {}", summary_title(summary), summary.started_at)
+        });
+        self.state.select((!self.matches.is_empty()).then_some(0));
+    }
+
+    pub fn selected(&self) -> Option<&ConversationSummary> {
+        self.state
+            .selected()
+            .and_then(|i| self.matches.get(i))
+            .map(|&i| &self.summaries[i])
+    }
+}
+
+/// First line of the first user message of a conversation.
+pub fn summary_title(summary: &ConversationSummary) -> &str {
+    summary
+        .first_message
+        .as_deref()
+        .and_then(|message| message.lines().find(|line| !line.trim().is_empty()))
+        .unwrap_or("(no messages)")
+}
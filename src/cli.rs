@@ -1,17 +1,216 @@
-use clap::Parser;
+use std::fs;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+
+use crate::ai::DEFAULT_MODEL;
+use crate::app::{AppResult, Message, ResponseInfo};
 
 #[derive(Parser, Clone, Debug)]
 #[command(version, about, long_about = None)]
 pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     /// System prompt
     #[arg(short, long, default_value = "You are a helpful, friendly assistant.")]
     pub system_prompt: String,
     /// Temperature
     #[arg(short, long, value_parser = validate_temperature, default_value = "0.5")]
     pub temperature: f64,
+    /// Maximum number of most recent turns sent to the model, a turn being a
+    /// message and its answer. Overrides `history_limit` of the config
+    #[arg(long, value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub history_limit: Option<usize>,
+    /// Notify when a response completes while the terminal is unfocused
+    #[arg(long, value_enum, default_value_t = Notification::Off)]
+    pub notify: Notification,
+    /// Prefilled message to start the conversation with, as `user:<text>` or
+    /// `assistant:<text>`. Can be repeated.
+    #[arg(long = "message", value_name = "ROLE:TEXT", value_parser = parse_message)]
+    pub messages: Vec<Message>,
+    /// Continue a conversation exported with `ait export`, e.g. after editing
+    /// it. The conversation is saved as a new conversation.
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["messages", "prompt"])]
+    pub import: Option<PathBuf>,
+    /// File with context for the conversation, `-` reads it from stdin. The
+    /// context is added to the system prompt
+    #[arg(long, value_name = "FILE")]
+    pub context: Option<PathBuf>,
+    /// Start the conversation with the context as a user message, which can be
+    /// edited before it is sent, instead of adding it to the system prompt
+    #[arg(long, requires = "context")]
+    pub append_context: bool,
+    /// Maximum number of messages kept in memory, older messages are loaded
+    /// when scrolling to the top
+    #[arg(long, value_parser = clap::value_parser!(u64).range(2..))]
+    pub scrollback: Option<u64>,
+    /// Code highlighting theme, see `ait themes` for the available themes
+    #[arg(long)]
+    pub theme: Option<String>,
+    /// Print the version, git commit and genai version as JSON and exit
+    #[arg(long)]
+    pub build_info: bool,
+    /// Print a code sample highlighted with the theme and exit
+    #[arg(long)]
+    pub theme_preview: bool,
+    /// Print the response to this prompt and exit, without starting the user
+    /// interface or saving the conversation
+    #[arg(long)]
+    pub prompt: Option<String>,
+    /// Print the request `--prompt` would send as JSON and exit, without
+    /// calling the provider
+    #[arg(long, requires = "prompt")]
+    pub dry_run: bool,
+    /// Browse the chat history and chat without saving anything
+    #[arg(long)]
+    pub read_only: bool,
+    /// Draw in the normal screen instead of the alternate screen, so that the
+    /// last screen stays in the scrollback after exiting
+    #[arg(long)]
+    pub no_alt_screen: bool,
+    /// Use a minimal high-contrast palette without dim or italic text
+    #[arg(long)]
+    pub high_contrast: bool,
+    /// Render the conversation as plain labelled text without borders,
+    /// scrollbars or rules, for screen readers; combine with --no-alt-screen
+    #[arg(long)]
+    pub plain: bool,
+    /// Number of responses requested when generating variations
+    #[arg(long, default_value = "3", value_parser = clap::value_parser!(u8).range(1..=10))]
+    pub variations: u8,
+}
+
+#[derive(Subcommand, Clone, Debug)]
+pub enum Command {
+    /// Export the latest conversation
+    Export(ExportArgs),
+    /// List the available code highlighting themes
+    Themes,
+    /// Show how many saved responses each model generated
+    Stats,
+    /// Answer JSON requests for conversations and responses on a unix socket
+    #[cfg(all(feature = "serve", unix))]
+    Serve(ServeArgs),
+    /// Measure the response latency of a model
+    #[command(hide = true)]
+    Bench(BenchArgs),
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct ExportArgs {
+    /// Id of the conversation to export, defaults to the latest conversation
+    #[arg(long)]
+    pub id: Option<i64>,
+    /// Format of the exported conversation
+    #[arg(long, value_enum, default_value_t = ExportFormat::Markdown)]
+    pub format: ExportFormat,
+    /// Write the conversation to this file instead of stdout
+    #[arg(short, long, conflicts_with = "snippets_dir")]
+    pub output: Option<PathBuf>,
+    /// Write every code snippet of the conversation to a file in this directory
+    /// instead of exporting the conversation
+    #[arg(long)]
+    pub snippets_dir: Option<PathBuf>,
+    /// Overwrite existing files
+    #[arg(long)]
+    pub force: bool,
+}
+
+#[derive(Args, Clone, Debug)]
+pub struct BenchArgs {
+    /// Number of concurrent requests
+    #[arg(long, default_value = "10", value_parser = clap::builder::RangedU64ValueParser::<usize>::new().range(1..))]
+    pub n: usize,
+    /// Prompt sent in every request
+    #[arg(long, default_value = "hi")]
+    pub prompt: String,
+    /// Model to send the requests to
+    #[arg(long, default_value = DEFAULT_MODEL)]
+    pub model: String,
+}
+
+#[cfg(all(feature = "serve", unix))]
+#[derive(Args, Clone, Debug)]
+pub struct ServeArgs {
+    /// Path of the unix socket to listen on
+    #[arg(long, default_value = "/tmp/ait.sock")]
+    pub socket: PathBuf,
+}
+
+/// Format of an exported conversation.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Markdown with a heading per message
+    Markdown,
+    /// Plain text, like the chat log
+    Text,
+}
+
+/// How to notify the user about a completed response.
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Notification {
+    /// No notification
+    Off,
+    /// Ring the terminal bell
+    Bell,
+    /// Send a desktop notification (requires the `notify` feature)
+    Desktop,
+}
+
+/// Reads the context given with `--context`, from stdin if the path is `-`.
+pub fn read_context(path: &Path) -> AppResult<String> {
+    let context = if path == Path::new("-") {
+        let mut context = String::new();
+        io::stdin()
+            .read_to_string(&mut context)
+            .context("Unable to read context from stdin")?;
+        context
+    } else {
+        fs::read_to_string(path)
+            .with_context(|| format!("Unable to read context from {}", path.display()))?
+    };
+    Ok(context.trim_end().to_string())
+}
+
+/// Describes the context given with `--context` by its size and file name.
+pub fn describe_context(path: &Path, context: &str) -> String {
+    let source = match path.file_name() {
+        _ if path == Path::new("-") => "stdin".into(),
+        Some(name) => name.to_string_lossy(),
+        None => path.to_string_lossy(),
+    };
+    format!("context: {} chars from {}", context.chars().count(), source)
+}
+
+/// Adds `context` to the end of the system prompt.
+pub fn system_prompt_with_context(system_prompt: &str, context: &str) -> String {
+    format!("{}\n\nContext:\n{}", system_prompt, context)
+}
+
+fn parse_message(val: &str) -> Result<Message, String> {
+    let (role, text) = val
+        .split_once(':')
+        .ok_or_else(|| String::from("Message must be given as `role:text`"))?;
+    if text.trim().is_empty() {
+        return Err(String::from("Message text must not be empty"));
+    }
+    match role.trim() {
+        "user" => Ok(Message::User(text.to_string())),
+        "assistant" => Ok(Message::Assistant(
+            text.to_string(),
+            ResponseInfo::default(),
+        )),
+        _ => Err(format!(
+            "Unknown role `{}`, expected `user` or `assistant`",
+            role
+        )),
+    }
 }
 
-fn validate_temperature(val: &str) -> Result<f64, String> {
+/// Parses a temperature between 0.0 and 2.0.
+pub fn validate_temperature(val: &str) -> Result<f64, String> {
     val.parse::<f64>()
         .map_err(|_| String::from("Value must be a number between 0.0 and 2.0"))
         .and_then(|v| {
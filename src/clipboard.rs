@@ -0,0 +1,123 @@
+#[cfg(target_os = "linux")]
+use std::io::{self, Write};
+
+use anyhow::Context;
+
+use crate::app::AppResult;
+
+/// Pastes longer than this many characters trigger a warning.
+pub const LARGE_PASTE_CHARS: usize = 100_000;
+
+/// System clipboard.
+///
+/// Uses the `arboard` crate where available. On Linux `arboard` is not used
+/// because of <https://github.com/1Password/arboard/issues/153>, instead text
+/// is copied with the OSC 52 terminal escape sequence, which most terminal
+/// emulators support. Reading the clipboard is not possible on Linux, text is
+/// pasted through the terminal instead.
+pub struct Clipboard {
+    #[cfg(not(target_os = "linux"))]
+    inner: arboard::Clipboard,
+}
+
+impl Clipboard {
+    /// Connects to the system clipboard.
+    pub fn new() -> AppResult<Self> {
+        Ok(Self {
+            #[cfg(not(target_os = "linux"))]
+            inner: arboard::Clipboard::new().context("Unable to access the clipboard")?,
+        })
+    }
+
+    /// Copies `text` to the clipboard.
+    pub fn set_text(&mut self, text: &str) -> AppResult<()> {
+        #[cfg(not(target_os = "linux"))]
+        self.inner
+            .set_text(text)
+            .context("Unable to copy text to the clipboard")?;
+        #[cfg(target_os = "linux")]
+        {
+            // The terminal user interface is drawn on stderr.
+            let mut stderr = io::stderr();
+            write!(stderr, "\x1b]52;c;{}\x07", base64_encode(text.as_bytes()))
+                .and_then(|_| stderr.flush())
+                .context("Unable to copy text to the clipboard")?;
+        }
+        Ok(())
+    }
+
+    /// Returns the text content of the clipboard, sanitized for pasting.
+    pub fn get_text(&mut self) -> AppResult<String> {
+        #[cfg(not(target_os = "linux"))]
+        {
+            let text = self
+                .inner
+                .get_text()
+                .context("Unable to read text from the clipboard")?;
+            Ok(sanitize_paste(&text))
+        }
+        #[cfg(target_os = "linux")]
+        anyhow::bail!("Reading the clipboard is not supported, paste using the terminal instead")
+    }
+}
+
+/// The clipboard, or an error explaining that it couldn't be accessed when
+/// the app started, e.g. without a display.
+pub fn available(clipboard: &mut Option<Clipboard>) -> AppResult<&mut Clipboard> {
+    clipboard.as_mut().context("The clipboard is not available")
+}
+
+/// Removes control characters, except newlines and tabs, from pasted text and
+/// normalizes line endings.
+pub fn sanitize_paste(text: &str) -> String {
+    text.replace("\r\n", "\n")
+        .chars()
+        .map(|c| if c == '\r' { '\n' } else { c })
+        .filter(|c| !c.is_control() || *c == '\n' || *c == '\t')
+        .collect()
+}
+
+#[cfg(target_os = "linux")]
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize_paste() {
+        assert_eq!(
+            sanitize_paste("a\r\nb\tc\x1b[31md\x07\re"),
+            "a\nb\tc[31md\ne".to_string()
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_base64_encode() {
+        assert_eq!(base64_encode(b""), "");
+        assert_eq!(base64_encode(b"f"), "Zg==");
+        assert_eq!(base64_encode(b"fo"), "Zm8=");
+        assert_eq!(base64_encode(b"foo"), "Zm9v");
+        assert_eq!(base64_encode(b"foob"), "Zm9vYg==");
+    }
+}
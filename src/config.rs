@@ -0,0 +1,321 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::num::NonZeroUsize;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+
+use ::dirs::home_dir;
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::app::{AppResult, Message};
+
+/// Persistent user preferences, stored in `~/.config/ait/config.toml`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    /// Stream responses as they are generated.
+    pub streaming: bool,
+    /// Model to start with, until another model is selected in the app.
+    pub model: Option<String>,
+    /// Code highlighting theme.
+    pub theme: Option<String>,
+    /// Background color of text selected with the mouse, e.g. `blue` or
+    /// `#3b4252`. Selected text is shown in reverse video by default.
+    pub selection_color: Option<String>,
+    /// What `q` does in normal mode.
+    pub quit_key: QuitKey,
+    /// Key bindings of the input area.
+    pub input_keys: InputKeys,
+    /// Regular expressions matching boilerplate removed from the start of
+    /// responses, e.g. `Sure! Here's[^:]*:`.
+    pub trim_response_prefixes: Vec<String>,
+    /// Indent of wrapped continuation lines of each role's messages.
+    pub wrap_indent: WrapIndent,
+    /// Longest time in milliseconds chunks of a streamed response are held
+    /// back to be displayed together, 0 displays every chunk right away.
+    pub stream_flush_ms: u64,
+    /// Held back chunks are displayed once they reach this many bytes.
+    pub stream_flush_bytes: usize,
+    /// Allow printing shell snippets when exiting, to run them from the shell.
+    pub shell_snippets: bool,
+    /// Number of the latest messages shown in the preview of the chat history.
+    pub history_preview_messages: usize,
+    /// Note model switches in the conversation.
+    pub model_switch_notes: bool,
+    /// Show the newest messages at the top of the chat.
+    pub newest_first: bool,
+    /// Most requests sent to providers per minute, 0 for no limit.
+    pub requests_per_minute: u32,
+    /// Maximum number of most recent turns sent to the model.
+    pub history_limit: Option<NonZeroUsize>,
+    /// Models listed in addition to the discovered ones, as `provider:model`.
+    /// The built-in list is used when this isn't set.
+    pub fallback_models: Option<Vec<String>>,
+    /// File with more API keys in the same format as `api_keys`, relative to
+    /// the config directory. Keys in the config file take precedence.
+    pub secrets_file: Option<PathBuf>,
+    /// API keys by provider, e.g. `openai = "..."`. Keys set in the
+    /// environment take precedence.
+    pub api_keys: BTreeMap<String, String>,
+}
+
+/// Behavior of the `q` key in normal mode.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QuitKey {
+    /// Quit right away
+    #[default]
+    Instant,
+    /// Quit when `q` is pressed twice in a short time
+    DoublePress,
+    /// Don't quit, only `Esc` quits
+    Disabled,
+}
+
+/// Key bindings of the input area.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum InputKeys {
+    /// The bindings of `tui-textarea`
+    #[default]
+    Default,
+    /// `C-a`/`C-e` to the beginning/end of the line, `C-k` kills to the end
+    /// of the line, `C-u` to its beginning and `C-w` the word before the
+    /// cursor
+    Emacs,
+    /// Modal editing: `Esc` switches to a normal mode with `i`/`a` to insert
+    /// again, `dd`, `x` and the `h`/`j`/`k`/`l`/`w`/`b` motions
+    Vim,
+}
+
+/// Number of spaces wrapped continuation lines are indented by, per role.
+///
+/// Lines of fenced code blocks are never indented.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct WrapIndent {
+    pub user: usize,
+    pub assistant: usize,
+    pub error: usize,
+}
+
+impl WrapIndent {
+    /// Indent of the continuation lines of `message`.
+    pub fn of(&self, message: &Message) -> usize {
+        match message {
+            Message::User(_) => self.user,
+            Message::Assistant(..) => self.assistant,
+            Message::Error(_) => self.error,
+            Message::Note(_) => 0,
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            streaming: true,
+            model: None,
+            theme: None,
+            selection_color: None,
+            quit_key: QuitKey::default(),
+            input_keys: InputKeys::default(),
+            trim_response_prefixes: Vec::new(),
+            wrap_indent: WrapIndent::default(),
+            stream_flush_ms: 30,
+            stream_flush_bytes: 256,
+            shell_snippets: false,
+            history_preview_messages: 50,
+            model_switch_notes: true,
+            newest_first: false,
+            requests_per_minute: 0,
+            history_limit: None,
+            fallback_models: None,
+            secrets_file: None,
+            api_keys: BTreeMap::new(),
+        }
+    }
+}
+
+/// Directory of a project with its own config and chat history.
+pub const PROJECT_DIR: &str = ".ait";
+
+/// Finds the project directory in `dir` or in the closest of its parents.
+fn find_project_dir(dir: &Path) -> Option<PathBuf> {
+    dir.ancestors()
+        .map(|dir| dir.join(PROJECT_DIR))
+        .find(|path| path.is_dir())
+}
+
+/// The project directory of the current directory, if it is in a project.
+pub fn project_dir() -> Option<&'static Path> {
+    static PROJECT: OnceLock<Option<PathBuf>> = OnceLock::new();
+    PROJECT
+        .get_or_init(|| {
+            env::current_dir()
+                .ok()
+                .and_then(|dir| find_project_dir(&dir))
+        })
+        .as_deref()
+}
+
+/// Name of the project the current directory is in, if any.
+pub fn project_name() -> Option<String> {
+    let root = project_dir()?.parent()?;
+    Some(
+        root.file_name()
+            .unwrap_or(root.as_os_str())
+            .to_string_lossy()
+            .to_string(),
+    )
+}
+
+/// The config file of the project if it has one, the global config file
+/// otherwise.
+fn config_path() -> AppResult<PathBuf> {
+    if let Some(path) = project_dir()
+        .map(|dir| dir.join("config.toml"))
+        .filter(|path| path.exists())
+    {
+        return Ok(path);
+    }
+    let mut path = home_dir().context("Cannot find home directory")?;
+    path.push(".config/ait");
+    path.push("config.toml");
+    Ok(path)
+}
+
+/// Loads the configuration, using the defaults if there is no config file.
+pub fn load_config() -> AppResult<Config> {
+    let path = config_path()?;
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+    let content = fs::read_to_string(&path).context("Unable to read config file")?;
+    let config = toml::from_str(&content)
+        .with_context(|| format!("Invalid config file {}", path.display()))?;
+    Ok(config)
+}
+
+/// Collects the API keys of the config file and of the secrets file.
+///
+/// Returns the keys by provider together with warnings about files holding
+/// keys that other users can read.
+pub fn load_api_keys(config: &Config) -> AppResult<(BTreeMap<String, String>, Vec<String>)> {
+    let mut keys = BTreeMap::new();
+    let mut warnings = Vec::new();
+    if let Some(secrets_file) = &config.secrets_file {
+        let path = config_path()?.with_file_name(secrets_file);
+        let content = fs::read_to_string(&path)
+            .with_context(|| format!("Unable to read secrets file {}", path.display()))?;
+        let secrets: BTreeMap<String, String> = toml::from_str(&content)
+            .with_context(|| format!("Invalid secrets file {}", path.display()))?;
+        warnings.extend(check_permissions(&path));
+        keys.extend(secrets);
+    }
+    if !config.api_keys.is_empty() {
+        warnings.extend(check_permissions(&config_path()?));
+        keys.extend(config.api_keys.clone());
+    }
+    Ok((keys, warnings))
+}
+
+/// Warns if a file holding API keys can be read by other users.
+#[cfg(unix)]
+fn check_permissions(path: &Path) -> Option<String> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path).ok()?.permissions().mode();
+    (mode & 0o077 != 0).then(|| {
+        format!(
+            "{} holds API keys but can be read by other users, restrict it with `chmod 600 {}`",
+            path.display(),
+            path.display()
+        )
+    })
+}
+
+#[cfg(not(unix))]
+fn check_permissions(_path: &Path) -> Option<String> {
+    None
+}
+
+fn templates_path() -> AppResult<PathBuf> {
+    Ok(config_path()?.with_file_name("templates.toml"))
+}
+
+/// Loads the message templates by name from `~/.config/ait/templates.toml`.
+pub fn load_templates() -> AppResult<BTreeMap<String, String>> {
+    let path = templates_path()?;
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let content = fs::read_to_string(&path).context("Unable to read templates file")?;
+    toml::from_str(&content).with_context(|| format!("Invalid templates file {}", path.display()))
+}
+
+/// Writes the message templates to the templates file.
+pub fn save_templates(templates: &BTreeMap<String, String>) -> AppResult<()> {
+    let path = templates_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Could not create config directory")?;
+    }
+    let content = toml::to_string(templates).context("Unable to serialize templates")?;
+    fs::write(&path, content).context("Unable to write templates file")?;
+    Ok(())
+}
+
+/// File remembering the model selected when the app was last closed, apart
+/// from the config file so that the config is never rewritten on exit.
+fn last_model_path() -> AppResult<PathBuf> {
+    let mut path = home_dir().context("Cannot find home directory")?;
+    path.push(".cache/ait");
+    path.push("last_model");
+    Ok(path)
+}
+
+/// The model selected when the app was last closed, if any.
+pub fn load_last_model() -> Option<String> {
+    let model = fs::read_to_string(last_model_path().ok()?).ok()?;
+    Some(model.trim().to_string()).filter(|model| !model.is_empty())
+}
+
+/// Remembers the selected model for the next session.
+pub fn save_last_model(model: &str) -> AppResult<()> {
+    let path = last_model_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Could not create cache directory")?;
+    }
+    fs::write(&path, model).context("Unable to remember the selected model")?;
+    Ok(())
+}
+
+/// Writes the configuration to the config file.
+pub fn save_config(config: &Config) -> AppResult<()> {
+    let path = config_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Could not create config directory")?;
+    }
+    let content = toml::to_string(config).context("Unable to serialize config")?;
+    fs::write(&path, content).context("Unable to write config file")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_project_dir_is_found_in_parents() {
+        let root = env::temp_dir().join(format!("ait-project-{}", std::process::id()));
+        let nested = root.join("src/deeply/nested");
+        fs::create_dir_all(&nested).unwrap();
+        assert_eq!(find_project_dir(&nested), None);
+
+        fs::create_dir_all(root.join(PROJECT_DIR)).unwrap();
+        assert_eq!(find_project_dir(&nested), Some(root.join(PROJECT_DIR)));
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,136 @@
+use std::ops::Range;
+
+use ratatui::style::Style;
+use ratatui::text::{Line, Span, Text};
+
+use crate::theme::Theme;
+
+/// Part of a word diff between two texts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiffOp<'a> {
+    /// Text found in both texts
+    Equal(&'a str),
+    /// Text only found in the new text
+    Insert(&'a str),
+    /// Text only found in the old text
+    Delete(&'a str),
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Equal,
+    Insert,
+    Delete,
+}
+
+/// Splits a text into words and the whitespace between them, as byte ranges.
+fn tokenize(text: &str) -> Vec<Range<usize>> {
+    let mut tokens = Vec::new();
+    let mut start = 0;
+    let mut in_whitespace = None;
+    for (i, c) in text.char_indices() {
+        let whitespace = c.is_whitespace();
+        if in_whitespace.is_some_and(|w| w != whitespace) {
+            tokens.push(start..i);
+            start = i;
+        }
+        in_whitespace = Some(whitespace);
+    }
+    if start < text.len() {
+        tokens.push(start..text.len());
+    }
+    tokens
+}
+
+/// Computes the words removed from `old` and added in `new`.
+pub fn word_diff<'a>(old: &'a str, new: &'a str) -> Vec<DiffOp<'a>> {
+    let old_tokens = tokenize(old);
+    let new_tokens = tokenize(new);
+    let old_token = |i: usize| &old[old_tokens[i].clone()];
+    let new_token = |j: usize| &new[new_tokens[j].clone()];
+    let (n, m) = (old_tokens.len(), new_tokens.len());
+    // lengths[i][j] is the length of the longest common subsequence of the
+    // tokens from i in old and from j in new
+    let mut lengths = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lengths[i][j] = if old_token(i) == new_token(j) {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+    // Walks the table, merging runs of tokens of the same kind. Equal and
+    // inserted tokens are ranges of new, deleted tokens ranges of old.
+    let mut runs: Vec<(Kind, Range<usize>)> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        let (kind, range) = if i < n && j < m && old_token(i) == new_token(j) {
+            i += 1;
+            j += 1;
+            (Kind::Equal, new_tokens[j - 1].clone())
+        } else if j < m && (i == n || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            j += 1;
+            (Kind::Insert, new_tokens[j - 1].clone())
+        } else {
+            i += 1;
+            (Kind::Delete, old_tokens[i - 1].clone())
+        };
+        match runs.last_mut() {
+            Some((last_kind, last_range)) if *last_kind == kind => last_range.end = range.end,
+            _ => runs.push((kind, range)),
+        }
+    }
+    runs.into_iter()
+        .map(|(kind, range)| match kind {
+            Kind::Equal => DiffOp::Equal(&new[range]),
+            Kind::Insert => DiffOp::Insert(&new[range]),
+            Kind::Delete => DiffOp::Delete(&old[range]),
+        })
+        .collect()
+}
+
+/// Renders the diff from `old` to `new`, with added and removed words styled
+/// by the theme.
+pub fn diff_text(old: &str, new: &str, theme: &Theme) -> Text<'static> {
+    let mut lines = vec![Line::default()];
+    for op in word_diff(old, new) {
+        let (text, style) = match op {
+            DiffOp::Equal(text) => (text, Style::new()),
+            DiffOp::Insert(text) => (text, theme.inserted),
+            DiffOp::Delete(text) => (text, theme.deleted),
+        };
+        for (i, part) in text.split('\n').enumerate() {
+            if i > 0 {
+                lines.push(Line::default());
+            }
+            if !part.is_empty() {
+                if let Some(line) = lines.last_mut() {
+                    line.push_span(Span::styled(part.to_string(), style));
+                }
+            }
+        }
+    }
+    Text::from(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_word_diff() {
+        assert_eq!(
+            word_diff("the quick fox jumps", "the slow fox jumps high"),
+            vec![
+                DiffOp::Equal("the "),
+                DiffOp::Insert("slow"),
+                DiffOp::Delete("quick"),
+                DiffOp::Equal(" fox jumps"),
+                DiffOp::Insert(" high"),
+            ]
+        );
+        assert_eq!(word_diff("same", "same"), vec![DiffOp::Equal("same")]);
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::{bail, Context};
+
+use crate::app::AppResult;
+use crate::snippets::{extension_for_language, SnippetItem};
+
+/// Editor used when neither `VISUAL` nor `EDITOR` is set.
+const DEFAULT_EDITOR: &str = "vi";
+
+/// Returns the command of the user's editor, split into the program and its
+/// arguments, e.g. `code --wait`.
+pub fn editor_command() -> Vec<String> {
+    std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .ok()
+        .filter(|editor| !editor.trim().is_empty())
+        .unwrap_or_else(|| DEFAULT_EDITOR.to_string())
+        .split_whitespace()
+        .map(str::to_string)
+        .collect()
+}
+
+/// Writes a snippet to a new file in the temporary directory, with the
+/// extension of its language so that the editor highlights it.
+///
+/// The file is kept after the editor exits, as it holds the edits.
+pub fn write_snippet_file(snippet: &SnippetItem) -> AppResult<PathBuf> {
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or_default();
+    let path = std::env::temp_dir().join(format!(
+        "ait-snippet-{}.{}",
+        timestamp,
+        extension_for_language(snippet.language.as_deref())
+    ));
+    std::fs::write(&path, &snippet.text)
+        .with_context(|| format!("Failed to write snippet to {}", path.display()))?;
+    Ok(path)
+}
+
+/// Opens `path` in the user's editor and waits for it to exit.
+///
+/// The editor takes over the terminal, so the user interface has to be
+/// suspended while it runs.
+pub fn open_in_editor(path: &Path) -> AppResult<()> {
+    let command = editor_command();
+    let status = Command::new(&command[0])
+        .args(&command[1..])
+        .arg(path)
+        .status()
+        .with_context(|| format!("Failed to start the editor `{}`", command[0]))?;
+    if !status.success() {
+        bail!("The editor `{}` exited with {}", command[0], status);
+    }
+    Ok(())
+}
@@ -4,10 +4,8 @@ use crossterm::event::{Event as CrosstermEvent, KeyEvent, MouseEvent};
 use futures::{FutureExt, StreamExt};
 use tokio::sync::mpsc;
 
-use crate::app::AppResult;
-
 /// Terminal events.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Debug)]
 pub enum Event {
     /// Terminal tick.
     Tick,
@@ -17,81 +15,108 @@ pub enum Event {
     Mouse(MouseEvent),
     /// Terminal resize.
     Resize(u16, u16),
+    /// Terminal gained focus.
+    FocusGained,
+    /// Terminal lost focus.
+    FocusLost,
+    /// Text pasted into the terminal.
+    Paste(String),
 }
 
 /// Terminal event handler.
 #[allow(dead_code)]
 #[derive(Debug)]
 pub struct EventHandler {
-    /// Event sender channel.
-    sender: mpsc::UnboundedSender<Event>,
     /// Event receiver channel.
     receiver: mpsc::UnboundedReceiver<Event>,
     /// Event handler thread.
     handler: tokio::task::JoinHandle<()>,
+    /// Interval of the tick events.
+    tick_rate: Duration,
+}
+
+/// Converts a terminal event into an [`Event`], ignoring key releases and
+/// repeats.
+fn to_event(event: CrosstermEvent) -> Option<Event> {
+    match event {
+        CrosstermEvent::Key(key) if key.kind == crossterm::event::KeyEventKind::Press => {
+            Some(Event::Key(key))
+        }
+        CrosstermEvent::Key(_) => None,
+        CrosstermEvent::Mouse(mouse) => Some(Event::Mouse(mouse)),
+        CrosstermEvent::Resize(x, y) => Some(Event::Resize(x, y)),
+        CrosstermEvent::FocusLost => Some(Event::FocusLost),
+        CrosstermEvent::FocusGained => Some(Event::FocusGained),
+        CrosstermEvent::Paste(text) => Some(Event::Paste(text)),
+    }
 }
 
 impl EventHandler {
     /// Constructs a new instance of [`EventHandler`].
+    ///
+    /// The handler thread stops when the terminal input ends or the
+    /// [`EventHandler`] is dropped, which closes the event channel.
     pub fn new(tick_rate: u64) -> Self {
         let tick_rate = Duration::from_millis(tick_rate);
+        let (receiver, handler) = Self::spawn(tick_rate);
+        Self {
+            receiver,
+            handler,
+            tick_rate,
+        }
+    }
+
+    /// Stops reading terminal events, so that another process can read the
+    /// terminal input, until [`EventHandler::start`] is called.
+    pub async fn stop(&mut self) {
+        self.handler.abort();
+        let _ = (&mut self.handler).await;
+    }
+
+    /// Starts reading terminal events again after [`EventHandler::stop`].
+    pub fn start(&mut self) {
+        (self.receiver, self.handler) = Self::spawn(self.tick_rate);
+    }
+
+    /// Spawns the handler thread, sending events to the returned receiver.
+    fn spawn(tick_rate: Duration) -> (mpsc::UnboundedReceiver<Event>, tokio::task::JoinHandle<()>) {
         let (sender, receiver) = mpsc::unbounded_channel();
-        let _sender = sender.clone();
         let handler = tokio::spawn(async move {
             let mut reader = crossterm::event::EventStream::new();
             let mut tick = tokio::time::interval(tick_rate);
             loop {
                 let tick_delay = tick.tick();
                 let crossterm_event = reader.next().fuse();
-                tokio::select! {
-                  _ = _sender.closed() => {
+                let event = tokio::select! {
+                  _ = sender.closed() => {
                     break;
                   }
-                  _ = tick_delay => {
-                    _sender.send(Event::Tick).unwrap();
-                  }
-                  Some(Ok(evt)) = crossterm_event => {
-                    match evt {
-                      CrosstermEvent::Key(key) => {
-                        if key.kind == crossterm::event::KeyEventKind::Press {
-                          _sender.send(Event::Key(key)).unwrap();
-                        }
-                      },
-                      CrosstermEvent::Mouse(mouse) => {
-                        _sender.send(Event::Mouse(mouse)).unwrap();
-                      },
-                      CrosstermEvent::Resize(x, y) => {
-                        _sender.send(Event::Resize(x, y)).unwrap();
-                      },
-                      CrosstermEvent::FocusLost => {
-                      },
-                      CrosstermEvent::FocusGained => {
-                      },
-                      CrosstermEvent::Paste(_) => {
-                      },
-                    }
-                  }
+                  _ = tick_delay => Some(Event::Tick),
+                  crossterm_event = crossterm_event => match crossterm_event {
+                    Some(Ok(evt)) => to_event(evt),
+                    Some(Err(_)) => None,
+                    // The terminal input ended
+                    None => break,
+                  },
                 };
+                if let Some(event) = event {
+                    if sender.send(event).is_err() {
+                        break;
+                    }
+                }
             }
         });
-        Self {
-            sender,
-            receiver,
-            handler,
-        }
+        (receiver, handler)
     }
 
-    /// Receive the next event from the handler thread.
+    /// Receive the next event from the handler thread, or `None` once the
+    /// handler thread has stopped.
     ///
     /// This function will always block the current thread if
     /// there is no data available and it's possible for more data to be sent.
-    pub async fn next(&mut self) -> AppResult<Event> {
-        self.receiver.recv().await.ok_or(
-            Box::new(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                "This is an IO error",
-            ))
-            .into(),
-        )
+    /// It is cancel safe, no event is lost when it is used in a `select!` and
+    /// another branch completes first.
+    pub async fn next(&mut self) -> Option<Event> {
+        self.receiver.recv().await
     }
 }
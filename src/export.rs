@@ -0,0 +1,265 @@
+use std::path::{Path, PathBuf};
+use std::{fmt, fs};
+
+use anyhow::{bail, Context};
+
+use crate::{
+    app::{AppResult, Message, ResponseInfo},
+    cli::{ExportArgs, ExportFormat},
+    snippets::{extension_for_language, find_fenced_code_blocks},
+    storage::{get_conversation_system_prompt, list_all_conversations, list_all_messages},
+};
+
+/// Directory snippets are exported to from within the application.
+pub const DEFAULT_SNIPPETS_DIR: &str = "ait-snippets";
+
+/// An export would overwrite an existing file.
+#[derive(Debug)]
+pub struct FileExists(pub PathBuf);
+
+impl fmt::Display for FileExists {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} already exists", self.0.display())
+    }
+}
+
+impl std::error::Error for FileExists {}
+
+/// Fails with [`FileExists`] if any of `paths` exists, unless `overwrite` is set.
+fn check_overwrite<'a>(
+    paths: impl IntoIterator<Item = &'a PathBuf>,
+    overwrite: bool,
+) -> AppResult<()> {
+    if overwrite {
+        return Ok(());
+    }
+    match paths.into_iter().find(|path| path.exists()) {
+        Some(path) => Err(FileExists(path.clone()).into()),
+        None => Ok(()),
+    }
+}
+
+/// Writes every fenced code block in `messages` to its own file in `dir`.
+///
+/// Files are named sequentially (`snippet-001.rs`, `snippet-002.py`, ...) with
+/// an extension inferred from the language of the code block. Existing files
+/// are only replaced if `overwrite` is set. Returns the paths of the written
+/// files.
+pub fn export_snippets(
+    messages: &[Message],
+    dir: &Path,
+    overwrite: bool,
+) -> AppResult<Vec<PathBuf>> {
+    let blocks = messages
+        .iter()
+        .flat_map(|m| find_fenced_code_blocks(m.as_ref().lines().map(String::from).collect()))
+        .collect::<Vec<(Option<String>, String)>>();
+    if blocks.is_empty() {
+        return Ok(Vec::new());
+    }
+    let paths = blocks
+        .iter()
+        .enumerate()
+        .map(|(i, (language, _))| {
+            dir.join(format!(
+                "snippet-{:03}.{}",
+                i + 1,
+                extension_for_language(language.as_deref())
+            ))
+        })
+        .collect::<Vec<PathBuf>>();
+    check_overwrite(&paths, overwrite)?;
+    fs::create_dir_all(dir).context("Could not create snippet directory")?;
+    for (path, (_, code)) in paths.iter().zip(blocks) {
+        fs::write(path, code + "\n")
+            .with_context(|| format!("Unable to write snippet to {}", path.display()))?;
+    }
+    Ok(paths)
+}
+
+/// Formats a message as a markdown section headed by its role.
+pub fn format_markdown_message(message: &Message) -> String {
+    let heading = match message {
+        Message::User(_) => "User",
+        Message::Assistant(..) => "Assistant",
+        Message::Error(_) => "Error",
+        Message::Note(_) => "Note",
+    };
+    format!("## {}\n\n{}\n", heading, message.as_str())
+}
+
+/// A conversation read from an exported Markdown file.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MarkdownConversation {
+    pub system_prompt: Option<String>,
+    pub messages: Vec<Message>,
+}
+
+/// Parses a conversation in the Markdown export format, the inverse of
+/// [`format_conversation`].
+///
+/// Messages start at a `## User`, `## Assistant`, `## Error` or `## System`
+/// heading, headings inside fenced code blocks are part of the message.
+pub fn parse_markdown_conversation(text: &str) -> AppResult<MarkdownConversation> {
+    let mut conversation = MarkdownConversation::default();
+    let mut section: Option<(&str, String)> = None;
+    let mut in_code_block = false;
+    let mut finish = |section: Option<(&str, String)>| {
+        let Some((heading, body)) = section else {
+            return;
+        };
+        let body = body.trim_matches('\n').to_string();
+        match heading {
+            "System" => conversation.system_prompt = Some(body),
+            "User" => conversation.messages.push(Message::User(body)),
+            "Assistant" => conversation
+                .messages
+                .push(Message::Assistant(body, ResponseInfo::default())),
+            "Note" => conversation.messages.push(Message::Note(body)),
+            _ => conversation.messages.push(Message::Error(body)),
+        }
+    };
+    for (i, line) in text.lines().enumerate() {
+        let heading = line
+            .strip_prefix("## ")
+            .map(str::trim_end)
+            .filter(|heading| ["System", "User", "Assistant", "Error", "Note"].contains(heading));
+        match heading {
+            Some(heading) if !in_code_block => {
+                finish(section.take());
+                section = Some((heading, String::new()));
+                continue;
+            }
+            _ => {}
+        }
+        if line.trim_start().starts_with("```") {
+            in_code_block = !in_code_block;
+        }
+        match &mut section {
+            Some((_, body)) => {
+                body.push_str(line);
+                body.push('\n');
+            }
+            None if line.trim().is_empty() => {}
+            None => bail!(
+                "Line {} is not part of a message, messages start with a heading like `## User`",
+                i + 1
+            ),
+        }
+    }
+    finish(section);
+    Ok(conversation)
+}
+
+/// Reads a conversation exported as Markdown, e.g. to continue it after
+/// editing it.
+pub fn import_markdown(path: &Path) -> AppResult<MarkdownConversation> {
+    let text = fs::read_to_string(path)
+        .with_context(|| format!("Unable to read conversation from {}", path.display()))?;
+    parse_markdown_conversation(&text)
+        .with_context(|| format!("Invalid conversation in {}", path.display()))
+}
+
+/// Formats a conversation for export.
+pub fn format_conversation(
+    system_prompt: &str,
+    messages: &[Message],
+    format: ExportFormat,
+) -> String {
+    let mut output = String::new();
+    match format {
+        ExportFormat::Markdown => {
+            output.push_str(&format!("## System\n\n{}\n", system_prompt));
+            for message in messages {
+                output.push('\n');
+                output.push_str(&format_markdown_message(message));
+            }
+        }
+        ExportFormat::Text => {
+            output.push_str(&format!("System: {}\n", system_prompt));
+            for message in messages {
+                let sender = match message {
+                    Message::User(_) => "User",
+                    Message::Assistant(..) => "Assistant",
+                    Message::Error(_) => "Error",
+                    Message::Note(_) => "Note",
+                };
+                output.push_str(&format!("{}: {}\n", sender, message.as_str()));
+            }
+        }
+    }
+    output
+}
+
+/// Runs the `export` subcommand.
+pub fn run_export(args: &ExportArgs) -> AppResult<()> {
+    let conversations = list_all_conversations()?;
+    let conversation_id = match args.id {
+        Some(id) => {
+            if !conversations.iter().any(|(i, _)| *i == id) {
+                bail!("There is no conversation with id {}", id);
+            }
+            id
+        }
+        None => {
+            conversations
+                .first()
+                .context("There are no saved conversations to export")?
+                .0
+        }
+    };
+    let messages = list_all_messages(conversation_id)?;
+
+    if let Some(snippets_dir) = &args.snippets_dir {
+        let paths = export_snippets(&messages, snippets_dir, args.force)
+            .context("Use --force to overwrite existing files")?;
+        if paths.is_empty() {
+            eprintln!("The conversation contains no code snippets");
+        }
+        for path in paths {
+            println!("{}", path.display());
+        }
+        return Ok(());
+    }
+
+    let system_prompt = get_conversation_system_prompt(conversation_id)?;
+    let output = format_conversation(&system_prompt, &messages, args.format);
+    match &args.output {
+        Some(path) => {
+            check_overwrite([path], args.force)
+                .context("Use --force to overwrite existing files")?;
+            fs::write(path, output)
+                .with_context(|| format!("Unable to write conversation to {}", path.display()))?
+        }
+        None => print!("{}", output),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown_round_trip() {
+        let messages = vec![
+            Message::User("Write a heading in markdown".to_string()),
+            Message::Assistant(
+                "Like this:\n\n```markdown\n## User\n```\n\nor with `#`.".to_string(),
+                ResponseInfo::default(),
+            ),
+            Message::Error("Error: rate limited".to_string()),
+            Message::User("Thanks\n\n  indented".to_string()),
+        ];
+        let markdown = format_conversation("Be brief.", &messages, ExportFormat::Markdown);
+        let conversation = parse_markdown_conversation(&markdown).unwrap();
+        assert_eq!(conversation.system_prompt.as_deref(), Some("Be brief."));
+        assert_eq!(conversation.messages, messages);
+    }
+
+    #[test]
+    fn test_markdown_text_outside_messages_is_rejected() {
+        assert!(parse_markdown_conversation("\n## User\n\nhi\n").is_ok());
+        assert!(parse_markdown_conversation("notes\n## User\n\nhi\n").is_err());
+    }
+}
@@ -0,0 +1,62 @@
+/// Scores how well `query` matches `text`, ignoring case.
+///
+/// All characters of the query have to appear in the text in order. Matches
+/// of consecutive characters and at the start of words score higher. Returns
+/// `None` if the text doesn't match.
+pub fn fuzzy_score(query: &str, text: &str) -> Option<i64> {
+    let mut score = 0;
+    let mut query_chars = query.chars().flat_map(char::to_lowercase).peekable();
+    let mut previous: Option<char> = None;
+    let mut previous_matched = false;
+    for c in text.chars() {
+        let Some(&q) = query_chars.peek() else {
+            break;
+        };
+        let matched = c.to_lowercase().eq(q.to_lowercase());
+        if matched {
+            query_chars.next();
+            score += 1;
+            if previous_matched {
+                score += 5;
+            }
+            if previous.is_none_or(|p| !p.is_alphanumeric()) {
+                score += 3;
+            }
+        }
+        previous = Some(c);
+        previous_matched = matched;
+    }
+    query_chars.peek().is_none().then_some(score)
+}
+
+/// Returns the indices of the items matching `query`, best matches first.
+///
+/// Items with the same score keep their order, all items match an empty query.
+pub fn fuzzy_filter<T>(query: &str, items: &[T], key: impl Fn(&T) -> String) -> Vec<usize> {
+    let mut matches = items
+        .iter()
+        .enumerate()
+        .filter_map(|(i, item)| fuzzy_score(query, &key(item)).map(|score| (i, score)))
+        .collect::<Vec<(usize, i64)>>();
+    matches.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+    matches.into_iter().map(|(i, _)| i).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_fuzzy_filter() {
+        let titles = [
+            "rust lifetimes",
+            "trust issues",
+            "regex syntax",
+            "Rust macros",
+        ];
+        assert_eq!(fuzzy_filter("rust", &titles, |t| t.to_string()), [0, 3, 1]);
+        assert_eq!(fuzzy_filter("rgx", &titles, |t| t.to_string()), [2]);
+        assert_eq!(fuzzy_filter("", &titles, |t| t.to_string()).len(), 4);
+        assert_eq!(fuzzy_score("xyz", "rust"), None);
+    }
+}
@@ -1,30 +1,42 @@
 use crate::app::{App, AppMode, AppResult};
+use crate::config::InputKeys;
+use crate::selection::{Selection, SelectionMode};
 
 use anyhow::Context;
 use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
 use crossterm::event::{MouseEvent, MouseEventKind};
+use tui_textarea::{CursorMove, TextArea};
 
 /// Handles the key events and updates the state of [`App`].
-pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
+pub async fn handle_key_events(key_event: KeyEvent, app: &mut App<'_>) -> AppResult<()> {
     let KeyEvent {
         code, modifiers, ..
     } = key_event;
+    // Clear a highlighted mouse selection before anything else
+    if code == KeyCode::Esc && app.selection.start.is_some() {
+        app.selection = Selection::default();
+        return Ok(());
+    }
     match app.app_mode {
         AppMode::Normal => match code {
             // Exit application on `ESC` or `q`
-            KeyCode::Esc | KeyCode::Char('q') => app.quit(),
+            KeyCode::Esc => app.request_quit("Esc"),
+            KeyCode::Char('q') => app.press_quit_key(),
             KeyCode::Char('m') => app.set_app_mode(AppMode::ModelSelection),
             KeyCode::Char('s') => app.set_app_mode(AppMode::SnippetSelection),
             KeyCode::Char('i') => app.set_app_mode(AppMode::Editing),
-            KeyCode::Char('h') => {
-                app.set_chat_list()?;
-                app.set_app_mode(AppMode::ShowHistory)
-            }
+            KeyCode::Char('h') => app.open_chat_history()?,
             KeyCode::Char('?') => app.set_app_mode(AppMode::Help),
-            #[cfg(not(target_os = "linux"))]
+            KeyCode::Char('p') | KeyCode::Char('P')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.open_chat_switcher()?
+            }
+            KeyCode::Char('p') => app.open_system_prompt_editor(),
             KeyCode::Char('y') => app.yank_latest_assistant_message(),
+            KeyCode::Char('Y') => app.copy_latest_assistant_message_as_markdown(),
             KeyCode::Up | KeyCode::Char('k') => {
-                app.decrement_vertical_scroll();
+                app.decrement_vertical_scroll()?;
             }
             KeyCode::Down | KeyCode::Char('j') => {
                 app.increment_vertical_scroll()?;
@@ -40,33 +52,67 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
                 app.set_app_mode(AppMode::Editing);
             }
             KeyCode::Char('n') => app.new_chat(),
+            KeyCode::Char('o') => {
+                app.new_chat();
+                app.set_app_mode(AppMode::Editing);
+            }
+            KeyCode::Char('R') => app.retry_last_message()?,
+            KeyCode::Char('v') => app.request_variations()?,
+            KeyCode::Char('a') => app.open_model_comparison(),
+            KeyCode::Char('I') => app.toggle_response_info(),
+            KeyCode::Char('C') => app.toggle_compact(),
+            KeyCode::Char('M') => app.toggle_mouse_capture(),
+            KeyCode::Char('S') => app.toggle_streaming(),
+            KeyCode::Char('c') => app.copy_last_snippet(),
+            KeyCode::Char('e') => app.edit_last_snippet(),
+            KeyCode::Char('t') => app.open_temperature_entry(),
+            KeyCode::Char('D') => app.open_dashboard()?,
+            KeyCode::Char('T') => app.set_app_mode(AppMode::TemplateSelection),
+            _ => {}
+        },
+        AppMode::VariationSelection => match code {
+            KeyCode::Esc | KeyCode::Char('q') => app.set_app_mode(AppMode::Normal),
+            KeyCode::Char('j') | KeyCode::Down => app.select_next_variation(),
+            KeyCode::Char('k') | KeyCode::Up => app.select_previous_variation(),
+            KeyCode::Char('d') => app.toggle_variation_diff(),
+            KeyCode::Enter => app
+                .choose_variation()
+                .await
+                .context("Unable to keep the chosen variation")?,
             _ => {}
         },
         AppMode::Editing => match code {
+            _ if app.input_keys == InputKeys::Vim
+                && app.vim.handle_key(&mut app.input_textarea, key_event) => {}
             // Exit editing mode on `ESC`
             KeyCode::Esc => app.set_app_mode(AppMode::Normal),
-            KeyCode::Char('V') | KeyCode::Char('v') => {
-                if modifiers.contains(KeyModifiers::CONTROL) {
-                    #[cfg(not(target_os = "linux"))]
-                    app.paste_to_input_textarea();
-                } else {
-                    app.input_textarea.input(key_event);
-                }
+            KeyCode::Char('V') | KeyCode::Char('v')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.paste_to_input_textarea();
             }
-            KeyCode::Char('s') | KeyCode::Char('S') => {
-                if modifiers.contains(KeyModifiers::CONTROL) {
-                    app.submit_message()
-                        .context("Handler failed to submit message")?;
-                } else {
-                    app.input_textarea.input(key_event);
-                }
+            KeyCode::Char('s') | KeyCode::Char('S')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.submit_message()
+                    .context("Handler failed to submit message")?;
             }
+            KeyCode::Char('t') | KeyCode::Char('T')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.set_app_mode(AppMode::TemplateSelection);
+            }
+            _ if app.input_keys == InputKeys::Emacs
+                && handle_emacs_keys(&mut app.input_textarea, key_event) => {}
             _ => {
                 app.input_textarea.input(key_event);
             }
         },
         AppMode::ShowHistory => match key_event.code {
             KeyCode::Esc | KeyCode::Char('q') => app.set_app_mode(AppMode::Normal),
+            KeyCode::Char('/') => app.set_app_mode(AppMode::HistoryFilter),
+            KeyCode::Char('f') => app.toggle_selected_chat_favorite()?,
+            KeyCode::Char('m') => app.merge_current_into_selected_chat()?,
             KeyCode::Char('h') | KeyCode::Left => app.select_no_chat(),
             KeyCode::Char('j') | KeyCode::Down => app.select_next_chat(),
             KeyCode::Char('k') | KeyCode::Up => app.select_previous_chat(),
@@ -93,54 +139,425 @@ pub fn handle_key_events(key_event: KeyEvent, app: &mut App) -> AppResult<()> {
             KeyCode::Char('G') | KeyCode::End => app.select_last_model(),
             KeyCode::Enter => {
                 app.set_model();
-                app.set_app_mode(AppMode::Editing);
+                if app.compare_pending {
+                    app.compare_with_selected_model();
+                } else {
+                    app.set_app_mode(AppMode::Editing);
+                }
+            }
+            KeyCode::Char('c') => app.open_custom_model_entry(),
+            KeyCode::Char('r') | KeyCode::Char('R')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.request_model_refresh()
             }
             _ => {}
         },
+        AppMode::TemperatureEntry => match code {
+            KeyCode::Esc => app.set_app_mode(AppMode::Normal),
+            KeyCode::Enter => app.set_temperature(),
+            _ => {
+                app.temperature_textarea.input(key_event);
+            }
+        },
+        AppMode::ChatSwitcher => match code {
+            KeyCode::Esc => app.set_app_mode(AppMode::Normal),
+            KeyCode::Down => app.select_next_switcher_chat(),
+            KeyCode::Up => app.select_previous_switcher_chat(),
+            KeyCode::Char('n') | KeyCode::Char('N')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.select_next_switcher_chat()
+            }
+            KeyCode::Char('p') | KeyCode::Char('P')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.select_previous_switcher_chat()
+            }
+            KeyCode::Enter => app.switch_to_selected_chat()?,
+            _ => {
+                app.chat_switcher_textarea.input(key_event);
+                app.update_chat_switcher();
+            }
+        },
+        AppMode::HistoryFilter => match code {
+            KeyCode::Esc => app.clear_history_filter()?,
+            KeyCode::Enter => app.set_app_mode(AppMode::ShowHistory),
+            _ => {
+                app.history_filter_textarea.input(key_event);
+                app.update_history_filter()?;
+            }
+        },
+        AppMode::TemplateSelection => match code {
+            KeyCode::Esc | KeyCode::Char('q') => app.set_app_mode(AppMode::Editing),
+            KeyCode::Char('j') | KeyCode::Down => app.select_next_template(),
+            KeyCode::Char('k') | KeyCode::Up => app.select_previous_template(),
+            KeyCode::Char('a') => app.open_template_name_entry(),
+            KeyCode::Char('d') => app.delete_selected_template(),
+            KeyCode::Enter => app.insert_template(),
+            _ => {}
+        },
+        AppMode::TemplateNameEntry => match code {
+            KeyCode::Esc => app.set_app_mode(AppMode::TemplateSelection),
+            KeyCode::Enter => app.save_template(),
+            _ => {
+                app.template_name_textarea.input(key_event);
+            }
+        },
+        AppMode::CustomModelEntry => match code {
+            KeyCode::Esc => app.set_app_mode(AppMode::ModelSelection),
+            KeyCode::Enter => app.set_custom_model(),
+            _ => {
+                app.custom_model_textarea.input(key_event);
+            }
+        },
         AppMode::SnippetSelection => match key_event.code {
             KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('s') => {
                 app.set_app_mode(AppMode::Normal)
             }
-            KeyCode::Char('h') | KeyCode::Left => app.select_no_snippet(),
+            KeyCode::Char('h') => app.select_no_snippet(),
+            KeyCode::Left => app.scroll_snippet_preview_left(),
+            KeyCode::Right => app.scroll_snippet_preview_right(),
+            KeyCode::Char('K') => app.scroll_snippet_preview_up(),
+            KeyCode::Char('J') => app.scroll_snippet_preview_down(),
             KeyCode::Char('j') | KeyCode::Down => app.select_next_snippet(),
             KeyCode::Char('k') | KeyCode::Up => app.select_previous_snippet(),
             KeyCode::Char('g') | KeyCode::Home => app.select_first_snippet(),
             KeyCode::Char('G') | KeyCode::End => app.select_last_snippet(),
-            #[cfg(not(target_os = "linux"))]
+            KeyCode::Char('E') => app.export_snippets(),
+            KeyCode::Char('p') => app.toggle_snippet_pin(),
+            KeyCode::Char('x') => app.print_snippet_on_exit(),
             KeyCode::Enter | KeyCode::Char('y') => {
-                app.copy_snippet()
-                    .context("Error when copying snippet to clipboard")?;
+                app.copy_snippet();
                 app.set_app_mode(AppMode::Normal);
             }
             _ => {}
         },
+        AppMode::SystemPromptEditing => match code {
+            KeyCode::Esc => app.set_app_mode(AppMode::Normal),
+            KeyCode::Char('s') | KeyCode::Char('S')
+                if modifiers.contains(KeyModifiers::CONTROL) =>
+            {
+                app.confirm_system_prompt();
+            }
+            _ => {
+                app.system_prompt_textarea.input(key_event);
+            }
+        },
+        AppMode::Dashboard => match code {
+            KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('D') => {
+                app.set_app_mode(AppMode::Normal)
+            }
+            _ => {}
+        },
         AppMode::Help => match key_event.code {
             KeyCode::Esc | KeyCode::Char('q') | KeyCode::Char('?') => {
                 app.set_app_mode(AppMode::Normal)
             }
+            KeyCode::Up | KeyCode::Char('k') => app.scroll_help_up(),
+            KeyCode::Down | KeyCode::Char('j') => app.scroll_help_down(),
             _ => {}
         },
     }
     Ok(())
 }
 
+/// Handles the emacs bindings that differ from the `tui-textarea` ones,
+/// returns whether the key was handled.
+fn handle_emacs_keys(textarea: &mut TextArea, key_event: KeyEvent) -> bool {
+    if key_event.modifiers != KeyModifiers::CONTROL {
+        return false;
+    }
+    match key_event.code {
+        KeyCode::Char('a') => textarea.move_cursor(CursorMove::Head),
+        KeyCode::Char('e') => textarea.move_cursor(CursorMove::End),
+        KeyCode::Char('k') => {
+            textarea.delete_line_by_end();
+        }
+        KeyCode::Char('u') => {
+            textarea.delete_line_by_head();
+        }
+        KeyCode::Char('w') => {
+            textarea.delete_word();
+        }
+        _ => return false,
+    }
+    true
+}
+
+/// Handles text pasted into the terminal.
+pub fn handle_paste_events(text: &str, app: &mut App) {
+    app.paste_text(text);
+}
+
+/// Holding `Alt` while dragging selects text across lines instead of a block.
+fn selection_mode(modifiers: KeyModifiers) -> SelectionMode {
+    if modifiers.contains(KeyModifiers::ALT) {
+        SelectionMode::Line
+    } else {
+        SelectionMode::Block
+    }
+}
+
 pub fn handle_mouse_events(event: MouseEvent, app: &mut App) {
     match event.kind {
         MouseEventKind::Down(_) => {
-            // Start selection
-            app.selection.start = Some((event.column, event.row));
-            app.selection.end = Some((event.column, event.row));
+            // Start a new selection, replacing the previous one
+            app.selection = Selection {
+                start: Some((event.column, event.row)),
+                end: Some((event.column, event.row)),
+                mode: selection_mode(event.modifiers),
+                ..Default::default()
+            };
         }
-        MouseEventKind::Drag(_) => {
+        MouseEventKind::Drag(_) if app.selection.is_dragging() => {
             // Update selection end point while dragging
-            if app.selection.start.is_some() {
-                app.selection.end = Some((event.column, event.row));
-            }
+            app.selection.end = Some((event.column, event.row));
+            app.selection.mode = selection_mode(event.modifiers);
         }
-        MouseEventKind::Up(_) => {
-            app.selection.start = None;
-            app.selection.end = None;
+        MouseEventKind::Up(_) if app.selection.is_dragging() => {
+            app.copy_selection();
         }
         _ => {}
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai::AiError;
+    use crate::app::Message;
+
+    #[test]
+    fn test_paste_inserts_into_active_textarea() {
+        let mut app = App::default();
+        app.set_app_mode(AppMode::Editing);
+        handle_paste_events("hello\r\nworld\x1b", &mut app);
+        assert_eq!(app.input_textarea.lines(), ["hello", "world"]);
+
+        app.set_app_mode(AppMode::Normal);
+        handle_paste_events("ignored", &mut app);
+        assert_eq!(app.input_textarea.lines(), ["hello", "world"]);
+    }
+
+    #[tokio::test]
+    async fn test_help_scroll_is_kept_when_reopened() {
+        let mut app = App::default();
+        for code in [
+            KeyCode::Char('?'),
+            KeyCode::Down,
+            KeyCode::Down,
+            KeyCode::Esc,
+        ] {
+            handle_key_events(KeyEvent::from(code), &mut app)
+                .await
+                .unwrap();
+        }
+        handle_key_events(KeyEvent::from(KeyCode::Char('?')), &mut app)
+            .await
+            .unwrap();
+        assert_eq!(app.help_scroll, 2);
+    }
+
+    #[tokio::test]
+    async fn test_emacs_keys_kill_text() {
+        let ctrl = |c| KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL);
+        let mut app = App {
+            input_keys: InputKeys::Emacs,
+            ..Default::default()
+        };
+        app.set_app_mode(AppMode::Editing);
+        app.input_textarea.insert_str("hello brave world");
+        for key in [ctrl('w'), ctrl('a'), ctrl('k')] {
+            handle_key_events(key, &mut app).await.unwrap();
+        }
+        assert_eq!(app.input_textarea.lines(), [""]);
+        app.input_textarea.insert_str("one two");
+        handle_key_events(ctrl('u'), &mut app).await.unwrap();
+        assert_eq!(app.input_textarea.lines(), [""]);
+    }
+
+    #[tokio::test]
+    async fn test_quit_while_streaming_needs_confirmation() {
+        let mut app = App::default();
+        let q = KeyEvent::from(KeyCode::Char('q'));
+        app.start_streaming();
+        handle_key_events(q, &mut app).await.unwrap();
+        assert!(app.running);
+        handle_key_events(q, &mut app).await.unwrap();
+        assert!(!app.running);
+    }
+
+    #[tokio::test]
+    async fn test_retry_only_retryable_errors() {
+        let r = KeyEvent::from(KeyCode::Char('R'));
+        for (error, is_retried) in [
+            (AiError::Network(String::new()), true),
+            (AiError::Auth(String::new()), false),
+        ] {
+            let mut app = App {
+                read_only: true,
+                ..Default::default()
+            };
+            app.messages.push(Message::User("hello".to_string()));
+            app.receive_response(Err(error)).await.unwrap();
+            handle_key_events(r, &mut app).await.unwrap();
+            assert_eq!(app.has_unprocessed_messages, is_retried);
+            assert_eq!(
+                matches!(app.messages.last(), Some(Message::User(_))),
+                is_retried
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_shell_snippet_is_printed_after_confirmation() {
+        use crate::snippets::SnippetItem;
+
+        let x = KeyEvent::from(KeyCode::Char('x'));
+        let mut app = App {
+            shell_snippets_enabled: true,
+            ..Default::default()
+        };
+        app.snippet_list
+            .items
+            .push(SnippetItem::new("ls -la\n", false).with_language(Some("bash".to_string())));
+        app.snippet_list.state.select_first();
+        app.set_app_mode(AppMode::SnippetSelection);
+        handle_key_events(x, &mut app).await.unwrap();
+        assert!(app.running);
+        assert_eq!(app.exit_command, None);
+        handle_key_events(x, &mut app).await.unwrap();
+        assert!(!app.running);
+        assert_eq!(app.exit_command.as_deref(), Some("ls -la"));
+    }
+
+    #[tokio::test]
+    async fn test_switching_models_mid_conversation_adds_a_note() {
+        let enter = KeyEvent::from(KeyCode::Enter);
+        let mut app = App {
+            read_only: true,
+            ..Default::default()
+        };
+        app.messages.push(Message::User("hi".to_string()));
+        for i in [0, 1] {
+            app.set_app_mode(AppMode::ModelSelection);
+            app.model_list.state.select(Some(i));
+            handle_key_events(enter, &mut app).await.unwrap();
+        }
+        // Switching again before the next message replaces the note
+        let note = format!("Switched to {}", app.model_list.items[1].name);
+        assert_eq!(
+            app.messages,
+            vec![Message::User("hi".to_string()), Message::Note(note)]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_another_model_answers_the_last_message_again() {
+        let mut app = App {
+            read_only: true,
+            ..Default::default()
+        };
+        app.messages.push(Message::User("hi".to_string()));
+        app.messages
+            .push(Message::Assistant("hello".to_string(), Default::default()));
+        handle_key_events(KeyEvent::from(KeyCode::Char('a')), &mut app)
+            .await
+            .unwrap();
+        assert!(matches!(app.app_mode, AppMode::ModelSelection));
+        app.model_list.state.select(Some(1));
+        handle_key_events(KeyEvent::from(KeyCode::Enter), &mut app)
+            .await
+            .unwrap();
+        assert!(app.has_unprocessed_messages);
+        let note = format!(
+            "Answer of {} to the same message",
+            app.model_list.items[1].name
+        );
+        assert_eq!(app.messages.last(), Some(&Message::Note(note)));
+        assert_eq!(app.request_messages().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_last_snippet_is_the_last_block_of_the_newest_answer() {
+        let block = |text: &str| format!("```\n{}\n```", text);
+        let mut app = App::default();
+        for text in [block("A"), format!("{}\n{}", block("B"), block("A"))] {
+            app.messages
+                .push(Message::Assistant(text, Default::default()));
+        }
+        assert_eq!(app.last_snippet().unwrap().text, "A");
+    }
+
+    #[tokio::test]
+    async fn test_dismissed_variations_keep_the_answer() {
+        let mut app = App::default();
+        let messages = vec![
+            Message::User("hi".to_string()),
+            Message::Assistant("hello".to_string(), Default::default()),
+        ];
+        app.messages = messages.clone();
+        handle_key_events(KeyEvent::from(KeyCode::Char('v')), &mut app)
+            .await
+            .unwrap();
+        app.receive_variations_response(Ok(vec![Message::Assistant(
+            "hey".to_string(),
+            Default::default(),
+        )]));
+        assert!(matches!(app.app_mode, AppMode::VariationSelection));
+        handle_key_events(KeyEvent::from(KeyCode::Esc), &mut app)
+            .await
+            .unwrap();
+        assert_eq!(app.messages, messages);
+    }
+
+    #[tokio::test]
+    async fn test_submit_after_failed_request() {
+        let mut app = App {
+            read_only: true,
+            ..Default::default()
+        };
+        app.messages.push(Message::User("hi".to_string()));
+        app.awaiting_response = true;
+        app.receive_response(Err(AiError::Auth(String::new())))
+            .await
+            .unwrap();
+        assert!(!app.can_retry());
+
+        app.set_app_mode(AppMode::Editing);
+        app.input_textarea.insert_str("again");
+        app.submit_message().unwrap();
+        assert_eq!(
+            app.messages.last(),
+            Some(&Message::User("again".to_string()))
+        );
+        assert!(app.has_unprocessed_messages);
+    }
+
+    #[tokio::test]
+    async fn test_submit_during_comparison_is_rejected() {
+        let mut app = App {
+            read_only: true,
+            ..Default::default()
+        };
+        app.messages.push(Message::User("hi".to_string()));
+        app.messages
+            .push(Message::Assistant("hello".to_string(), Default::default()));
+        app.set_app_mode(AppMode::ModelSelection);
+        app.compare_pending = true;
+        app.model_list.state.select(Some(1));
+        handle_key_events(KeyEvent::from(KeyCode::Enter), &mut app)
+            .await
+            .unwrap();
+        // The request of the comparison is sent
+        app.has_unprocessed_messages = false;
+        app.awaiting_response = true;
+
+        app.set_app_mode(AppMode::Editing);
+        app.input_textarea.insert_str("next");
+        app.submit_message().unwrap();
+        assert_eq!(app.messages.len(), 3);
+        assert_eq!(app.input_textarea.lines(), ["next"]);
+    }
+}
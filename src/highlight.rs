@@ -0,0 +1,175 @@
+use std::{io::Cursor, sync::OnceLock};
+
+use anyhow::bail;
+use ratatui::{
+    style::{Color, Modifier, Style},
+    text::{Line, Span},
+};
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{FontStyle, Theme, ThemeSet},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::app::AppResult;
+
+/// Theme used when no theme is configured.
+pub const DEFAULT_THEME: &str = "catppuccin-mocha";
+
+/// Themes embedded in addition to the themes bundled with `syntect`.
+const EMBEDDED_THEMES: [(&str, &str); 4] = [
+    (
+        "catppuccin-latte",
+        include_str!("themes/catppuccin-latte.tmTheme"),
+    ),
+    (
+        "catppuccin-frappe",
+        include_str!("themes/catppuccin-frappe.tmTheme"),
+    ),
+    (
+        "catppuccin-macchiato",
+        include_str!("themes/catppuccin-macchiato.tmTheme"),
+    ),
+    (
+        "catppuccin-mocha",
+        include_str!("themes/catppuccin-mocha.tmTheme"),
+    ),
+];
+
+fn syntax_set() -> &'static SyntaxSet {
+    static SYNTAX_SET: OnceLock<SyntaxSet> = OnceLock::new();
+    SYNTAX_SET.get_or_init(SyntaxSet::load_defaults_newlines)
+}
+
+fn theme_set() -> &'static ThemeSet {
+    static THEME_SET: OnceLock<ThemeSet> = OnceLock::new();
+    THEME_SET.get_or_init(|| {
+        let mut theme_set = ThemeSet::load_defaults();
+        for (name, content) in EMBEDDED_THEMES {
+            let theme = ThemeSet::load_from_reader(&mut Cursor::new(content))
+                .expect("embedded themes are valid");
+            theme_set.themes.insert(name.to_string(), theme);
+        }
+        theme_set
+    })
+}
+
+/// Names of the embedded code highlighting themes.
+pub fn list_themes() -> Vec<&'static str> {
+    theme_set().themes.keys().map(String::as_str).collect()
+}
+
+/// Loads an embedded code highlighting theme by name.
+pub fn load_theme(name: &str) -> AppResult<Theme> {
+    match theme_set().themes.get(name) {
+        Some(theme) => Ok(theme.clone()),
+        None => bail!(
+            "Unknown theme `{}`, available themes are: {}",
+            name,
+            list_themes().join(", ")
+        ),
+    }
+}
+
+/// Highlights a code snippet, `language` is the language given after the
+/// opening fence of the code block. Unknown languages are not highlighted.
+pub fn create_highlighted_code(
+    code: &str,
+    language: Option<&str>,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let syntax_set = syntax_set();
+    let syntax = language
+        .and_then(|l| syntax_set.find_syntax_by_token(l))
+        .unwrap_or_else(|| syntax_set.find_syntax_plain_text());
+    let mut highlighter = HighlightLines::new(syntax, theme);
+    LinesWithEndings::from(code)
+        .map(|line| match highlighter.highlight_line(line, syntax_set) {
+            Ok(ranges) => Line::from(
+                ranges
+                    .into_iter()
+                    .map(|(style, text)| {
+                        Span::styled(text.trim_end_matches('\n').to_string(), to_style(style))
+                    })
+                    .collect::<Vec<_>>(),
+            ),
+            Err(_) => Line::from(line.trim_end_matches('\n').to_string()),
+        })
+        .collect()
+}
+
+/// Code highlighted by `--theme-preview`.
+const PREVIEW_SAMPLE: &str = r#"use std::collections::HashMap;
+
+/// Counts the words of a text.
+fn count_words(text: &str) -> HashMap<&str, usize> {
+    let mut counts = HashMap::new();
+    for word in text.split_whitespace() {
+        *counts.entry(word).or_insert(0) += 1;
+    }
+    counts
+}
+
+fn main() {
+    let counts = count_words("the quick brown fox jumps over the lazy dog");
+    println!("{} distinct words, 'the' appears {} times", counts.len(), counts["the"]);
+}"#;
+
+/// Renders a highlighted code sample with ANSI escape sequences for previewing
+/// a theme outside of the terminal user interface.
+pub fn preview_theme(theme: &Theme) -> String {
+    let mut preview = String::new();
+    for line in create_highlighted_code(PREVIEW_SAMPLE, Some("rust"), theme) {
+        for span in line.spans {
+            let mut codes = Vec::new();
+            if let Some(Color::Rgb(r, g, b)) = span.style.fg {
+                codes.push(format!("38;2;{};{};{}", r, g, b));
+            }
+            if span.style.add_modifier.contains(Modifier::BOLD) {
+                codes.push("1".to_string());
+            }
+            if span.style.add_modifier.contains(Modifier::ITALIC) {
+                codes.push("3".to_string());
+            }
+            if span.style.add_modifier.contains(Modifier::UNDERLINED) {
+                codes.push("4".to_string());
+            }
+            preview.push_str(&format!("\x1b[{}m{}\x1b[0m", codes.join(";"), span.content));
+        }
+        preview.push('\n');
+    }
+    preview
+}
+
+fn to_style(style: syntect::highlighting::Style) -> Style {
+    let fg = style.foreground;
+    let mut modifier = Modifier::empty();
+    if style.font_style.contains(FontStyle::BOLD) {
+        modifier |= Modifier::BOLD;
+    }
+    if style.font_style.contains(FontStyle::ITALIC) {
+        modifier |= Modifier::ITALIC;
+    }
+    if style.font_style.contains(FontStyle::UNDERLINE) {
+        modifier |= Modifier::UNDERLINED;
+    }
+    Style::default()
+        .fg(Color::Rgb(fg.r, fg.g, fg.b))
+        .add_modifier(modifier)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_theme() {
+        for (name, _) in EMBEDDED_THEMES {
+            assert!(load_theme(name).is_ok());
+        }
+        assert!(list_themes().contains(&DEFAULT_THEME));
+        assert!(load_theme(DEFAULT_THEME).is_ok());
+        assert!(load_theme("no-such-theme").is_err());
+    }
+}
@@ -9,6 +9,9 @@ pub mod event;
 /// Widget renderer.
 pub mod ui;
 
+/// User interface styles.
+pub mod theme;
+
 /// Terminal user interface.
 pub mod tui;
 
@@ -24,11 +27,57 @@ pub mod models;
 /// Snippets finder.
 pub mod snippets;
 
+/// Code syntax highlighting.
+pub mod highlight;
+
 /// Command line interface.
 pub mod cli;
 
+/// User configuration.
+pub mod config;
+
 ///Chat conversations storage.
 pub mod storage;
 
 /// Chat list.
 pub mod chats;
+
+/// Fuzzy text matching.
+pub mod fuzzy;
+
+/// Conversation export.
+pub mod export;
+
+/// Response latency benchmark.
+pub mod bench;
+
+/// Usage statistics.
+pub mod stats;
+
+/// Read-only JSON API over a unix socket.
+#[cfg(all(feature = "serve", unix))]
+pub mod serve;
+
+/// Message templates.
+pub mod templates;
+
+/// Response variations.
+pub mod variations;
+
+/// Word diffs between responses.
+pub mod diff;
+
+/// System clipboard.
+pub mod clipboard;
+
+/// Mouse text selection.
+pub mod selection;
+
+/// Response notifications.
+pub mod notification;
+
+/// External editor.
+pub mod editor;
+
+/// Vim key bindings of the input area.
+pub mod vim;
@@ -1,97 +1,492 @@
+use std::env;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::time::Duration;
+
 use anyhow::Context;
 use clap::Parser;
-use ratatui::backend::CrosstermBackend;
+use ratatui::backend::{Backend, CrosstermBackend};
 use ratatui::Terminal;
 use tokio::sync::mpsc;
 use tokio::task;
 
-use ait::ai::{assistant_response, get_models};
-use ait::app::{App, AppResult};
-use ait::cli::Cli;
+use ait::ai::{
+    assistant_response, assistant_response_streaming, assistant_response_variations,
+    coalesce_stream_events, compile_prefix_patterns, default_fallback_models, dry_run_request,
+    get_models, parse_fallback_models, set_api_keys, set_rate_limit, trim_response_prefixes,
+    AiError, ChunkCoalescing, DEFAULT_MODEL, FALLBACK_MODEL,
+};
+use ait::app::{App, AppMode, AppResult, Message};
+use ait::bench::run_bench;
+use ait::cli::{describe_context, read_context, system_prompt_with_context, Cli, Command};
+use ait::config::{
+    load_api_keys, load_config, load_last_model, load_templates, project_name, Config,
+};
+use ait::editor::{open_in_editor, write_snippet_file};
 use ait::event::{Event, EventHandler};
-use ait::handler::{handle_key_events, handle_mouse_events};
+use ait::export::{import_markdown, run_export};
+use ait::handler::{handle_key_events, handle_mouse_events, handle_paste_events};
+use ait::highlight::{list_themes, load_theme, preview_theme, DEFAULT_THEME};
+use ait::notification::notify_response_complete;
+use ait::snippets::SnippetItem;
+use ait::stats::run_stats;
 use ait::storage::create_db;
+use ait::theme::Theme;
 use ait::tui::Tui;
 
-#[tokio::main]
-async fn main() -> AppResult<()> {
+/// Opens a snippet in the external editor, handing the terminal over to the
+/// editor until it exits. Returns the file the snippet was written to.
+async fn edit_snippet<B: Backend>(tui: &mut Tui<B>, snippet: &SnippetItem) -> AppResult<PathBuf> {
+    let path = write_snippet_file(snippet)?;
+    tui.suspend()
+        .await
+        .context("Failed to suspend the user interface")?;
+    // The editor blocks until it exits, so it runs off the runtime's threads
+    let editor_path = path.clone();
+    let edited = task::spawn_blocking(move || open_in_editor(&editor_path))
+        .await
+        .context("Failed to wait for the editor")
+        .and_then(|edited| edited);
+    tui.resume()
+        .context("Failed to resume the user interface")?;
+    edited.map(|()| path)
+}
+
+/// Describes the build, for bug reports.
+fn build_info() -> String {
+    let mut features = Vec::new();
+    if cfg!(feature = "notify") {
+        features.push("notify");
+    }
+    if cfg!(feature = "serve") {
+        features.push("serve");
+    }
+    serde_json::json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "git_sha": env!("AIT_GIT_SHA"),
+        "genai_version": env!("AIT_GENAI_VERSION"),
+        "target": env!("AIT_TARGET"),
+        "features": features,
+    })
+    .to_string()
+}
+
+/// Exit status of `--prompt` when no response could be generated.
+fn exit_code(error: &AiError) -> i32 {
+    match error {
+        AiError::Auth(_) => 2,
+        AiError::RateLimit(_) => 3,
+        AiError::Network(_) | AiError::Timeout(_) => 4,
+        AiError::Unsupported(_) | AiError::Other(_) => 1,
+    }
+}
+
+fn main() -> AppResult<()> {
     let cli = Cli::parse();
-    let temperature = cli.temperature;
+    if cli.build_info {
+        println!("{}", build_info());
+        return Ok(());
+    }
+    let config = match load_config() {
+        Ok(config) => config,
+        // Subcommands only use the rate limit of the config
+        Err(e) if cli.command.is_some() => {
+            eprintln!("Warning: ignoring the config: {:#}", e);
+            Config::default()
+        }
+        Err(e) => return Err(e.context("Failed to load config")),
+    };
+    // The keys are set in the environment before the threads of the runtime
+    // exist, as they could read it at the same time
+    let key_warnings = match &cli.command {
+        Some(_) => Vec::new(),
+        None => {
+            let (api_keys, mut warnings) =
+                load_api_keys(&config).context("Failed to load API keys")?;
+            warnings.extend(set_api_keys(&api_keys));
+            warnings
+        }
+    };
+    for warning in &key_warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    tokio::runtime::Runtime::new()
+        .context("Failed to start the async runtime")?
+        .block_on(run(cli, config, key_warnings))
+}
+
+/// Runs the subcommand, the one-shot prompt or the app, with the warnings
+/// about the API keys of the config.
+async fn run(cli: Cli, config: Config, key_warnings: Vec<String>) -> AppResult<()> {
+    let history_limit = cli
+        .history_limit
+        .or(config.history_limit.map(NonZeroUsize::get));
+    // The model of the last session, or the model of the config until then
+    let last_model = load_last_model().or_else(|| config.model.clone());
+
+    let corrupt_db = create_db().context("Failed to create database")?;
+    if let Some(backup_path) = &corrupt_db {
+        eprintln!(
+            "The chat history database was corrupt and has been replaced, the old file was moved to {}",
+            backup_path.display()
+        );
+    }
 
-    create_db().context("Failed to create database")?;
+    // Also limits the requests of the subcommands
+    set_rate_limit(config.requests_per_minute);
+
+    match &cli.command {
+        Some(Command::Export(args)) => {
+            return run_export(args).context("Failed to export conversation");
+        }
+        Some(Command::Bench(args)) => {
+            return run_bench(args, &cli.system_prompt, cli.temperature)
+                .await
+                .context("Failed to run benchmark");
+        }
+        #[cfg(all(feature = "serve", unix))]
+        Some(Command::Serve(args)) => {
+            return ait::serve::run_serve(args, &cli.system_prompt, cli.temperature)
+                .await
+                .context("Failed to serve");
+        }
+        Some(Command::Stats) => {
+            return run_stats().context("Failed to show statistics");
+        }
+        Some(Command::Themes) => {
+            for theme in list_themes() {
+                println!("{}", theme);
+            }
+            return Ok(());
+        }
+        None => {}
+    }
+
+    let response_prefix_patterns =
+        compile_prefix_patterns(&config.trim_response_prefixes).context("Failed to load config")?;
+    let fallback_models = match &config.fallback_models {
+        Some(entries) => parse_fallback_models(entries).context("Failed to load config")?,
+        None => default_fallback_models(),
+    };
+    let context = cli.context.as_deref().map(read_context).transpose()?;
+    let mut system_prompt = cli.system_prompt.clone();
+    let mut appended_context = None;
+    let mut context_label = None;
+    match (context, &cli.context) {
+        (Some(context), _) if cli.append_context => appended_context = Some(context),
+        (Some(context), Some(path)) => {
+            context_label = Some(describe_context(path, &context));
+            system_prompt = system_prompt_with_context(&system_prompt, &context);
+        }
+        _ => {}
+    }
+    if let Some(prompt) = &cli.prompt {
+        let mut messages = cli.messages.clone();
+        let prompt = match &appended_context {
+            Some(context) => format!("{}\n\n{}", context, prompt),
+            None => prompt.clone(),
+        };
+        messages.push(Message::User(prompt));
+        let model = last_model.as_deref().unwrap_or(DEFAULT_MODEL);
+        if cli.dry_run {
+            println!(
+                "{}",
+                dry_run_request(
+                    &messages,
+                    model,
+                    &system_prompt,
+                    &cli.temperature,
+                    history_limit
+                )?
+            );
+            return Ok(());
+        }
+        match assistant_response(
+            &messages,
+            model,
+            &system_prompt,
+            &cli.temperature,
+            history_limit,
+        )
+        .await
+        {
+            Ok(response) => {
+                println!(
+                    "{}",
+                    trim_response_prefixes(response.as_str(), &response_prefix_patterns)
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                std::process::exit(exit_code(&e));
+            }
+        }
+    }
+    let theme_name = cli
+        .theme
+        .as_deref()
+        .or(config.theme.as_deref())
+        .unwrap_or(DEFAULT_THEME);
+    let code_theme = load_theme(theme_name).context("Failed to load theme")?;
+    if cli.theme_preview {
+        println!("{}:\n", theme_name);
+        print!("{}", preview_theme(&code_theme));
+        return Ok(());
+    }
+
+    let imported = cli.import.as_deref().map(import_markdown).transpose()?;
 
     // Create an application.
-    let mut app = App::new(&cli.system_prompt);
-    let models = get_models()
+    let mut app = App::new(&system_prompt);
+    app.streaming_enabled = config.streaming;
+    app.quit_key = config.quit_key;
+    app.input_keys = config.input_keys;
+    app.wrap_indent = config.wrap_indent;
+    app.history_preview_messages = config.history_preview_messages;
+    app.model_switch_notes = config.model_switch_notes;
+    app.newest_first = config.newest_first;
+    app.response_prefix_patterns = response_prefix_patterns;
+    app.temperature = cli.temperature;
+    app.read_only = cli.read_only;
+    app.project = project_name();
+    app.plain = cli.plain;
+    app.context_label = context_label;
+    app.shell_snippets_enabled = config.shell_snippets;
+    app.template_list = load_templates().context("Failed to load templates")?.into();
+    app.code_theme = code_theme;
+    let mut theme = if cli.high_contrast {
+        Theme::high_contrast()
+    } else {
+        Theme::default()
+    };
+    if let Some(color) = &config.selection_color {
+        let color = color
+            .parse()
+            .with_context(|| format!("Invalid selection color `{}`", color))?;
+        theme = theme.with_selection_color(color);
+    }
+    // https://no-color.org: any non-empty value disables colors
+    if env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        theme = theme.without_color();
+    }
+    app.set_theme(theme);
+    app.scrollback_limit = cli.scrollback.map(|n| n as usize);
+    app.history_limit = history_limit;
+    let (models, warnings) = get_models(&fallback_models)
         .await
         .context("Failed to find models from providers")?;
     app.set_models(models);
+    if let Some(model) = &last_model {
+        if !app.select_model_by_name(model) {
+            app.select_model_by_name(FALLBACK_MODEL);
+        }
+    }
+    app.set_model_warnings(warnings);
+    if let Some(warning) = key_warnings.first() {
+        app.set_error_status(warning.clone());
+    }
     app.set_chat_list()?;
+    if let Some(backup_path) = &corrupt_db {
+        app.set_error_status(format!(
+            "The chat history was corrupt and has been reset, the old file was moved to {}",
+            backup_path.display()
+        ));
+    }
+    app.seed_messages(cli.messages.clone())
+        .context("Failed to seed conversation")?;
+    if let Some(conversation) = imported {
+        if let Some(system_prompt) = conversation.system_prompt {
+            app.system_prompt = system_prompt;
+        }
+        app.seed_messages(conversation.messages)
+            .context("Failed to save the imported conversation")?;
+    }
+    // The context is left in the input, to be edited and sent as a message
+    if let Some(context) = appended_context {
+        app.input_textarea.insert_str(context + "\n\n");
+        app.set_app_mode(AppMode::Editing);
+    }
 
     // Initialize the terminal user interface.
     let backend = CrosstermBackend::new(std::io::stderr());
     let terminal = Terminal::new(backend).context("Failed to create terminal")?;
     let events = EventHandler::new(250);
-    let mut tui = Tui::new(terminal, events);
+    let mut tui = Tui::new(terminal, events).with_alternate_screen(!cli.no_alt_screen);
     tui.init().context("Failed to initialize terminal")?;
 
     // Create a channel to receive the assistant responses
     let (assistant_response_tx, mut assistant_response_rx) = mpsc::channel(32);
+    // Create a channel to receive the chunks of streamed responses
+    let (incomplete_tx, mut incomplete_rx) = mpsc::channel(1024);
+    // Create a channel to receive response variations
+    let (variations_tx, mut variations_rx) = mpsc::channel(1);
+    let n_variations = cli.variations as usize;
+    let chunk_coalescing = ChunkCoalescing {
+        interval: Duration::from_millis(config.stream_flush_ms),
+        max_len: config.stream_flush_bytes,
+    };
+    // Create a channel to receive refreshed models
+    let (models_tx, mut models_rx) = mpsc::channel(1);
 
-    // Start the main loop.
-    while app.running {
-        // Render the user interface.
-        tui.draw(&mut app)
-            .context("Failed to render user interface")?;
-        // Handle events.
-        match tui
-            .events
-            .next()
-            .await
-            .context("Unable to get next event")?
-        {
-            Event::Tick => app.tick(),
-            Event::Key(key_event) => {
-                handle_key_events(key_event, &mut app).context("Error handling key events")?
+    // Start the main loop. An error ends it, and is only reported once the
+    // terminal is restored, so that it is visible.
+    let result: AppResult<()> = async {
+        while app.running {
+            // Check for a new query and spawn a task to handle it
+            if app.has_unprocessed_messages {
+                app.has_unprocessed_messages = false;
+                app.awaiting_response = true;
+                let assistant_response_tx = assistant_response_tx.clone();
+                let messages = app
+                    .request_messages()
+                    .context("Failed to load conversation history")?;
+                let selected_model_name = app.selected_model_name.clone(); // This clone is necessary for the async task
+                let system_prompt = app.system_prompt.clone(); // This clone is necessary for the async task
+                let temperature = app.temperature;
+                if app.should_stream() {
+                    let incomplete_tx = incomplete_tx.clone();
+                    task::spawn(async move {
+                        // Chunks are merged on their way to the app, the complete
+                        // response is only sent once all of them are passed on
+                        let (chunk_tx, chunk_rx) = mpsc::channel(1024);
+                        let (assistant_response, _) = tokio::join!(
+                            assistant_response_streaming(
+                                &messages,
+                                &selected_model_name,
+                                &system_prompt,
+                                &temperature,
+                                history_limit,
+                                chunk_tx,
+                            ),
+                            coalesce_stream_events(chunk_rx, incomplete_tx, chunk_coalescing)
+                        );
+                        let _ = assistant_response_tx.send(assistant_response).await;
+                    });
+                } else {
+                    task::spawn(async move {
+                        let assistant_response = assistant_response(
+                            &messages,
+                            &selected_model_name,
+                            &system_prompt,
+                            &temperature,
+                            history_limit,
+                        )
+                        .await;
+                        let _ = assistant_response_tx.send(assistant_response).await;
+                    });
+                }
             }
-            Event::Mouse(mouse_event) => {
-                handle_mouse_events(mouse_event, &mut app);
+
+            // Check for a variations request and spawn a task to handle it
+            if app.has_unprocessed_variations {
+                app.has_unprocessed_variations = false;
+                app.awaiting_response = true;
+                let variations_tx = variations_tx.clone();
+                let messages = app
+                    .request_messages()
+                    .context("Failed to load conversation history")?;
+                let selected_model_name = app.selected_model_name.clone(); // This clone is necessary for the async task
+                let system_prompt = app.system_prompt.clone(); // This clone is necessary for the async task
+                let temperature = app.temperature;
+                task::spawn(async move {
+                    let variations = assistant_response_variations(
+                        &messages,
+                        &selected_model_name,
+                        &system_prompt,
+                        &temperature,
+                        history_limit,
+                        n_variations,
+                    )
+                    .await;
+                    let _ = variations_tx.send(variations).await;
+                });
             }
-            Event::Resize(_, _) => {}
-        }
 
-        // Check for a new query and spawn a task to handle it
-        if app.has_unprocessed_messages {
-            app.has_unprocessed_messages = false;
-            let assistant_response_tx = assistant_response_tx.clone();
-            let messages = app.messages.clone(); // This clone is necessary for the async task
-            let selected_model_name = app.selected_model_name.clone(); // This clone is necessary for the async task
-            let system_prompt = cli.system_prompt.clone(); // This clone is necessary for the async task
-            task::spawn(async move {
-                let assistant_response = assistant_response(
-                    &messages,
-                    &selected_model_name,
-                    &system_prompt,
-                    &temperature,
-                )
-                .await;
-                let _ = assistant_response_tx.send(assistant_response).await;
-            });
-        }
+            // Check for a model refresh request and spawn a task to handle it
+            if app.has_unprocessed_model_refresh {
+                app.has_unprocessed_model_refresh = false;
+                let models_tx = models_tx.clone();
+                let fallback_models = fallback_models.clone();
+                task::spawn(async move {
+                    let _ = models_tx.send(get_models(&fallback_models).await).await;
+                });
+            }
 
-        // Check for a response from the assistant and process it
-        if let Ok(assistant_response) = assistant_response_rx.try_recv() {
-            match assistant_response {
-                Ok(response) => app
-                    .receive_message(response)
-                    .await
-                    .context("Error while receiving message")?,
-                Err(e) => eprintln!("Error receiving assistant response: {}", e),
+            if let Some(snippet) = app.snippet_to_edit.take() {
+                match edit_snippet(&mut tui, &snippet).await {
+                    Ok(path) => {
+                        app.set_status(format!("Edited the code block in {}", path.display()))
+                    }
+                    Err(e) => app.set_error_status(format!("{:#}", e)),
+                }
+            }
+
+            tui.set_mouse_capture(app.mouse_capture)?;
+
+            // Render the user interface.
+            tui.draw(&mut app)
+                .context("Failed to render user interface")?;
+
+            // Wait for an event or a result of one of the tasks. The chunks of a
+            // streamed response are checked before the complete response, which
+            // is only sent after the last chunk.
+            tokio::select! {
+                biased;
+                event = tui.events.next() => {
+                    // The terminal input is gone when there are no more events
+                    let Some(event) = event else {
+                        break;
+                    };
+                    match event {
+                        Event::Tick => app.tick(),
+                        Event::Key(key_event) => handle_key_events(key_event, &mut app)
+                            .await
+                            .context("Error handling key events")?,
+                        Event::Mouse(mouse_event) => {
+                            handle_mouse_events(mouse_event, &mut app);
+                        }
+                        Event::Resize(_, _) => app.handle_resize(),
+                        Event::FocusGained => app.has_focus = true,
+                        Event::FocusLost => app.has_focus = false,
+                        Event::Paste(text) => handle_paste_events(&text, &mut app),
+                    }
+                }
+                // Display the chunks of a streamed response received so far
+                Some(stream_event) = incomplete_rx.recv() => {
+                    app.receive_stream_event(stream_event);
+                    while let Ok(stream_event) = incomplete_rx.try_recv() {
+                        app.receive_stream_event(stream_event);
+                    }
+                }
+                // Let the user choose one of the response variations
+                Some(variations) = variations_rx.recv() => app.receive_variations_response(variations),
+                Some(models) = models_rx.recv() => match models {
+                    Ok((models, warnings)) => app.receive_models(models, warnings),
+                    Err(e) => app.set_error_status(format!("Error refreshing models: {}", e)),
+                },
+                Some(assistant_response) = assistant_response_rx.recv() => {
+                    app.finish_streaming();
+                    app.receive_response(assistant_response)
+                        .await
+                        .context("Error while receiving message")?;
+                    if !app.has_focus {
+                        notify_response_complete(cli.notify, &app.selected_model_name);
+                    }
+                }
             }
         }
+        Ok(())
     }
+    .await;
 
     // Exit the user interface.
     tui.exit().context("Failed during application shutdown")?;
+    result?;
+    app.save_selected_model()
+        .context("Failed to remember the selected model")?;
+    if let Some(command) = &app.exit_command {
+        println!("{}", command);
+    }
     Ok(())
 }
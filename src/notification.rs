@@ -0,0 +1,35 @@
+use std::io::{self, Write};
+
+use crate::cli::Notification;
+
+/// Notifies the user that a response from `model` has completed.
+///
+/// Notification failures are ignored, they should never interrupt the chat.
+pub fn notify_response_complete(notification: Notification, model: &str) {
+    match notification {
+        Notification::Off => {}
+        Notification::Bell => ring_bell(),
+        Notification::Desktop => desktop_notification(model),
+    }
+}
+
+fn ring_bell() {
+    // The terminal user interface is drawn on stderr.
+    let mut stderr = io::stderr();
+    let _ = stderr.write_all(b"\x07");
+    let _ = stderr.flush();
+}
+
+#[cfg(feature = "notify")]
+fn desktop_notification(model: &str) {
+    let _ = notify_rust::Notification::new()
+        .summary("AI in the Terminal")
+        .body(&format!("{} has finished responding.", model))
+        .show();
+}
+
+/// Desktop notifications require the `notify` feature, ring the bell instead.
+#[cfg(not(feature = "notify"))]
+fn desktop_notification(_model: &str) {
+    ring_bell();
+}
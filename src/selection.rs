@@ -0,0 +1,163 @@
+use ratatui::{buffer::Buffer, layout::Rect};
+
+/// How the cells between the start and the end of a selection are selected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SelectionMode {
+    /// The rectangle of cells with the start and end in opposite corners
+    #[default]
+    Block,
+    /// The cells in reading order from the start to the end, following text
+    /// across lines
+    Line,
+}
+
+/// Text selected with the mouse between the cell where dragging started and
+/// the cell where it ended.
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    pub start: Option<(u16, u16)>, // (column, row)
+    pub end: Option<(u16, u16)>,
+    pub mode: SelectionMode,
+    /// Text in the selection when it was last rendered
+    pub text: Option<String>,
+    /// The mouse button was released, the selection stays highlighted
+    pub is_finished: bool,
+}
+
+/// Inclusive bounds of a selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Bounds {
+    start_col: u16,
+    end_col: u16,
+    start_row: u16,
+    end_row: u16,
+}
+
+impl Selection {
+    /// Is the selection being extended with the mouse?
+    pub fn is_dragging(&self) -> bool {
+        self.start.is_some() && !self.is_finished
+    }
+
+    /// Bounds of the selection, regardless of the direction it was made in.
+    fn bounds(&self) -> Option<Bounds> {
+        // Need both start and end points to make a selection
+        let (start, end) = self.start.zip(self.end)?;
+        Some(Bounds {
+            start_col: start.0.min(end.0),
+            end_col: start.0.max(end.0),
+            start_row: start.1.min(end.1),
+            end_row: start.1.max(end.1),
+        })
+    }
+
+    /// Columns selected in `row`, `area` is the area of the screen.
+    fn selected_columns(&self, bounds: Bounds, row: u16, area: Rect) -> (u16, u16) {
+        match self.mode {
+            SelectionMode::Block => (bounds.start_col, bounds.end_col),
+            SelectionMode::Line => {
+                // The start and end in reading order
+                let (first, last) = match (self.start, self.end) {
+                    (Some(start), Some(end)) if (start.1, start.0) <= (end.1, end.0) => {
+                        (start, end)
+                    }
+                    (Some(start), Some(end)) => (end, start),
+                    _ => return (bounds.start_col, bounds.end_col),
+                };
+                let from = if row == first.1 { first.0 } else { area.left() };
+                let to = if row == last.1 {
+                    last.0
+                } else {
+                    area.right().saturating_sub(1)
+                };
+                (from, to)
+            }
+        }
+    }
+
+    pub fn get_selected_text(&self, buffer: &Buffer) -> Option<String> {
+        let bounds = self.bounds()?;
+        let mut selected_text = String::new();
+        for row in bounds.start_row..=bounds.end_row {
+            // Add newline between rows, but not before first row
+            if row > bounds.start_row {
+                selected_text.push('\n');
+            }
+            let (from, to) = self.selected_columns(bounds, row, buffer.area);
+            for col in from..=to {
+                if let Some(cell) = buffer.cell((col, row)) {
+                    selected_text.push_str(cell.symbol());
+                }
+            }
+        }
+        Some(selected_text)
+    }
+
+    /// The selected cells, `area` is the area of the screen.
+    pub fn iter_selected_cells(&self, area: Rect) -> Option<impl Iterator<Item = (u16, u16)>> {
+        let bounds = self.bounds()?;
+        let selection = self.clone();
+        Some((bounds.start_row..=bounds.end_row).flat_map(move |row| {
+            let (from, to) = selection.selected_columns(bounds, row, area);
+            (from..=to).map(move |col| (col, row))
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(start: (u16, u16), end: (u16, u16)) -> Selection {
+        Selection {
+            start: Some(start),
+            end: Some(end),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_selected_cells_in_any_direction() {
+        let expected = vec![(1, 0), (2, 0), (1, 1), (2, 1)];
+        for (start, end) in [((1, 0), (2, 1)), ((2, 1), (1, 0)), ((1, 1), (2, 0))] {
+            let cells = selection(start, end)
+                .iter_selected_cells(Rect::new(0, 0, 4, 2))
+                .unwrap()
+                .collect::<Vec<_>>();
+            assert_eq!(cells, expected);
+        }
+        assert!(Selection::default()
+            .iter_selected_cells(Rect::new(0, 0, 4, 2))
+            .is_none());
+    }
+
+    #[test]
+    fn test_line_selection_follows_text() {
+        let buffer = Buffer::with_lines(["hello", "big", "world"]);
+        for (start, end) in [((3, 0), (1, 2)), ((1, 2), (3, 0))] {
+            let selection = Selection {
+                mode: SelectionMode::Line,
+                ..selection(start, end)
+            };
+            assert_eq!(
+                selection.get_selected_text(&buffer),
+                Some("lo\nbig  \nwo".to_string())
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_selected_text() {
+        let buffer = Buffer::with_lines(["hello", "world"]);
+        assert_eq!(
+            selection((3, 1), (1, 0)).get_selected_text(&buffer),
+            Some("ell\norl".to_string())
+        );
+        // Cells outside of the buffer are skipped
+        let buffer = Buffer::empty(Rect::new(0, 0, 2, 1));
+        assert_eq!(
+            selection((0, 0), (5, 0)).get_selected_text(&buffer),
+            Some("  ".to_string())
+        );
+    }
+}
@@ -0,0 +1,168 @@
+use std::{os::unix::fs::FileTypeExt, path::Path};
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+
+use crate::{
+    ai::{assistant_response, DEFAULT_MODEL},
+    app::{AppResult, Message},
+    cli::ServeArgs,
+    storage::{list_all_conversations, list_all_messages},
+};
+
+/// A request, sent as one line of JSON.
+#[derive(Debug, Deserialize)]
+#[serde(tag = "method", rename_all = "snake_case")]
+enum Request {
+    /// Lists the saved conversations
+    ListConversations,
+    /// Gets the messages of a conversation
+    GetMessages { conversation_id: i64 },
+    /// Sends a prompt to a model and returns the response, without saving it
+    Ask {
+        prompt: String,
+        model: Option<String>,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct JsonMessage<'a> {
+    role: &'a str,
+    text: &'a str,
+}
+
+impl<'a> From<&'a Message> for JsonMessage<'a> {
+    fn from(message: &'a Message) -> Self {
+        let role = match message {
+            Message::User(_) => "user",
+            Message::Assistant(..) => "assistant",
+            Message::Error(_) => "error",
+            Message::Note(_) => "note",
+        };
+        Self {
+            role,
+            text: message.as_str(),
+        }
+    }
+}
+
+struct Server {
+    system_prompt: String,
+    temperature: f64,
+}
+
+impl Server {
+    async fn handle(&self, request: Request) -> AppResult<Value> {
+        match request {
+            Request::ListConversations => {
+                let conversations = list_all_conversations()?
+                    .into_iter()
+                    .map(|(id, started_at)| json!({"id": id, "started_at": started_at}))
+                    .collect::<Vec<_>>();
+                Ok(Value::Array(conversations))
+            }
+            Request::GetMessages { conversation_id } => {
+                let messages = list_all_messages(conversation_id)?;
+                Ok(serde_json::to_value(
+                    messages.iter().map(JsonMessage::from).collect::<Vec<_>>(),
+                )?)
+            }
+            Request::Ask { prompt, model } => {
+                let model = model.as_deref().unwrap_or(DEFAULT_MODEL);
+                let messages = [Message::User(prompt)];
+                let response = assistant_response(
+                    &messages,
+                    model,
+                    &self.system_prompt,
+                    &self.temperature,
+                    None,
+                )
+                .await?;
+                Ok(serde_json::to_value(JsonMessage::from(&response))?)
+            }
+        }
+    }
+
+    async fn handle_line(&self, line: &str) -> Value {
+        let result = match serde_json::from_str::<Request>(line) {
+            Ok(request) => self.handle(request).await,
+            Err(e) => Err(anyhow::Error::new(e).context("Invalid request")),
+        };
+        match result {
+            Ok(result) => json!({"ok": true, "result": result}),
+            Err(e) => json!({"ok": false, "error": format!("{:#}", e)}),
+        }
+    }
+
+    async fn serve_connection(&self, stream: UnixStream) -> AppResult<()> {
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        while let Some(line) = lines.next_line().await? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut response = self.handle_line(&line).await.to_string();
+            response.push('\n');
+            writer.write_all(response.as_bytes()).await?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the `serve` subcommand: answers JSON-line requests on a unix socket
+/// until interrupted.
+pub async fn run_serve(args: &ServeArgs, system_prompt: &str, temperature: f64) -> AppResult<()> {
+    let socket = Path::new(&args.socket);
+    // Only a stale socket is removed, never a file given by mistake
+    if let Ok(metadata) = std::fs::symlink_metadata(socket) {
+        if !metadata.file_type().is_socket() {
+            anyhow::bail!("{} exists and is not a socket", socket.display());
+        }
+        std::fs::remove_file(socket)
+            .with_context(|| format!("Unable to remove stale socket {}", socket.display()))?;
+    }
+    let listener = UnixListener::bind(socket)
+        .with_context(|| format!("Unable to listen on {}", socket.display()))?;
+    eprintln!("Listening on {}", socket.display());
+    let server = std::sync::Arc::new(Server {
+        system_prompt: system_prompt.to_string(),
+        temperature,
+    });
+    loop {
+        let (stream, _) = listener
+            .accept()
+            .await
+            .context("Unable to accept connection")?;
+        let server = server.clone();
+        tokio::spawn(async move {
+            if let Err(e) = server.serve_connection(stream).await {
+                eprintln!("Connection error: {:#}", e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        assert!(matches!(
+            serde_json::from_str(r#"{"method": "list_conversations"}"#),
+            Ok(Request::ListConversations)
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"method": "get_messages", "conversation_id": 3}"#),
+            Ok(Request::GetMessages { conversation_id: 3 })
+        ));
+        assert!(matches!(
+            serde_json::from_str(r#"{"method": "ask", "prompt": "hi"}"#),
+            Ok(Request::Ask { model: None, .. })
+        ));
+        assert!(serde_json::from_str::<Request>(r#"{"method": "delete"}"#).is_err());
+    }
+}
@@ -13,12 +13,36 @@ impl SnippetList {
         self.items.clear();
         self.state.select(None);
     }
+
+    /// Removes all snippets except the pinned ones.
+    pub fn clear_unpinned(&mut self) {
+        self.items.retain(|item| item.pinned);
+        self.state.select(None);
+    }
+
+    /// Adds snippets that are not in the list yet, keeping the first
+    /// occurrence of repeated snippets.
+    pub fn extend_unique(&mut self, items: impl IntoIterator<Item = SnippetItem>) {
+        for item in items {
+            let is_duplicate = self
+                .items
+                .iter()
+                .any(|i| i.text == item.text && i.language == item.language);
+            if !is_duplicate {
+                self.items.push(item);
+            }
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SnippetItem {
     pub text: String,
+    /// Language given after the opening fence of the code block
+    pub language: Option<String>,
     pub selected: bool,
+    /// Kept when switching conversations
+    pub pinned: bool,
 }
 
 impl FromStr for SnippetItem {
@@ -50,14 +74,53 @@ impl SnippetItem {
     pub fn new(snippet: &str, selected: bool) -> Self {
         Self {
             text: snippet.to_string(),
+            language: None,
             selected,
+            pinned: false,
         }
     }
+
+    pub fn with_language(mut self, language: Option<String>) -> Self {
+        self.language = language;
+        self
+    }
+
+    /// Is the snippet a shell command, going by the language of its code block?
+    pub fn is_shell(&self) -> bool {
+        self.language.as_deref().is_some_and(|language| {
+            ["sh", "bash", "zsh", "fish", "shell", "console"]
+                .iter()
+                .any(|shell| language.eq_ignore_ascii_case(shell))
+        })
+    }
 }
 
 pub fn find_fenced_code_snippets(messages: Vec<String>) -> Vec<String> {
+    find_fenced_code_blocks(messages)
+        .into_iter()
+        .map(|(_, snippet)| snippet)
+        .collect()
+}
+
+/// Finds fenced code blocks together with the language given after the
+/// opening fence, if any.
+pub fn find_fenced_code_blocks(messages: Vec<String>) -> Vec<(Option<String>, String)> {
+    scan_code_blocks(messages, false)
+}
+
+/// Finds fenced code blocks like [`find_fenced_code_blocks`], including a
+/// trailing code block whose closing fence hasn't been received yet.
+pub fn find_partial_code_blocks(messages: Vec<String>) -> Vec<(Option<String>, String)> {
+    scan_code_blocks(messages, true)
+}
+
+fn scan_code_blocks(
+    messages: Vec<String>,
+    include_unterminated: bool,
+) -> Vec<(Option<String>, String)> {
     let mut snippets = Vec::new();
     let mut in_code_block = false;
+    let mut current_language = None;
     let mut current_snippet = String::new();
 
     for line in messages {
@@ -65,8 +128,18 @@ pub fn find_fenced_code_snippets(messages: Vec<String>) -> Vec<String> {
             // Toggle the state of being inside a code block
             if in_code_block {
                 // Code block ends, save the current snippet
-                snippets.push(current_snippet.trim_end_matches('\n').to_string());
+                snippets.push((
+                    current_language.take(),
+                    current_snippet.trim_end_matches('\n').to_string(),
+                ));
                 current_snippet.clear();
+            } else {
+                current_language = line
+                    .trim_start()
+                    .trim_start_matches('`')
+                    .split_whitespace()
+                    .next()
+                    .map(|l| l.to_lowercase());
             }
             in_code_block = !in_code_block;
         } else if in_code_block {
@@ -75,9 +148,54 @@ pub fn find_fenced_code_snippets(messages: Vec<String>) -> Vec<String> {
             current_snippet.push('\n');
         }
     }
+    if in_code_block && include_unterminated {
+        snippets.push((
+            current_language,
+            current_snippet.trim_end_matches('\n').to_string(),
+        ));
+    }
 
     snippets
 }
+
+/// Returns the file extension conventionally used for a fenced code block
+/// language, falling back to `txt`.
+pub fn extension_for_language(language: Option<&str>) -> &'static str {
+    match language.unwrap_or_default() {
+        "rust" | "rs" => "rs",
+        "python" | "py" => "py",
+        "javascript" | "js" => "js",
+        "typescript" | "ts" => "ts",
+        "tsx" => "tsx",
+        "jsx" => "jsx",
+        "bash" | "sh" | "shell" | "zsh" | "console" => "sh",
+        "fish" => "fish",
+        "powershell" | "ps1" => "ps1",
+        "c" => "c",
+        "cpp" | "c++" => "cpp",
+        "csharp" | "cs" | "c#" => "cs",
+        "go" | "golang" => "go",
+        "java" => "java",
+        "kotlin" | "kt" => "kt",
+        "swift" => "swift",
+        "ruby" | "rb" => "rb",
+        "php" => "php",
+        "lua" => "lua",
+        "haskell" | "hs" => "hs",
+        "html" => "html",
+        "css" => "css",
+        "json" => "json",
+        "yaml" | "yml" => "yaml",
+        "toml" => "toml",
+        "xml" => "xml",
+        "sql" => "sql",
+        "markdown" | "md" => "md",
+        "dockerfile" => "dockerfile",
+        "makefile" | "make" => "mk",
+        _ => "txt",
+    }
+}
+
 // A few tests to ensure the function is working as expected.
 
 mod tests {
@@ -111,6 +229,40 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_extend_unique() {
+        use crate::snippets::{SnippetItem, SnippetList};
+
+        let mut snippet_list = SnippetList::from_iter(vec![]);
+        let rust = || SnippetItem::new("fn main() {}", false).with_language(Some("rust".into()));
+        snippet_list.extend_unique([rust(), SnippetItem::new("fn main() {}", false)]);
+        snippet_list.extend_unique([rust()]);
+        assert_eq!(snippet_list.items.len(), 2);
+        assert_eq!(snippet_list.items[0].language.as_deref(), Some("rust"));
+    }
+
+    #[test]
+    fn test_find_partial_code_blocks() {
+        let messages = vec![
+            "```rust".to_string(),
+            "fn main() {}".to_string(),
+            "```".to_string(),
+            "```python".to_string(),
+            "def main():".to_string(),
+        ];
+        assert_eq!(
+            crate::snippets::find_fenced_code_blocks(messages.clone()).len(),
+            1
+        );
+        assert_eq!(
+            crate::snippets::find_partial_code_blocks(messages),
+            vec![
+                (Some("rust".to_string()), "fn main() {}".to_string()),
+                (Some("python".to_string()), "def main():".to_string()),
+            ]
+        );
+    }
+
     #[test]
     fn test_find_snippets2() {
         let messages = vec![
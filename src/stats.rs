@@ -0,0 +1,125 @@
+use crate::{
+    ai::estimate_tokens,
+    app::AppResult,
+    storage::{model_usage_stats, ModelUsage},
+};
+
+/// Price in dollars per million generated tokens of known models, longer
+/// prefixes first.
+const OUTPUT_PRICES: [(&str, f64); 7] = [
+    ("gpt-4o-mini", 0.6),
+    ("gpt-4o", 10.0),
+    ("claude-3-5-sonnet", 15.0),
+    ("claude-3-5-haiku", 4.0),
+    ("claude-3-opus", 75.0),
+    ("claude-3-haiku", 1.25),
+    ("claude-3-sonnet", 15.0),
+];
+
+/// Price per million generated tokens of a model, local models are free.
+fn output_price(usage: &ModelUsage) -> Option<f64> {
+    if usage.provider.as_deref() == Some("Ollama") {
+        return Some(0.0);
+    }
+    let model = usage.model.as_deref()?;
+    OUTPUT_PRICES
+        .iter()
+        .find(|(prefix, _)| model.starts_with(prefix))
+        .map(|(_, price)| *price)
+}
+
+/// Estimated cost in dollars of the responses of models with a known price.
+/// Prompts are not included.
+pub fn estimate_cost(models: &[ModelUsage]) -> f64 {
+    models
+        .iter()
+        .filter_map(|usage| {
+            let price = output_price(usage)?;
+            Some(estimate_tokens(usage.n_characters) as f64 * price / 1_000_000.0)
+        })
+        .sum()
+}
+
+/// Name of a model including its provider.
+pub fn model_name(usage: &ModelUsage) -> String {
+    match (&usage.provider, &usage.model) {
+        (Some(provider), Some(model)) => format!("{}/{}", provider, model),
+        (None, Some(model)) => model.clone(),
+        (_, None) => "(unknown)".to_string(),
+    }
+}
+
+/// Formats the model usage as a table, one model per line.
+fn format_model_usage(usage: &[ModelUsage]) -> String {
+    let width = usage
+        .iter()
+        .map(|u| model_name(u).len())
+        .max()
+        .unwrap_or(0)
+        .max(5);
+    let total = usage.iter().map(|u| u.n_responses).sum::<usize>().max(1);
+    let mut table = format!("{:<width$}  responses\n", "model");
+    for u in usage {
+        table.push_str(&format!(
+            "{:<width$}  {:>9} ({:.1}%)\n",
+            model_name(u),
+            u.n_responses,
+            100.0 * u.n_responses as f64 / total as f64
+        ));
+    }
+    table
+}
+
+/// Prints how many saved responses each model generated.
+pub fn run_stats() -> AppResult<()> {
+    let usage = model_usage_stats()?;
+    if usage.is_empty() {
+        println!("No responses have been saved yet");
+        return Ok(());
+    }
+    print!("{}", format_model_usage(&usage));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_estimate_cost() {
+        let usage = |provider: &str, model: &str| ModelUsage {
+            provider: Some(provider.to_string()),
+            model: Some(model.to_string()),
+            n_responses: 1,
+            n_characters: 4_000_000,
+        };
+        assert_eq!(estimate_cost(&[usage("OpenAI", "gpt-4o-mini")]), 0.6);
+        assert_eq!(estimate_cost(&[usage("OpenAI", "gpt-4o")]), 10.0);
+        assert_eq!(estimate_cost(&[usage("Ollama", "gemma:2b")]), 0.0);
+        assert_eq!(estimate_cost(&[usage("Groq", "unknown")]), 0.0);
+    }
+
+    #[test]
+    fn test_format_model_usage() {
+        let usage = [
+            ModelUsage {
+                provider: Some("OpenAI".to_string()),
+                model: Some("gpt-4o".to_string()),
+                n_responses: 3,
+                n_characters: 120,
+            },
+            ModelUsage {
+                provider: None,
+                model: None,
+                n_responses: 1,
+                n_characters: 40,
+            },
+        ];
+        assert_eq!(
+            format_model_usage(&usage),
+            "model          responses\n\
+             OpenAI/gpt-4o          3 (75.0%)\n\
+             (unknown)              1 (25.0%)\n"
+        );
+    }
+}
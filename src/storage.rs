@@ -1,19 +1,78 @@
 use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use ::dirs::home_dir;
 use anyhow::Context;
 use rusqlite::{params, Connection};
 
-use crate::app::{AppResult, Message};
+use crate::app::{AppResult, Message, ResponseInfo};
+use crate::config::project_dir;
 
-pub fn create_db() -> AppResult<()> {
-    // Connect to the SQLite database (or create it if it doesn't exist)
+/// The chats database of the project the current directory is in, or the
+/// global one in `~/.cache/ait`.
+pub fn db_path() -> AppResult<PathBuf> {
+    if let Some(dir) = project_dir() {
+        return Ok(dir.join("chats.db"));
+    }
     let mut path = home_dir().context("Cannot find home directory")?;
     path.push(".cache/ait");
-    fs::create_dir_all(&path).context("Could not create cache directory")?;
     path.push("chats.db");
-    let conn = Connection::open(path).context("Could not open db connection")?;
+    Ok(path)
+}
+
+/// Creates the database, or brings an existing database up to date.
+///
+/// A corrupt database is moved aside and replaced by an empty database, the
+/// path of the corrupt file is returned in that case.
+pub fn create_db() -> AppResult<Option<PathBuf>> {
+    // Connect to the SQLite database (or create it if it doesn't exist)
+    let path = db_path()?;
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir).context("Could not create cache directory")?;
+    }
+    match open_checked(&path) {
+        Ok(conn) => create_tables(&conn).map(|_| None),
+        Err(e) if is_corruption(&e) => {
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs());
+            let backup_path = path.with_file_name(format!("chats.db.corrupt-{}", timestamp));
+            fs::rename(&path, &backup_path).with_context(|| {
+                format!(
+                    "Could not move corrupt database to {}",
+                    backup_path.display()
+                )
+            })?;
+            let conn = Connection::open(&path).context("Could not open db connection")?;
+            create_tables(&conn)?;
+            Ok(Some(backup_path))
+        }
+        Err(e) => Err(e).context("Could not open db connection"),
+    }
+}
+
+/// Opens the database and checks that it isn't corrupt.
+fn open_checked(path: &Path) -> rusqlite::Result<Connection> {
+    let conn = Connection::open(path)?;
+    let result: String = conn.query_row("PRAGMA quick_check", [], |row| row.get(0))?;
+    if result != "ok" {
+        return Err(rusqlite::Error::SqliteFailure(
+            rusqlite::ffi::Error::new(rusqlite::ffi::SQLITE_CORRUPT),
+            Some(result),
+        ));
+    }
+    Ok(conn)
+}
 
+fn is_corruption(e: &rusqlite::Error) -> bool {
+    matches!(
+        e.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseCorrupt | rusqlite::ErrorCode::NotADatabase)
+    )
+}
+
+fn create_tables(conn: &Connection) -> AppResult<()> {
     // Create the Conversations table
     conn.execute(
         "CREATE TABLE IF NOT EXISTS Conversations (
@@ -39,42 +98,139 @@ pub fn create_db() -> AppResult<()> {
     )
     .context("Failed to create messages table")?;
 
+    migrate(conn)
+}
+
+/// A change to the schema, applied once to every database.
+type Migration = fn(&Connection) -> AppResult<()>;
+
+/// Schema changes in the order they were introduced. The number of applied
+/// migrations is stored in the `user_version` of the database, so migrations
+/// must only ever be appended.
+const MIGRATIONS: &[Migration] = &[
+    add_temperature_column,
+    add_model_columns,
+    add_favorite_column,
+    allow_note_sender,
+];
+
+/// Brings the schema of a database up to date.
+fn migrate(conn: &Connection) -> AppResult<()> {
+    let version: usize = conn
+        .query_row("PRAGMA user_version", [], |row| row.get(0))
+        .context("Failed to read database version")?;
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+        migration(conn)
+            .with_context(|| format!("Failed to migrate database to version {}", i + 1))?;
+        conn.pragma_update(None, "user_version", i + 1)
+            .context("Failed to update database version")?;
+    }
+    Ok(())
+}
+
+fn has_column(conn: &Connection, table: &str, column: &str) -> AppResult<bool> {
+    let exists = conn
+        .prepare("SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2")?
+        .exists(params![table, column])
+        .with_context(|| format!("Failed to inspect {} table", table))?;
+    Ok(exists)
+}
+
+fn add_temperature_column(conn: &Connection) -> AppResult<()> {
+    // Some databases got the column before migrations were versioned
+    if !has_column(conn, "Messages", "temperature")? {
+        conn.execute("ALTER TABLE Messages ADD COLUMN temperature REAL", [])?;
+    }
+    Ok(())
+}
+
+fn add_model_columns(conn: &Connection) -> AppResult<()> {
+    conn.execute_batch(
+        "ALTER TABLE Messages ADD COLUMN provider TEXT;
+        ALTER TABLE Messages ADD COLUMN model TEXT;",
+    )?;
+    Ok(())
+}
+
+fn add_favorite_column(conn: &Connection) -> AppResult<()> {
+    conn.execute(
+        "ALTER TABLE Conversations ADD COLUMN is_favorite INTEGER NOT NULL DEFAULT 0",
+        [],
+    )?;
+    Ok(())
+}
+
+/// Notes, e.g. about model switches, are saved with the conversation.
+fn allow_note_sender(conn: &Connection) -> AppResult<()> {
+    // The check of a column can only be changed by recreating its table
+    let tx = conn.unchecked_transaction()?;
+    tx.execute_batch(
+        "CREATE TABLE Messages_new (
+            message_id INTEGER PRIMARY KEY AUTOINCREMENT,
+            conversation_id INTEGER,
+            sender TEXT CHECK(sender IN ('human', 'assistant', 'note')),
+            message_text TEXT NOT NULL,
+            timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+            temperature REAL,
+            provider TEXT,
+            model TEXT,
+            FOREIGN KEY(conversation_id) REFERENCES Conversations(conversation_id)
+        );
+        INSERT INTO Messages_new
+            (message_id, conversation_id, sender, message_text, timestamp, temperature, provider, model)
+        SELECT message_id, conversation_id, sender, message_text, timestamp, temperature, provider, model
+        FROM Messages;
+        DROP TABLE Messages;
+        ALTER TABLE Messages_new RENAME TO Messages;",
+    )?;
+    tx.commit()?;
     Ok(())
 }
 
 pub fn insert_message(conversation_id: i64, message: &Message) -> AppResult<()> {
     // Connect to the SQLite database
-    let mut path = home_dir().context("Cannot find home directory")?;
-    path.push(".cache/ait");
-    path.push("chats.db");
+    let path = db_path()?;
     let conn = Connection::open(path)?;
     // Insert the message into the Messages table
-    let (sender, message_text) = match message {
-        Message::User(text) => ("human", text),
-        Message::Assistant(text) => ("assistant", text),
-        _ => return Ok(()),
+    let no_info = ResponseInfo::default();
+    let (sender, message_text, info) = match message {
+        Message::User(text) => ("human", text, &no_info),
+        Message::Assistant(text, info) => ("assistant", text, info),
+        Message::Note(text) => ("note", text, &no_info),
+        Message::Error(_) => return Ok(()),
     };
     conn.execute(
-        "INSERT INTO Messages (conversation_id, sender, message_text) VALUES (?1, ?2, ?3)",
-        params![conversation_id, sender, message_text],
+        "INSERT INTO Messages (conversation_id, sender, message_text, temperature, provider, model)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            conversation_id,
+            sender,
+            message_text,
+            info.temperature,
+            info.provider,
+            info.model
+        ],
     )?;
     Ok(())
 }
 
 pub fn delete_message(conversation_id: i64, message: &Message) -> AppResult<()> {
-    let mut path = home_dir().context("Cannot find home directory")?;
-    path.push(".cache/ait");
-    path.push("chats.db");
+    let path = db_path()?;
     let conn = Connection::open(path).context("Could not connect to database")?;
 
     let (sender, message_text) = match message {
         Message::User(text) => ("human", text),
-        Message::Assistant(text) => ("assistant", text),
-        _ => return Ok(()),
+        Message::Assistant(text, _) => ("assistant", text),
+        Message::Note(text) => ("note", text),
+        Message::Error(_) => return Ok(()),
     };
 
+    // Only the latest copy, notes in particular are often repeated
     conn.execute(
-        "DELETE FROM Messages WHERE conversation_id = ?1 AND sender = ?2 AND message_text = ?3",
+        "DELETE FROM Messages WHERE message_id = (
+            SELECT MAX(message_id) FROM Messages
+            WHERE conversation_id = ?1 AND sender = ?2 AND message_text = ?3
+        )",
         params![conversation_id, sender, message_text],
     )
     .context("Failed to delete message")?;
@@ -84,9 +240,7 @@ pub fn delete_message(conversation_id: i64, message: &Message) -> AppResult<()>
 
 pub fn create_db_conversation(system_prompt: &str) -> AppResult<i64> {
     // Connect to the SQLite database
-    let mut path = home_dir().context("Cannot find home directory")?;
-    path.push(".cache/ait");
-    path.push("chats.db");
+    let path = db_path()?;
     let conn = Connection::open(path).context("Could not connect to database")?;
     conn.execute(
         "INSERT INTO Conversations (system_prompt) VALUES (?1)",
@@ -98,11 +252,23 @@ pub fn create_db_conversation(system_prompt: &str) -> AppResult<i64> {
     Ok(conversation_id)
 }
 
+pub fn get_conversation_system_prompt(conversation_id: i64) -> AppResult<String> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    let system_prompt = conn
+        .query_row(
+            "SELECT system_prompt FROM Conversations WHERE conversation_id = ?1",
+            params![conversation_id],
+            |row| row.get(0),
+        )
+        .context("Failed to query conversation system prompt")?;
+    Ok(system_prompt)
+}
+
 pub fn list_all_conversations() -> AppResult<Vec<(i64, String)>> {
     // Connect to the SQLite database
-    let mut path = home_dir().context("Cannot find home directory")?;
-    path.push(".cache/ait");
-    path.push("chats.db");
+    let path = db_path()?;
     let conn = Connection::open(path).context("Could not connect to database")?;
     // Query the Conversations table for all conversation IDs
     let mut stmt = conn.prepare(
@@ -115,21 +281,84 @@ pub fn list_all_conversations() -> AppResult<Vec<(i64, String)>> {
     Ok(conversation_ids)
 }
 
+/// Lists the conversations with a message containing `term`, ignoring ASCII
+/// case, newest first.
+pub fn search_conversations(term: &str) -> AppResult<Vec<(i64, String)>> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    query_conversations_containing(&conn, term)
+}
+
+fn query_conversations_containing(conn: &Connection, term: &str) -> AppResult<Vec<(i64, String)>> {
+    let escaped = term
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_");
+    let mut stmt = conn.prepare(
+        "SELECT c.conversation_id, c.started_at FROM Conversations c
+        WHERE EXISTS (SELECT 1 FROM Messages m WHERE m.conversation_id = c.conversation_id
+            AND m.message_text LIKE ?1 ESCAPE '\\')
+        ORDER BY c.conversation_id DESC",
+    )?;
+    let conversations = stmt
+        .query_map(params![format!("%{}%", escaped)], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .context("Failed to search conversations")?
+        .collect::<rusqlite::Result<Vec<(i64, String)>>>()?;
+    Ok(conversations)
+}
+
+/// A saved conversation as shown in the quick switcher.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConversationSummary {
+    pub conversation_id: i64,
+    pub started_at: String,
+    /// First message of the user, if any
+    pub first_message: Option<String>,
+}
+
+/// Lists all conversations with their first user message, newest first.
+pub fn list_conversation_summaries() -> AppResult<Vec<ConversationSummary>> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    let mut stmt = conn.prepare(
+        "SELECT c.conversation_id, c.started_at,
+            (SELECT m.message_text FROM Messages m
+            WHERE m.conversation_id = c.conversation_id AND m.sender = 'human'
+            ORDER BY m.message_id ASC LIMIT 1)
+        FROM Conversations c ORDER BY c.conversation_id DESC",
+    )?;
+    let summaries = stmt
+        .query_map([], |row| {
+            Ok(ConversationSummary {
+                conversation_id: row.get(0)?,
+                started_at: row.get(1)?,
+                first_message: row.get(2)?,
+            })
+        })
+        .context("Failed to query conversations table")?
+        .collect::<rusqlite::Result<Vec<ConversationSummary>>>()?;
+    Ok(summaries)
+}
+
 pub fn list_all_messages(conversation_id: i64) -> AppResult<Vec<Message>> {
     // Connect to the SQLite database
-    let mut path = home_dir().context("Cannot find home directory")?;
-    path.push(".cache/ait");
-    path.push("chats.db");
+    let path = db_path()?;
     let conn = Connection::open(path).context("Could not connect to database")?;
+    query_all_messages(&conn, conversation_id)
+}
+
+fn query_all_messages(conn: &Connection, conversation_id: i64) -> AppResult<Vec<Message>> {
     // Query the Messages table for all messages in the specified conversation
-    let mut stmt = conn.prepare("SELECT * FROM Messages WHERE conversation_id = ?1")?;
+    let mut stmt = conn.prepare(
+        "SELECT sender, message_text, temperature, provider, model FROM Messages
+        WHERE conversation_id = ?1 ORDER BY message_id ASC",
+    )?;
     let messages = stmt
-        .query_map(params![conversation_id], |row| {
-            Ok(DBMessage {
-                sender: row.get(2)?,
-                message_text: row.get(3)?,
-            })
-        })
+        .query_map(params![conversation_id], DBMessage::from_row)
         .context("Failed to query messages table")?
         .collect::<rusqlite::Result<Vec<DBMessage>>>()?;
     let messages = messages
@@ -139,11 +368,70 @@ pub fn list_all_messages(conversation_id: i64) -> AppResult<Vec<Message>> {
     Ok(messages)
 }
 
+pub fn count_messages(conversation_id: i64) -> AppResult<usize> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    let count = conn
+        .query_row(
+            "SELECT COUNT(*) FROM Messages WHERE conversation_id = ?1",
+            params![conversation_id],
+            |row| row.get(0),
+        )
+        .context("Failed to count messages")?;
+    Ok(count)
+}
+
+/// Lists `limit` messages of a conversation, skipping the first `offset`.
+pub fn list_messages_page(
+    conversation_id: i64,
+    offset: usize,
+    limit: usize,
+) -> AppResult<Vec<Message>> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    let mut stmt = conn.prepare(
+        "SELECT sender, message_text, temperature, provider, model FROM Messages
+        WHERE conversation_id = ?1 ORDER BY message_id ASC LIMIT ?2 OFFSET ?3",
+    )?;
+    let messages = stmt
+        .query_map(params![conversation_id, limit, offset], DBMessage::from_row)
+        .context("Failed to query messages table")?
+        .collect::<rusqlite::Result<Vec<DBMessage>>>()?;
+    Ok(messages.into_iter().map(Message::from).collect())
+}
+
+/// Stars or unstars a conversation.
+pub fn set_conversation_favorite(conversation_id: i64, favorite: bool) -> AppResult<()> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    conn.execute(
+        "UPDATE Conversations SET is_favorite = ?2 WHERE conversation_id = ?1",
+        params![conversation_id, favorite],
+    )
+    .context("Failed to update conversation")?;
+    Ok(())
+}
+
+/// Lists the IDs of the starred conversations.
+pub fn list_favorite_conversations() -> AppResult<Vec<i64>> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    let mut stmt =
+        conn.prepare("SELECT conversation_id FROM Conversations WHERE is_favorite = 1")?;
+    let ids = stmt
+        .query_map([], |row| row.get(0))
+        .context("Failed to query conversations table")?
+        .collect::<rusqlite::Result<Vec<i64>>>()?;
+    Ok(ids)
+}
+
 pub fn delete_conversation(conversation_id: i64) -> AppResult<()> {
     // Connect to the SQLite database
-    let mut path = home_dir().context("Cannot find home directory")?;
-    path.push(".cache/ait");
-    path.push("chats.db");
+    let path = db_path()?;
     let conn = Connection::open(path).context("Could not connect to database")?;
     // Delete the messages from the Messages table
     conn.execute(
@@ -160,18 +448,359 @@ pub fn delete_conversation(conversation_id: i64) -> AppResult<()> {
     Ok(())
 }
 
+/// Appends the messages of the conversation `from` to the conversation
+/// `into` in the order they were sent, and deletes `from`.
+pub fn merge_conversations(into: i64, from: i64) -> AppResult<()> {
+    // Connect to the SQLite database
+    let path = db_path()?;
+    let mut conn = Connection::open(path).context("Could not connect to database")?;
+    merge_conversations_in(&mut conn, into, from)
+}
+
+fn merge_conversations_in(conn: &mut Connection, into: i64, from: i64) -> AppResult<()> {
+    // Either all messages are moved or none
+    let tx = conn.transaction().context("Failed to start transaction")?;
+    tx.execute(
+        "INSERT INTO Messages
+            (conversation_id, sender, message_text, timestamp, temperature, provider, model)
+        SELECT ?1, sender, message_text, timestamp, temperature, provider, model
+        FROM Messages WHERE conversation_id = ?2 ORDER BY timestamp ASC, message_id ASC",
+        params![into, from],
+    )
+    .context("Failed to copy messages")?;
+    tx.execute(
+        "DELETE FROM Messages WHERE conversation_id = ?1",
+        params![from],
+    )
+    .context("Failed to delete messages")?;
+    tx.execute(
+        "DELETE FROM Conversations WHERE conversation_id = ?1",
+        params![from],
+    )
+    .context("Failed to delete conversation")?;
+    tx.commit().context("Failed to merge conversations")?;
+    Ok(())
+}
+
 struct DBMessage {
     sender: String,
     message_text: String,
+    temperature: Option<f64>,
+    provider: Option<String>,
+    model: Option<String>,
+}
+
+impl DBMessage {
+    /// Reads the `sender, message_text, temperature, provider, model` columns.
+    fn from_row(row: &rusqlite::Row) -> rusqlite::Result<Self> {
+        Ok(DBMessage {
+            sender: row.get(0)?,
+            message_text: row.get(1)?,
+            temperature: row.get(2)?,
+            provider: row.get(3)?,
+            model: row.get(4)?,
+        })
+    }
+}
+
+/// Number of saved responses of a model.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelUsage {
+    /// Provider of the model, unknown for responses saved before it was recorded
+    pub provider: Option<String>,
+    /// The model, unknown for responses saved before it was recorded
+    pub model: Option<String>,
+    pub n_responses: usize,
+    /// Total length of the responses
+    pub n_characters: usize,
+}
+
+/// Aggregate statistics of all saved conversations.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageStats {
+    pub n_conversations: usize,
+    pub n_messages: usize,
+    /// Total length of all messages
+    pub n_characters: usize,
+    /// Responses of each model, most used models first
+    pub models: Vec<ModelUsage>,
+    /// Number of messages saved on each of the last days, oldest first
+    pub messages_per_day: Vec<(String, usize)>,
+}
+
+/// Number of days covered by [`UsageStats::messages_per_day`].
+const STATS_DAYS: usize = 30;
+
+/// Counts the saved responses of each model, most used models first.
+pub fn model_usage_stats() -> AppResult<Vec<ModelUsage>> {
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    query_model_usage(&conn)
+}
+
+/// Collects statistics across all saved conversations.
+pub fn usage_stats() -> AppResult<UsageStats> {
+    let path = db_path()?;
+    let conn = Connection::open(path).context("Could not connect to database")?;
+    query_usage_stats(&conn)
+}
+
+fn query_usage_stats(conn: &Connection) -> AppResult<UsageStats> {
+    let n_conversations = conn
+        .query_row("SELECT COUNT(*) FROM Conversations", [], |row| row.get(0))
+        .context("Failed to count conversations")?;
+    let (n_messages, n_characters) = conn
+        .query_row(
+            "SELECT COUNT(*), COALESCE(SUM(LENGTH(message_text)), 0) FROM Messages
+            WHERE sender != 'note'",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .context("Failed to count messages")?;
+    // Every day is listed, including the days without messages
+    let mut stmt = conn.prepare(
+        "WITH RECURSIVE days(day) AS (
+            SELECT date('now', ?1)
+            UNION ALL SELECT date(day, '+1 day') FROM days WHERE day < date('now')
+        )
+        SELECT day, (
+            SELECT COUNT(*) FROM Messages WHERE date(timestamp) = day AND sender != 'note'
+        ) FROM days",
+    )?;
+    let messages_per_day = stmt
+        .query_map(params![format!("-{} days", STATS_DAYS - 1)], |row| {
+            Ok((row.get(0)?, row.get(1)?))
+        })
+        .context("Failed to count messages per day")?
+        .collect::<rusqlite::Result<Vec<(String, usize)>>>()?;
+    Ok(UsageStats {
+        n_conversations,
+        n_messages,
+        n_characters,
+        models: query_model_usage(conn)?,
+        messages_per_day,
+    })
+}
+
+fn query_model_usage(conn: &Connection) -> AppResult<Vec<ModelUsage>> {
+    let mut stmt = conn.prepare(
+        "SELECT provider, model, COUNT(*), SUM(LENGTH(message_text)) FROM Messages
+        WHERE sender = 'assistant'
+        GROUP BY provider, model ORDER BY COUNT(*) DESC, model ASC",
+    )?;
+    let usage = stmt
+        .query_map([], |row| {
+            Ok(ModelUsage {
+                provider: row.get(0)?,
+                model: row.get(1)?,
+                n_responses: row.get(2)?,
+                n_characters: row.get(3)?,
+            })
+        })
+        .context("Failed to query model usage")?
+        .collect::<rusqlite::Result<Vec<ModelUsage>>>()?;
+    Ok(usage)
 }
 
 impl From<DBMessage> for Message {
     fn from(db_message: DBMessage) -> Self {
         let sender = match db_message.sender.as_str() {
             "human" => Message::User(db_message.message_text),
-            "assistant" => Message::Assistant(db_message.message_text),
+            "assistant" => Message::Assistant(
+                db_message.message_text,
+                ResponseInfo {
+                    temperature: db_message.temperature,
+                    provider: db_message.provider,
+                    model: db_message.model,
+                },
+            ),
+            "note" => Message::Note(db_message.message_text),
             _ => Message::Error("Unknown sender type".to_string()),
         };
         sender
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_corrupt_database() {
+        let path = std::env::temp_dir().join(format!("ait-test-{}.db", std::process::id()));
+        fs::write(&path, "this is not a database, just some text").unwrap();
+        let result = open_checked(&path);
+        fs::remove_file(&path).unwrap();
+        assert!(is_corruption(&result.unwrap_err()));
+    }
+
+    #[test]
+    fn test_migrate_old_database() {
+        let conn = Connection::open_in_memory().unwrap();
+        // The schema before migrations were introduced
+        conn.execute_batch(
+            "CREATE TABLE Conversations (
+                conversation_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                system_prompt TEXT NOT NULL,
+                started_at DATETIME DEFAULT CURRENT_TIMESTAMP
+            );
+            CREATE TABLE Messages (
+                message_id INTEGER PRIMARY KEY AUTOINCREMENT,
+                conversation_id INTEGER,
+                sender TEXT CHECK(sender IN ('human', 'assistant')),
+                message_text TEXT NOT NULL,
+                timestamp DATETIME DEFAULT CURRENT_TIMESTAMP,
+                FOREIGN KEY(conversation_id) REFERENCES Conversations(conversation_id)
+            );
+            INSERT INTO Conversations (conversation_id, system_prompt) VALUES (1, '');
+            INSERT INTO Messages (conversation_id, sender, message_text) VALUES (1, 'human', 'hi');",
+        )
+        .unwrap();
+
+        create_tables(&conn).unwrap();
+        let version: usize = conn
+            .query_row("PRAGMA user_version", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert!(has_column(&conn, "Messages", "temperature").unwrap());
+        let messages = query_all_messages(&conn, 1).unwrap();
+        assert_eq!(messages[0].as_str(), "hi");
+        conn.execute(
+            "INSERT INTO Messages (conversation_id, sender, message_text) VALUES (1, 'note', 'n')",
+            [],
+        )
+        .unwrap();
+        let messages = query_all_messages(&conn, 1).unwrap();
+        assert_eq!(messages[1], Message::Note("n".to_string()));
+
+        // Migrating again is a no-op
+        create_tables(&conn).unwrap();
+    }
+
+    #[test]
+    fn test_model_usage() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Conversations (conversation_id, system_prompt) VALUES (1, '');
+            INSERT INTO Messages (conversation_id, sender, message_text, provider, model) VALUES
+                (1, 'human', 'q', NULL, NULL),
+                (1, 'assistant', 'a', 'OpenAI', 'gpt-4o'),
+                (1, 'assistant', 'b', 'OpenAI', 'gpt-4o'),
+                (1, 'assistant', 'c', NULL, NULL);",
+        )
+        .unwrap();
+        let usage = query_model_usage(&conn).unwrap();
+        assert_eq!(
+            usage,
+            vec![
+                ModelUsage {
+                    provider: Some("OpenAI".to_string()),
+                    model: Some("gpt-4o".to_string()),
+                    n_responses: 2,
+                    n_characters: 2,
+                },
+                ModelUsage {
+                    provider: None,
+                    model: None,
+                    n_responses: 1,
+                    n_characters: 1,
+                },
+            ]
+        );
+
+        let stats = query_usage_stats(&conn).unwrap();
+        assert_eq!(stats.n_conversations, 1);
+        assert_eq!(stats.n_messages, 4);
+        assert_eq!(stats.n_characters, 4);
+        assert_eq!(stats.messages_per_day.len(), STATS_DAYS);
+        // The messages were all saved today
+        assert_eq!(stats.messages_per_day.last().unwrap().1, 4);
+    }
+
+    #[test]
+    fn test_messages_are_listed_in_order() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn.execute(
+            "INSERT INTO Conversations (conversation_id, system_prompt) VALUES (1, '')",
+            [],
+        )
+        .unwrap();
+        for (id, sender, text) in [
+            (3, "human", "third"),
+            (1, "human", "first"),
+            (4, "assistant", "fourth"),
+            (2, "assistant", "second"),
+        ] {
+            conn.execute(
+                "INSERT INTO Messages (message_id, conversation_id, sender, message_text)
+                VALUES (?1, 1, ?2, ?3)",
+                params![id, sender, text],
+            )
+            .unwrap();
+        }
+        let messages = query_all_messages(&conn, 1).unwrap();
+        let texts = messages.iter().map(Message::as_str).collect::<Vec<_>>();
+        assert_eq!(texts, vec!["first", "second", "third", "fourth"]);
+    }
+
+    #[test]
+    fn test_search_conversations() {
+        let conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        for (id, text) in [(1, "Rust borrow checker"), (2, "100% sure")] {
+            conn.execute(
+                "INSERT INTO Conversations (conversation_id, system_prompt) VALUES (?1, '')",
+                params![id],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO Messages (conversation_id, sender, message_text)
+                VALUES (?1, 'human', ?2)",
+                params![id, text],
+            )
+            .unwrap();
+        }
+        let ids = |term| {
+            query_conversations_containing(&conn, term)
+                .unwrap()
+                .into_iter()
+                .map(|(id, _)| id)
+                .collect::<Vec<i64>>()
+        };
+        assert_eq!(ids("BORROW"), vec![1]);
+        assert_eq!(ids("%"), vec![2]);
+        assert_eq!(ids("r"), vec![2, 1]);
+        assert!(ids("python").is_empty());
+    }
+
+    #[test]
+    fn test_merge_conversations() {
+        let mut conn = Connection::open_in_memory().unwrap();
+        create_tables(&conn).unwrap();
+        conn.execute_batch(
+            "INSERT INTO Conversations (conversation_id, system_prompt) VALUES (1, ''), (2, '');
+            INSERT INTO Messages (conversation_id, sender, message_text, timestamp) VALUES
+                (1, 'human', 'one', '2024-01-01 10:00:00'),
+                (2, 'assistant', 'three', '2024-01-02 10:00:01'),
+                (2, 'human', 'two', '2024-01-02 10:00:00');",
+        )
+        .unwrap();
+        merge_conversations_in(&mut conn, 1, 2).unwrap();
+        let texts = conn
+            .prepare(
+                "SELECT message_text FROM Messages WHERE conversation_id = 1 ORDER BY message_id",
+            )
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(texts, vec!["one", "two", "three"]);
+        let n_conversations: i64 = conn
+            .query_row("SELECT COUNT(*) FROM Conversations", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(n_conversations, 1);
+    }
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+
+use ratatui::widgets::ListState;
+
+/// Named user message boilerplate, inserted into the input when chosen.
+#[derive(Debug, Default)]
+pub struct TemplateList {
+    pub items: Vec<TemplateItem>,
+    pub state: ListState,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TemplateItem {
+    pub name: String,
+    pub text: String,
+}
+
+impl TemplateList {
+    /// Adds a template, replacing the text of a template with the same name.
+    pub fn insert(&mut self, name: &str, text: &str) {
+        let i = match self
+            .items
+            .binary_search_by(|item| item.name.as_str().cmp(name))
+        {
+            Ok(i) => {
+                self.items[i].text = text.to_string();
+                i
+            }
+            Err(i) => {
+                self.items.insert(
+                    i,
+                    TemplateItem {
+                        name: name.to_string(),
+                        text: text.to_string(),
+                    },
+                );
+                i
+            }
+        };
+        self.state.select(Some(i));
+    }
+
+    /// Removes the selected template.
+    pub fn remove_selected(&mut self) -> Option<TemplateItem> {
+        let i = self.state.selected().filter(|&i| i < self.items.len())?;
+        let item = self.items.remove(i);
+        if self.items.is_empty() {
+            self.state.select(None);
+        } else {
+            self.state.select(Some(i.min(self.items.len() - 1)));
+        }
+        Some(item)
+    }
+
+    pub fn selected(&self) -> Option<&TemplateItem> {
+        self.state.selected().and_then(|i| self.items.get(i))
+    }
+
+    /// Templates by name, as they are stored.
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.items
+            .iter()
+            .map(|item| (item.name.clone(), item.text.clone()))
+            .collect()
+    }
+}
+
+impl From<BTreeMap<String, String>> for TemplateList {
+    fn from(templates: BTreeMap<String, String>) -> Self {
+        let items = templates
+            .into_iter()
+            .map(|(name, text)| TemplateItem { name, text })
+            .collect();
+        let mut state = ListState::default();
+        state.select_first();
+        Self { items, state }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_keeps_templates_sorted() {
+        let mut templates = TemplateList::default();
+        templates.insert("review", "Review this code for bugs:");
+        templates.insert("explain", "Explain this:");
+        templates.insert("review", "Review this code:");
+        let names: Vec<_> = templates.items.iter().map(|t| t.name.as_str()).collect();
+        assert_eq!(names, ["explain", "review"]);
+        assert_eq!(templates.selected().unwrap().text, "Review this code:");
+        templates.remove_selected();
+        assert_eq!(templates.selected().unwrap().name, "explain");
+    }
+}
@@ -0,0 +1,195 @@
+use ratatui::style::{Color, Modifier, Style};
+
+/// Styles of the user interface.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Theme {
+    /// Messages written by the user
+    pub user: Style,
+    /// Responses of the assistant
+    pub assistant: Style,
+    /// Errors shown in the conversation
+    pub error: Style,
+    /// Notes shown in the conversation, e.g. about model switches
+    pub note: Style,
+    /// Applied on top of the role style for the rule below each role header
+    pub separator: Style,
+    /// Highlighted item of a list
+    pub list_highlight: Style,
+    /// Text selected with the mouse
+    pub selection: Style,
+    /// Text typed in the input areas
+    pub input: Style,
+    /// Text of the system prompt editor
+    pub system_prompt: Style,
+    /// Status line messages
+    pub status: Style,
+    /// Conversations previewed in the chat history
+    pub preview: Style,
+    /// Secondary details, e.g. the dates in the chat history
+    pub muted: Style,
+    /// Placeholder messages of empty lists
+    pub hint: Style,
+    /// Charts of the dashboard
+    pub chart: Style,
+    /// Matches of the chat history filter
+    pub search_match: Style,
+    /// Words added in a variation diff
+    pub inserted: Style,
+    /// Words removed in a variation diff
+    pub deleted: Style,
+    /// Whether code snippets are syntax highlighted
+    pub syntax_highlighting: bool,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Self {
+            user: Style::new().fg(Color::Yellow),
+            assistant: Style::new().fg(Color::Green),
+            error: Style::new().fg(Color::Red),
+            note: Style::new().add_modifier(Modifier::DIM | Modifier::ITALIC),
+            separator: Style::new().add_modifier(Modifier::DIM),
+            list_highlight: Style::new()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::LightBlue)
+                .bg(Color::DarkGray),
+            selection: Style::new().add_modifier(Modifier::REVERSED),
+            input: Style::new().fg(Color::Yellow),
+            system_prompt: Style::new().fg(Color::Cyan),
+            status: Style::new().fg(Color::Cyan),
+            preview: Style::new().fg(Color::Magenta),
+            muted: Style::new().fg(Color::DarkGray),
+            hint: Style::new().add_modifier(Modifier::ITALIC),
+            chart: Style::new().fg(Color::Cyan),
+            search_match: Style::new()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Black)
+                .bg(Color::Yellow),
+            inserted: Style::new().fg(Color::Green),
+            deleted: Style::new()
+                .add_modifier(Modifier::CROSSED_OUT)
+                .fg(Color::Red),
+            syntax_highlighting: true,
+        }
+    }
+}
+
+impl Theme {
+    /// A minimal palette of bright colors on the terminal background, without
+    /// dim or italic text that some terminals render poorly. Text keeps the
+    /// color of the terminal, so it stays readable on light backgrounds.
+    pub fn high_contrast() -> Self {
+        let text = Style::new();
+        Self {
+            user: Style::new().fg(Color::LightYellow),
+            assistant: text,
+            error: Style::new()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::LightRed),
+            // Notes have no header to tell them apart from answers
+            note: text.add_modifier(Modifier::UNDERLINED),
+            separator: Style::new(),
+            list_highlight: Style::new().add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            selection: Style::new().add_modifier(Modifier::REVERSED),
+            input: Style::new().fg(Color::LightYellow),
+            system_prompt: text,
+            status: text.add_modifier(Modifier::BOLD),
+            preview: text,
+            muted: text,
+            hint: text,
+            chart: text,
+            search_match: Style::new()
+                .add_modifier(Modifier::BOLD)
+                .fg(Color::Black)
+                .bg(Color::LightYellow),
+            inserted: Style::new()
+                .add_modifier(Modifier::UNDERLINED)
+                .fg(Color::LightGreen),
+            deleted: Style::new()
+                .add_modifier(Modifier::CROSSED_OUT)
+                .fg(Color::LightRed),
+            syntax_highlighting: false,
+        }
+    }
+
+    /// Removes every color, for `NO_COLOR` and monochrome terminals. Styles
+    /// that only stood out by their colors are reversed or underlined instead.
+    pub fn without_color(self) -> Self {
+        let strip = |style: Style| Style {
+            fg: None,
+            bg: None,
+            underline_color: None,
+            ..style
+        };
+        Self {
+            user: strip(self.user),
+            assistant: strip(self.assistant),
+            error: strip(self.error).add_modifier(Modifier::BOLD),
+            note: strip(self.note),
+            separator: strip(self.separator),
+            list_highlight: strip(self.list_highlight)
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            selection: Style::new().add_modifier(Modifier::REVERSED),
+            input: strip(self.input),
+            system_prompt: strip(self.system_prompt),
+            status: strip(self.status),
+            preview: strip(self.preview),
+            muted: strip(self.muted),
+            hint: strip(self.hint),
+            chart: strip(self.chart),
+            search_match: strip(self.search_match)
+                .add_modifier(Modifier::BOLD | Modifier::REVERSED),
+            inserted: strip(self.inserted).add_modifier(Modifier::UNDERLINED),
+            deleted: strip(self.deleted).add_modifier(Modifier::CROSSED_OUT),
+            syntax_highlighting: false,
+        }
+    }
+
+    /// Uses `color` as the background of selected text instead of reversing
+    /// its colors.
+    pub fn with_selection_color(mut self, color: Color) -> Self {
+        self.selection = Style::new().bg(color);
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_without_color_removes_every_color() {
+        for theme in [Theme::default(), Theme::high_contrast()] {
+            let theme = theme.without_color();
+            for style in [
+                theme.user,
+                theme.assistant,
+                theme.error,
+                theme.list_highlight,
+                theme.input,
+                theme.search_match,
+                theme.inserted,
+                theme.deleted,
+            ] {
+                assert_eq!((style.fg, style.bg), (None, None));
+            }
+            assert!(theme
+                .list_highlight
+                .add_modifier
+                .contains(Modifier::REVERSED));
+            assert!(!theme.syntax_highlighting);
+        }
+    }
+
+    #[test]
+    fn test_high_contrast_avoids_dim_and_italic() {
+        let theme = Theme::high_contrast();
+        for style in [theme.note, theme.separator, theme.muted, theme.hint] {
+            assert!(!style
+                .add_modifier
+                .intersects(Modifier::DIM | Modifier::ITALIC));
+        }
+        assert_ne!(theme.note, theme.assistant);
+        assert_eq!(theme.assistant.fg, None);
+    }
+}
@@ -2,16 +2,25 @@ use crate::app::{App, AppResult};
 use crate::event::EventHandler;
 use crate::ui;
 use anyhow::Context;
-use crossterm::event::{DisableMouseCapture, EnableMouseCapture};
+use crossterm::event::{
+    DisableBracketedPaste, DisableFocusChange, DisableMouseCapture, EnableBracketedPaste,
+    EnableFocusChange, EnableMouseCapture,
+};
 #[cfg(not(target_os = "windows"))]
 use crossterm::event::{
     KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags,
 };
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::backend::Backend;
+use ratatui::layout::Position;
 use ratatui::Terminal;
 use std::io;
 use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether the terminal is set up for the interface, so that the panic hook
+/// only resets it when it isn't suspended.
+static TERMINAL_ACTIVE: AtomicBool = AtomicBool::new(false);
 
 /// Representation of a terminal user interface.
 ///
@@ -23,37 +32,44 @@ pub struct Tui<B: Backend> {
     terminal: Terminal<B>,
     /// Terminal event handler.
     pub events: EventHandler,
+    /// Mouse events are reported to the application.
+    mouse_capture: bool,
+    /// The interface is drawn in the alternate screen.
+    alternate_screen: bool,
 }
 
 impl<B: Backend> Tui<B> {
     /// Constructs a new instance of [`Tui`].
     pub fn new(terminal: Terminal<B>, events: EventHandler) -> Self {
-        Self { terminal, events }
+        Self {
+            terminal,
+            events,
+            mouse_capture: true,
+            alternate_screen: true,
+        }
+    }
+
+    /// Draws the interface in the normal screen when `enabled` is false, so
+    /// that it stays in the scrollback of the terminal after exiting.
+    pub fn with_alternate_screen(mut self, enabled: bool) -> Self {
+        self.alternate_screen = enabled;
+        self
     }
 
     /// Initializes the terminal interface.
     ///
     /// It enables the raw mode and sets terminal properties.
     pub fn init(&mut self) -> AppResult<()> {
-        terminal::enable_raw_mode().context("Could not enable raw mode")?;
-        #[cfg(not(target_os = "windows"))]
-        crossterm::execute!(
-            io::stderr(),
-            EnterAlternateScreen,
-            EnableMouseCapture,
-            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
-        )
-        .context("Could not initialize terminal, error in `crossterm::execute!`")?;
-
-        #[cfg(target_os = "windows")]
-        crossterm::execute!(io::stderr(), EnterAlternateScreen, EnableMouseCapture)
-            .context("Could not initialize terminal, error in `crossterm::execute!`")?;
+        self.enter()?;
 
         // Define a custom panic hook to reset the terminal properties.
         // This way, you won't have your terminal messed up if an unexpected error happens.
         let panic_hook = panic::take_hook();
+        let alternate_screen = self.alternate_screen;
         panic::set_hook(Box::new(move |panic| {
-            Self::reset().expect("failed to reset the terminal");
+            if TERMINAL_ACTIVE.load(Ordering::SeqCst) {
+                Self::reset(alternate_screen).expect("failed to reset the terminal");
+            }
             panic_hook(panic);
         }));
 
@@ -64,6 +80,82 @@ impl<B: Backend> Tui<B> {
         Ok(())
     }
 
+    /// Hands the terminal over to another process, e.g. an editor, until
+    /// [`Tui::resume`] is called.
+    ///
+    /// Terminal events are no longer read, so that they reach the process.
+    pub async fn suspend(&mut self) -> AppResult<()> {
+        self.events.stop().await;
+        Self::reset(self.alternate_screen).context("Failed to reset terminal")?;
+        self.terminal
+            .show_cursor()
+            .context("Failed to show cursor")?;
+        Ok(())
+    }
+
+    /// Takes the terminal back after [`Tui::suspend`] and redraws the whole
+    /// interface on the next draw.
+    pub fn resume(&mut self) -> AppResult<()> {
+        self.enter()?;
+        self.events.start();
+        self.terminal
+            .hide_cursor()
+            .context("Error when hiding terminal cursor")?;
+        self.terminal.clear().context("Could not clear terminal")?;
+        Ok(())
+    }
+
+    /// Enables the raw mode and sets the terminal properties, keeping mouse
+    /// capture as it was.
+    fn enter(&mut self) -> AppResult<()> {
+        terminal::enable_raw_mode().context("Could not enable raw mode")?;
+        if self.alternate_screen {
+            crossterm::execute!(io::stderr(), EnterAlternateScreen)
+                .context("Could not enter the alternate screen")?;
+        }
+        #[cfg(not(target_os = "windows"))]
+        crossterm::execute!(
+            io::stderr(),
+            EnableMouseCapture,
+            EnableFocusChange,
+            EnableBracketedPaste,
+            PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::DISAMBIGUATE_ESCAPE_CODES)
+        )
+        .context("Could not initialize terminal, error in `crossterm::execute!`")?;
+
+        #[cfg(target_os = "windows")]
+        crossterm::execute!(
+            io::stderr(),
+            EnableMouseCapture,
+            EnableFocusChange,
+            EnableBracketedPaste
+        )
+        .context("Could not initialize terminal, error in `crossterm::execute!`")?;
+
+        if !self.mouse_capture {
+            crossterm::execute!(io::stderr(), DisableMouseCapture)
+                .context("Could not change mouse capture")?;
+        }
+        TERMINAL_ACTIVE.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Enables or disables mouse events, leaving text selection to the
+    /// terminal while they are disabled.
+    pub fn set_mouse_capture(&mut self, enabled: bool) -> AppResult<()> {
+        if enabled == self.mouse_capture {
+            return Ok(());
+        }
+        if enabled {
+            crossterm::execute!(io::stderr(), EnableMouseCapture)
+        } else {
+            crossterm::execute!(io::stderr(), DisableMouseCapture)
+        }
+        .context("Could not change mouse capture")?;
+        self.mouse_capture = enabled;
+        Ok(())
+    }
+
     /// [`Draw`] the terminal interface by [`rendering`] the widgets.
     ///
     /// [`Draw`]: ratatui::Terminal::draw
@@ -79,19 +171,30 @@ impl<B: Backend> Tui<B> {
     ///
     /// This function is also used for the panic hook to revert
     /// the terminal properties if unexpected errors occur.
-    fn reset() -> AppResult<()> {
+    fn reset(alternate_screen: bool) -> AppResult<()> {
+        TERMINAL_ACTIVE.store(false, Ordering::SeqCst);
         terminal::disable_raw_mode().context("Failed to disable raw mode")?;
+        if alternate_screen {
+            crossterm::execute!(io::stderr(), LeaveAlternateScreen)
+                .context("Could not leave the alternate screen")?;
+        }
         #[cfg(not(target_os = "windows"))]
         crossterm::execute!(
             io::stderr(),
-            LeaveAlternateScreen,
             DisableMouseCapture,
+            DisableFocusChange,
+            DisableBracketedPaste,
             PopKeyboardEnhancementFlags
         )
         .context("Failed resetting terminal, error during `crossterm::execute!`")?;
         #[cfg(target_os = "windows")]
-        crossterm::execute!(io::stderr(), LeaveAlternateScreen, DisableMouseCapture)
-            .context("Failed resetting terminal, error during `crossterm::execute!`")?;
+        crossterm::execute!(
+            io::stderr(),
+            DisableMouseCapture,
+            DisableFocusChange,
+            DisableBracketedPaste
+        )
+        .context("Failed resetting terminal, error during `crossterm::execute!`")?;
         Ok(())
     }
 
@@ -99,10 +202,21 @@ impl<B: Backend> Tui<B> {
     ///
     /// It disables the raw mode and reverts back the terminal properties.
     pub fn exit(&mut self) -> AppResult<()> {
-        Self::reset().context("Failed to reset terminal")?;
+        Self::reset(self.alternate_screen).context("Failed to reset terminal")?;
         self.terminal
             .show_cursor()
             .context("Failed to show cursor")?;
+        if !self.alternate_screen {
+            // The shell continues below the last screen of the interface
+            let size = self
+                .terminal
+                .size()
+                .context("Failed to get terminal size")?;
+            self.terminal
+                .set_cursor_position(Position::new(0, size.height.saturating_sub(1)))
+                .context("Failed to move cursor")?;
+            eprintln!();
+        }
         Ok(())
     }
 }
@@ -1,26 +1,27 @@
-use std::cmp::min;
+use std::{borrow::Cow, cmp::min};
 
 use ratatui::{
-    layout::{Alignment, Constraint, Layout, Margin, Rect},
-    style::{Color, Modifier, Style, Stylize},
+    layout::{Alignment, Constraint, Direction, Layout, Margin, Rect},
+    style::{Style, Stylize},
     text::{Line, Span, Text},
     widgets::{
-        Block, BorderType, Clear, HighlightSpacing, List, ListItem, Padding, Paragraph, Scrollbar,
-        ScrollbarOrientation, ScrollbarState, Wrap,
+        Bar, BarChart, BarGroup, Block, BorderType, Clear, Gauge, HighlightSpacing, List, ListItem,
+        Padding, Paragraph, Scrollbar, ScrollbarOrientation, ScrollbarState, Sparkline, Wrap,
     },
     Frame,
 };
 
 use crate::{
-    app::{App, AppMode, Message},
-    storage::list_all_messages,
+    ai::{context_limit, estimate_tokens},
+    app::{App, AppMode, AppResult, Message},
+    chats::summary_title,
+    config::{InputKeys, QuitKey, WrapIndent},
+    stats::{estimate_cost, model_name},
+    storage::{count_messages, list_messages_page, UsageStats},
+    theme::Theme,
+    vim::VimMode,
 };
 
-pub const SELECTED_STYLE: Style = Style::new()
-    .add_modifier(Modifier::BOLD)
-    .fg(Color::LightBlue)
-    .bg(Color::DarkGray);
-
 /// helper function to create a centered rect using up certain percentage of the available rect `r`
 fn centered_rect(percent_x: u16, percent_y: u16, r: Rect) -> Rect {
     let popup_layout = Layout::vertical([
@@ -46,59 +47,284 @@ fn left_aligned_rect(r: Rect, p: u16) -> Rect {
     Layout::horizontal([Constraint::Fill(1), Constraint::Percentage(100 - p)]).split(r)[0]
 }
 
+/// Width available for message text inside a messages area of the given width.
+pub fn message_text_width(area_width: u16) -> usize {
+    (area_width as usize).saturating_sub(3).max(1)
+}
+
+/// Number of lines a message takes up when rendered `width` columns wide: a
+/// role header, a rule, the wrapped text and a blank line, only the text
+/// prefixed with the role in compact mode, or the text prefixed with its label
+/// and a blank line in plain mode.
+pub fn message_line_count(
+    message: &Message,
+    width: usize,
+    compact: bool,
+    plain: bool,
+    wrap_indent: &WrapIndent,
+) -> usize {
+    if plain {
+        wrap_prefixed_message(message, width, plain_label(message)).len() + 1
+    } else if compact {
+        wrap_prefixed_message(message, width, compact_prefix(message)).len()
+    } else if let Message::Note(text) = message {
+        wrap_message(text, width, 0).len() + 1
+    } else {
+        wrap_message(message.as_str(), width, wrap_indent.of(message)).len() + 3
+    }
+}
+
+/// Role shown before the first line of a message in compact mode.
+fn compact_prefix(message: &Message) -> &'static str {
+    match message {
+        Message::User(_) => "USER: ",
+        Message::Assistant(..) => "ASSISTANT: ",
+        Message::Error(_) => "ERROR: ",
+        Message::Note(_) => "NOTE: ",
+    }
+}
+
+/// Label of a message in plain mode, spelled out for screen readers.
+fn plain_label(message: &Message) -> &'static str {
+    match message {
+        Message::User(_) => "User: ",
+        Message::Assistant(..) => "Assistant: ",
+        Message::Error(_) => "Error: ",
+        Message::Note(_) => "Note: ",
+    }
+}
+
+/// Wraps a message to `width` columns with `prefix` in front of the first
+/// line.
+fn wrap_prefixed_message<'a>(
+    message: &'a Message,
+    width: usize,
+    prefix: &'static str,
+) -> Vec<Cow<'a, str>> {
+    let options = textwrap::Options::new(width.max(1))
+        .initial_indent(prefix)
+        .break_words(true)
+        .word_splitter(textwrap::WordSplitter::NoHyphenation);
+    textwrap::wrap(message.as_str(), options)
+}
+
+/// Wraps a message to `width` columns, indenting continuation lines by
+/// `indent` spaces except in fenced code blocks.
+///
+/// Words longer than a line (URLs, base64 blobs, ...) are broken at character
+/// boundaries so that no line ever overflows the area.
+pub fn wrap_message(text: &str, width: usize, indent: usize) -> Vec<Cow<'_, str>> {
+    let options = textwrap::Options::new(width.max(1))
+        .break_words(true)
+        .word_splitter(textwrap::WordSplitter::NoHyphenation);
+    // The indent can't take up the whole line
+    let indent = " ".repeat(indent.min(width / 2));
+    if indent.is_empty() {
+        return textwrap::wrap(text, options);
+    }
+    let mut lines = Vec::new();
+    let mut in_code_block = false;
+    for line in text.split('\n') {
+        let is_fence = line.trim_start().starts_with("```");
+        let line_options = if in_code_block || is_fence {
+            options.clone()
+        } else {
+            options.clone().subsequent_indent(&indent)
+        };
+        lines.extend(
+            textwrap::wrap(line, line_options)
+                .into_iter()
+                .map(|l| Cow::Owned(l.into_owned())),
+        );
+        if is_fence {
+            in_code_block = !in_code_block;
+        }
+    }
+    lines
+}
+
+/// Layout the lines of the messages are rendered with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct LineLayout {
+    width: usize,
+    compact: bool,
+    plain: bool,
+    show_response_info: bool,
+    wrap_indent: WrapIndent,
+}
+
+/// Rendered lines of the messages, kept between frames so that only the
+/// messages that changed are wrapped again, e.g. while a response is streamed.
+#[derive(Debug, Default)]
+pub struct LineCache {
+    layout: Option<LineLayout>,
+    /// Each rendered message with its lines, in the order of the conversation
+    messages: Vec<(Message, Vec<Line<'static>>)>,
+}
+
+impl LineCache {
+    /// Renders the messages that changed since the last update, or all of
+    /// them when the layout changed.
+    fn update(&mut self, messages: &[Message], layout: LineLayout, theme: &Theme) {
+        if self.layout != Some(layout) {
+            self.layout = Some(layout);
+            self.messages.clear();
+        }
+        let n_unchanged = self
+            .messages
+            .iter()
+            .zip(messages)
+            .take_while(|((cached, _), message)| cached == *message)
+            .count();
+        self.messages.truncate(n_unchanged);
+        for message in &messages[n_unchanged..] {
+            let lines = message_lines(message, layout, theme);
+            self.messages.push((message.clone(), lines));
+        }
+    }
+
+    fn lines(&self) -> impl Iterator<Item = &Line<'static>> {
+        self.messages.iter().flat_map(|(_, lines)| lines)
+    }
+
+    /// The lines of the messages from the newest message to the oldest one.
+    fn lines_newest_first(&self) -> impl Iterator<Item = &Line<'static>> {
+        self.messages.iter().rev().flat_map(|(_, lines)| lines)
+    }
+
+    fn len(&self) -> usize {
+        self.messages.iter().map(|(_, lines)| lines.len()).sum()
+    }
+}
+
+/// Lines of a message: a role header, a rule, the wrapped text and a blank
+/// line.
+fn message_lines(message: &Message, layout: LineLayout, theme: &Theme) -> Vec<Line<'static>> {
+    if layout.plain {
+        let mut line_vec =
+            prefixed_message_lines(message, layout.width, plain_label(message), theme);
+        line_vec.push(Line::from(""));
+        return line_vec;
+    }
+    if layout.compact {
+        return prefixed_message_lines(message, layout.width, compact_prefix(message), theme);
+    }
+    // Notes are a single dim line without a header
+    if let Message::Note(text) = message {
+        let mut line_vec = wrap_message(text, layout.width, 0)
+            .into_iter()
+            .map(|l| Line::from(Span::styled(l.into_owned(), theme.note)))
+            .collect::<Vec<_>>();
+        line_vec.push(Line::from(""));
+        return line_vec;
+    }
+    let (header, style) = match message {
+        Message::User(_) => ("USER:".to_string(), theme.user),
+        Message::Assistant(_, info) => match info.temperature {
+            Some(temperature) if layout.show_response_info => (
+                format!("ASSISTANT (temperature {}):", temperature),
+                theme.assistant,
+            ),
+            _ => ("ASSISTANT:".to_string(), theme.assistant),
+        },
+        Message::Error(_) => ("ERROR:".to_string(), theme.error),
+        Message::Note(_) => ("NOTE:".to_string(), theme.note),
+    };
+    let mut line_vec = vec![
+        Line::from(Span::styled(header, style).bold()),
+        Line::from(Span::styled(
+            "─".repeat(layout.width),
+            style.patch(theme.separator),
+        )),
+    ];
+    line_vec.extend(
+        wrap_message(
+            message.as_str(),
+            layout.width,
+            layout.wrap_indent.of(message),
+        )
+        .into_iter()
+        .map(|l| Line::from(Span::styled(l.into_owned(), style))),
+    );
+    line_vec.push(Line::from(""));
+    line_vec
+}
+
 fn render_messages(f: &mut Frame, app: &mut App, messages_area: Rect) {
-    let messages: Vec<Line> = app
-        .messages
-        .iter()
-        .flat_map(|m| {
-            let wrapped_message = textwrap::wrap(m.as_ref(), messages_area.width as usize - 3);
-            let mut line_vec = Vec::new();
-            match m {
-                Message::User(_) => {
-                    line_vec.push(Line::from(Span::raw("USER:").bold().yellow()));
-                    line_vec.push(Line::from(Span::raw("---").bold().yellow()));
-                    line_vec.extend(
-                        wrapped_message
-                            .into_iter()
-                            .map(|l| Line::from(Span::raw(l).yellow())),
-                    );
-                    line_vec.push(Line::from(Span::raw("").bold().yellow()));
-                }
-                Message::Assistant(_) => {
-                    line_vec.push(Line::from(Span::raw("ASSISTANT:").bold().green()));
-                    line_vec.push(Line::from(Span::raw("---").bold().green()));
-                    line_vec.extend(
-                        wrapped_message
-                            .into_iter()
-                            .map(|l| Line::from(Span::raw(l).green())),
-                    );
-                    line_vec.push(Line::from(Span::raw("").bold().green()));
-                }
-                Message::Error(_) => {
-                    line_vec.push(Line::from(Span::raw("ERROR:").bold().red()));
-                    line_vec.push(Line::from(Span::raw("---").bold().red()));
-                    line_vec.extend(
-                        wrapped_message
-                            .into_iter()
-                            .map(|l| Line::from(Span::raw(l).red())),
-                    );
-                    line_vec.push(Line::from(Span::raw("").bold().red()));
-                }
-            }
-            line_vec
-        })
-        .collect();
+    let layout = LineLayout {
+        width: message_text_width(messages_area.width),
+        compact: app.compact,
+        plain: app.plain,
+        show_response_info: app.show_response_info,
+        wrap_indent: app.wrap_indent,
+    };
+    app.line_cache.update(&app.messages, layout, &app.theme);
+    // The response being streamed changes with every chunk and isn't cached
+    let streaming_lines = app
+        .streaming_message()
+        .map(|m| message_lines(&m, layout, &app.theme))
+        .unwrap_or_default();
+    let n_lines = app.line_cache.len() + streaming_lines.len();
+    // Only the visible lines are handed to the paragraph
+    let visible_lines: Vec<Line> = if app.newest_first {
+        // New lines are added above the view: it follows them at the top and
+        // otherwise moves down with the lines it shows
+        if app.vertical_scroll > 0 {
+            app.vertical_scroll = (app.vertical_scroll + n_lines)
+                .saturating_sub(app.rendered_lines)
+                .min(n_lines.saturating_sub(2));
+        }
+        streaming_lines
+            .iter()
+            .chain(app.line_cache.lines_newest_first())
+            .skip(app.vertical_scroll)
+            .take(messages_area.height as usize)
+            .cloned()
+            .collect()
+    } else {
+        app.line_cache
+            .lines()
+            .chain(&streaming_lines)
+            .skip(app.vertical_scroll)
+            .take(messages_area.height as usize)
+            .cloned()
+            .collect()
+    };
+    app.rendered_lines = n_lines;
 
     let scrollbar = Scrollbar::new(ScrollbarOrientation::VerticalRight)
         .begin_symbol(Some("↑"))
         .end_symbol(Some("↓"));
 
-    let mut scrollbar_state = ScrollbarState::new(messages.len()).position(app.vertical_scroll);
+    let mut scrollbar_state = ScrollbarState::new(n_lines).position(app.vertical_scroll);
+    let mut conversation_label = match app.conversation_id {
+        Some(id) => format!("#{}", id),
+        None => "(unsaved)".to_string(),
+    };
+    if let Some(project) = &app.project {
+        conversation_label.push_str(&format!(" (project {})", project));
+    }
+    if app.read_only {
+        conversation_label.push_str(" (read-only)");
+    }
+    let mut model_label = app.selected_model_name.clone();
+    // Other conversations keep the system prompt they were started with
+    if let Some(context_label) = app
+        .context_label
+        .as_ref()
+        .filter(|_| app.system_prompt == app.default_system_prompt)
+    {
+        model_label.push_str(&format!(" - {}", context_label));
+    }
 
-    let messages_text = Text::from(messages);
-    let messages = Paragraph::new(messages_text)
-        .scroll((app.vertical_scroll as u16, 0))
-        .block(Block::bordered().title(format!("Chat - {}", app.selected_model_name)));
+    let title = format!("Chat {} - {}", conversation_label, model_label);
+    if app.plain {
+        // The title on its own line, then the messages, nothing else
+        let messages = Paragraph::new(Text::from(visible_lines)).block(Block::new().title(title));
+        f.render_widget(messages, messages_area);
+        return;
+    }
+    let messages = Paragraph::new(Text::from(visible_lines)).block(Block::bordered().title(title));
 
     f.render_widget(messages, messages_area);
 
@@ -113,14 +339,43 @@ fn render_messages(f: &mut Frame, app: &mut App, messages_area: Rect) {
     );
 }
 
+/// Lines of a message in compact and plain mode, with `prefix` in bold in
+/// front of the first line.
+fn prefixed_message_lines(
+    message: &Message,
+    width: usize,
+    prefix: &'static str,
+    theme: &Theme,
+) -> Vec<Line<'static>> {
+    let style = match message {
+        Message::User(_) => theme.user,
+        Message::Assistant(..) => theme.assistant,
+        Message::Error(_) => theme.error,
+        Message::Note(_) => theme.note,
+    };
+    wrap_prefixed_message(message, width, prefix)
+        .into_iter()
+        .enumerate()
+        .map(|(i, line)| match line.strip_prefix(prefix) {
+            Some(rest) if i == 0 => Line::from(vec![
+                Span::styled(prefix, style).bold(),
+                Span::styled(rest.to_string(), style),
+            ]),
+            _ => Line::from(Span::styled(line.into_owned(), style)),
+        })
+        .collect()
+}
+
 pub fn render(f: &mut Frame, app: &mut App) {
-    f.render_widget(
-        Block::bordered()
-            .title("AI in the Terminal")
-            .title_alignment(Alignment::Center)
-            .border_type(BorderType::Rounded),
-        f.area(),
-    );
+    if !app.plain {
+        f.render_widget(
+            Block::bordered()
+                .title("AI in the Terminal")
+                .title_alignment(Alignment::Center)
+                .border_type(BorderType::Rounded),
+            f.area(),
+        );
+    }
 
     let input_area_constraint = match app.app_mode {
         AppMode::Editing => Constraint::Min(1),
@@ -144,6 +399,22 @@ pub fn render(f: &mut Frame, app: &mut App) {
         AppMode::Editing => {
             render_messages(f, app, messages_area);
             f.render_widget(&app.input_textarea, input_area);
+            if app.input_keys == InputKeys::Vim {
+                // The submode is shown on the bottom border of the input
+                let label = match app.vim.mode {
+                    VimMode::Insert => " -- INSERT -- ",
+                    VimMode::Normal => " -- NORMAL -- ",
+                };
+                let border = Rect {
+                    y: input_area.bottom().saturating_sub(1),
+                    height: 1,
+                    ..input_area.inner(Margin {
+                        vertical: 0,
+                        horizontal: 1,
+                    })
+                };
+                f.render_widget(Line::from(label).right_aligned(), border);
+            }
         }
         AppMode::ModelSelection => {
             let block = Block::bordered().title("Select Model");
@@ -163,18 +434,66 @@ pub fn render(f: &mut Frame, app: &mut App) {
             let preview_area = right_aligned_rect(messages_area, 40);
             f.render_widget(Clear, preview_area); //this clears out the background
             f.render_widget(preview_block, preview_area);
-            let preview_text = app.get_snippet_text();
             let preview_block_content = Block::new().padding(Padding::uniform(1));
-            if let Some(preview_text) = preview_text {
-                let snippet_paragraph = Paragraph::new(Text::from(preview_text.as_str()).magenta())
+            if let Some(preview_lines) = app.get_highlighted_snippet() {
+                let mut scrollbar_state = ScrollbarState::new(preview_lines.len())
+                    .position(app.snippet_preview_vscroll as usize);
+                let snippet_paragraph = Paragraph::new(Text::from(preview_lines))
+                    .scroll((app.snippet_preview_vscroll, app.snippet_preview_hscroll))
                     .block(preview_block_content);
                 f.render_widget(snippet_paragraph, preview_area);
+                if !app.plain {
+                    f.render_stateful_widget(
+                        Scrollbar::new(ScrollbarOrientation::VerticalRight),
+                        preview_area.inner(Margin {
+                            vertical: 1,
+                            horizontal: 0,
+                        }),
+                        &mut scrollbar_state,
+                    );
+                }
             }
         }
-        AppMode::ShowHistory => {
+        AppMode::VariationSelection => {
+            let block = Block::bordered().title("Select Variation");
+            let area = centered_rect(20, 100, messages_area);
+            f.render_widget(Clear, area); //this clears out the background
+            f.render_widget(block, area);
+            render_variation_list(f, area, app);
+
+            let diff = if app.variation_list.show_diff {
+                app.variation_list.selected_diff(&app.theme).cloned()
+            } else {
+                None
+            };
+            let preview_block = Block::bordered().title(if diff.is_some() {
+                "Variation Preview (diff)"
+            } else {
+                "Variation Preview"
+            });
+            let preview_area = right_aligned_rect(messages_area, 40);
+            f.render_widget(Clear, preview_area); //this clears out the background
+            f.render_widget(preview_block, preview_area);
+            let preview_block_content = Block::new().padding(Padding::uniform(1));
+            if let Some(preview_text) = app.get_variation_text() {
+                let text =
+                    diff.unwrap_or_else(|| Text::from(preview_text).style(app.theme.assistant));
+                let variation_paragraph = Paragraph::new(text)
+                    .wrap(Wrap { trim: false })
+                    .block(preview_block_content);
+                f.render_widget(variation_paragraph, preview_area);
+            }
+        }
+        AppMode::ShowHistory | AppMode::HistoryFilter => {
             let block = Block::bordered().title("Select Chat");
-            let area = left_aligned_rect(messages_area, 25);
+            let mut area = left_aligned_rect(messages_area, 25);
             f.render_widget(Clear, area); //this clears out the background
+            if matches!(app.app_mode, AppMode::HistoryFilter) || !app.history_filter.is_empty() {
+                let [filter_area, list_area] =
+                    Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(area);
+                f.render_widget(&app.history_filter_textarea, filter_area);
+                area = list_area;
+            }
             f.render_widget(block, area);
             render_chat_history_list(f, area, app);
 
@@ -183,29 +502,86 @@ pub fn render(f: &mut Frame, app: &mut App) {
             f.render_widget(Clear, preview_area); //this clears out the background
             f.render_widget(preview_block, preview_area);
             let chat_id = app.get_selected_chat_id();
-            let preview_text = if let Some(id) = chat_id {
-                let text = list_all_messages(*id)
-                    .unwrap_or([].to_vec())
-                    .into_iter()
-                    .map(|m| match m {
-                        Message::User(t) => format!("USER: {}\n", t),
-                        Message::Assistant(t) => format!("ASSISTANT: {}\n", t),
-                        Message::Error(t) => format!("ERROR: {}\n", t),
-                    })
-                    .collect::<Vec<String>>()
-                    .join("\n");
-                Some(text)
-            } else {
-                None
-            };
+            let preview_text = chat_id
+                .map(|id| history_preview(*id, app.history_preview_messages).unwrap_or_default());
             let preview_block_content = Block::new().padding(Padding::uniform(1));
             if let Some(preview_text) = preview_text {
-                let snippet_paragraph = Paragraph::new(Text::from(preview_text.as_str()).magenta())
+                let text =
+                    highlight_matches(&preview_text, &app.history_filter, app.theme.search_match)
+                        .style(app.theme.preview);
+                let snippet_paragraph = Paragraph::new(text)
                     .wrap(Wrap { trim: true })
                     .block(preview_block_content);
                 f.render_widget(snippet_paragraph, preview_area);
             }
         }
+        AppMode::ChatSwitcher => {
+            render_messages(f, app, messages_area);
+            let area = centered_rect(60, 70, messages_area);
+            f.render_widget(Clear, area); //this clears out the background
+            let [search_area, list_area] =
+                Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(area);
+            f.render_widget(&app.chat_switcher_textarea, search_area);
+            f.render_widget(Block::bordered(), list_area);
+            render_chat_switcher_list(f, list_area, app);
+        }
+        AppMode::TemplateSelection => {
+            render_messages(f, app, messages_area);
+            let block = Block::bordered().title("Select Template");
+            let area = centered_rect(20, 100, messages_area);
+            f.render_widget(Clear, area); //this clears out the background
+            f.render_widget(block, area);
+            render_template_list(f, area, app);
+
+            let preview_block = Block::bordered().title("Template Preview");
+            let preview_area = right_aligned_rect(messages_area, 40);
+            f.render_widget(Clear, preview_area); //this clears out the background
+            f.render_widget(preview_block, preview_area);
+            if let Some(template) = app.template_list.selected() {
+                let template_paragraph =
+                    Paragraph::new(Text::from(template.text.as_str()).style(app.theme.user))
+                        .wrap(Wrap { trim: false })
+                        .block(Block::new().padding(Padding::uniform(1)));
+                f.render_widget(template_paragraph, preview_area);
+            }
+        }
+        AppMode::TemplateNameEntry => {
+            render_messages(f, app, messages_area);
+            let area = centered_rect(40, 50, messages_area);
+            let [area, _] =
+                Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(area);
+            f.render_widget(Clear, area); //this clears out the background
+            f.render_widget(&app.template_name_textarea, area);
+        }
+        AppMode::CustomModelEntry => {
+            render_messages(f, app, messages_area);
+            let area = centered_rect(40, 50, messages_area);
+            let [area, _] =
+                Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(area);
+            f.render_widget(Clear, area); //this clears out the background
+            f.render_widget(&app.custom_model_textarea, area);
+        }
+        AppMode::TemperatureEntry => {
+            render_messages(f, app, messages_area);
+            let area = centered_rect(30, 50, messages_area);
+            let [area, _] =
+                Layout::vertical([Constraint::Length(3), Constraint::Fill(1)]).areas(area);
+            f.render_widget(Clear, area); //this clears out the background
+            f.render_widget(&app.temperature_textarea, area);
+        }
+        AppMode::SystemPromptEditing => {
+            render_messages(f, app, messages_area);
+            let area = centered_rect(60, 50, messages_area);
+            f.render_widget(Clear, area); //this clears out the background
+            f.render_widget(&app.system_prompt_textarea, area);
+        }
+        AppMode::Dashboard => {
+            let block = Block::bordered().title("Statistics");
+            let area = centered_rect(60, 80, messages_area);
+            f.render_widget(Clear, area); //this clears out the background
+            render_dashboard(f, block.inner(area), &app.usage_stats, &app.theme);
+            f.render_widget(block, area);
+        }
         AppMode::Help => {
             let block = Block::bordered().title("Help");
             let area = centered_rect(50, 60, messages_area);
@@ -214,53 +590,117 @@ pub fn render(f: &mut Frame, app: &mut App) {
 
             let normal_keys = vec![
                 "Press ".into(),
-                "Esc/q".bold(),
+                quit_keys(app).bold(),
                 " to exit, ".into(),
                 "i".bold(),
                 " to start editing, ".into(),
+                "o".bold(),
+                " to start editing in a new chat, ".into(),
                 "y".bold(),
-                " to copy the last answer (not linux yet), ".into(),
+                " to copy the last answer, ".into(),
+                "Y".bold(),
+                " to copy it as markdown, ".into(),
+                "c".bold(),
+                " to copy the last code block, ".into(),
+                "e".bold(),
+                " to copy it and open it in your editor, ".into(),
                 "m".bold(),
                 " to choose model, ".into(),
                 "h".bold(),
                 " to browse previous conversations, ".into(),
+                "p".bold(),
+                " to edit the system prompt before the first message, ".into(),
                 "s".bold(),
-                " to browse code snippets.".into(),
+                " to browse code snippets, ".into(),
+                "v".bold(),
+                " to generate several variations of the last answer, ".into(),
+                "a".bold(),
+                " to answer the last message again with another model, ".into(),
+                "R".bold(),
+                " to retry a request that failed, ".into(),
+                "I".bold(),
+                " to show the temperature of each answer, ".into(),
+                "C".bold(),
+                " to switch to a compact layout, ".into(),
+                "M".bold(),
+                " to let the terminal select text instead of the app, ".into(),
+                "t".bold(),
+                " to change the temperature, ".into(),
+                "D".bold(),
+                " to show statistics of all conversations, ".into(),
+                "T".bold(),
+                " to choose a message template, ".into(),
+                "Ctrl + P".bold(),
+                " to quickly switch to a chat by searching its first message, ".into(),
+                "S".bold(),
+                " to switch between streamed and complete answers.".into(),
             ];
             let editing_keys = vec![
                 "Press ".into(),
                 "Esc".bold(),
                 " to stop editing. Press ".into(),
                 "CONTROL + S (C-s)".bold(),
-                " to submit the message. ".into(),
-                "Paste into the text area by pressing ".into(),
+                " to submit the message, or ".into(),
+                "CONTROL + T (C-t)".bold(),
+                " to insert a template. ".into(),
+                "Paste into the text area using the terminal, or by pressing ".into(),
                 "Ctrl + V".bold(),
+                " (not on Linux).".into(),
             ];
             let model_keys = vec![
                 "Press ".into(),
                 "Up/Down".bold(),
                 " to select model, or press ".into(),
                 "Enter".bold(),
-                " to select model, and return to 'normal' mode.".into(),
+                " to select model, and return to 'normal' mode. Press ".into(),
+                "c".bold(),
+                " to enter a model name that isn't listed. Press ".into(),
+                "Ctrl + R".bold(),
+                " to look for new models.".into(),
             ];
             let chat_keys = vec![
                 "Press ".into(),
                 "Up/Down".bold(),
                 " to select chat, or press ".into(),
                 "d".bold(),
-                " to delete the selected chat, or press ".into(),
+                " to delete the selected chat, ".into(),
+                "f".bold(),
+                " to star it so it's listed first, ".into(),
+                "m".bold(),
+                " twice to merge the current chat into it, ".into(),
+                "/".bold(),
+                " to filter the chats by their messages, or press ".into(),
                 "Enter".bold(),
                 " to select a chat, and return to 'normal' mode.".into(),
             ];
             let snippet_keys = vec![
                 "Press ".into(),
                 "Up/Down".bold(),
-                " to select snippet, or press ".into(),
+                " to select snippet, ".into(),
+                "Left/Right".bold(),
+                " and ".into(),
+                "J/K".bold(),
+                " to scroll the preview, or press ".into(),
                 "Enter".bold(),
-                " to copy snippet to the clipboard (not linux yet), and return to 'normal' mode."
+                " to copy snippet to the clipboard, and return to 'normal' mode. Press ".into(),
+                "E".bold(),
+                " to write all snippets to files in ./ait-snippets. Press ".into(),
+                "p".bold(),
+                " to pin a snippet (marked with *) so it is kept when switching conversations. Press "
                     .into(),
+                "x".bold(),
+                " twice to quit and print a shell snippet, if enabled in the config.".into(),
             ];
-            let msg = vec![
+            let variation_keys = vec![
+                "Press ".into(),
+                "Up/Down".bold(),
+                " to select a variation, ".into(),
+                "d".bold(),
+                " to show what changed from the previous answer, or press ".into(),
+                "Enter".bold(),
+                " to keep it as the answer, and return to 'normal' mode.".into(),
+            ];
+            let mut msg = vec![
                 Line::from(Span::raw("Welcome to AI in the Terminal! ").bold()),
                 Line::from(""),
                 Line::from(Span::raw("When in 'normal' mode, you can:").bold()),
@@ -277,17 +717,82 @@ pub fn render(f: &mut Frame, app: &mut App) {
                 Line::from(""),
                 Line::from(Span::raw("When browsing snippets, you can:").bold()),
                 Line::from(snippet_keys),
+                Line::from(""),
+                Line::from(Span::raw("When choosing variations, you can:").bold()),
+                Line::from(variation_keys),
             ];
+            if !app.model_warnings.is_empty() {
+                msg.push(Line::from(""));
+                msg.push(Line::from(
+                    Span::styled("Problems discovering models:", app.theme.error).bold(),
+                ));
+                msg.extend(
+                    app.model_warnings
+                        .iter()
+                        .map(|w| Line::from(Span::styled(w.as_str(), app.theme.error))),
+                );
+            }
             let help_text_block = Block::new().padding(Padding::uniform(1));
+            let inner = help_text_block.inner(area);
+            // The end of the help depends on the size of the area
+            let n_lines: usize = msg
+                .iter()
+                .map(|line| wrap_message(&line.to_string(), inner.width as usize, 0).len())
+                .sum();
+            let max_scroll = n_lines.saturating_sub(inner.height as usize);
+            app.help_scroll = app
+                .help_scroll
+                .min(u16::try_from(max_scroll).unwrap_or(u16::MAX));
             let text = Text::from(msg).patch_style(Style::default());
             let help_message = Paragraph::new(text)
                 .block(help_text_block)
-                .wrap(Wrap { trim: true });
+                .wrap(Wrap { trim: true })
+                .scroll((app.help_scroll, 0));
             f.render_widget(help_message, area);
         }
     }
 
     let msg = match app.app_mode {
+        AppMode::CustomModelEntry => {
+            vec![
+                "Press ".into(),
+                "Esc".bold(),
+                " to go back to the model list. Press ".into(),
+                "Enter".bold(),
+                " to use the model.".into(),
+            ]
+        }
+        AppMode::TemperatureEntry => {
+            vec![
+                "Press ".into(),
+                "Esc".bold(),
+                " to keep the current temperature. Press ".into(),
+                "Enter".bold(),
+                " to use the entered temperature.".into(),
+            ]
+        }
+        AppMode::SystemPromptEditing => {
+            vec![
+                "Press ".into(),
+                "Esc".bold(),
+                " to discard changes. Press ".into(),
+                "CONTROL + S (C-s)".bold(),
+                " to use this system prompt.".into(),
+            ]
+        }
+        AppMode::Editing if app.input_keys == InputKeys::Vim => {
+            let esc = match app.vim.mode {
+                VimMode::Insert => " for the vim normal mode. Press ",
+                VimMode::Normal => " to stop editing, or i to insert text. Press ",
+            };
+            vec![
+                "Press ".into(),
+                "Esc".bold(),
+                esc.into(),
+                "CONTROL + S (C-s)".bold(),
+                " to submit the message.".into(),
+            ]
+        }
         AppMode::Editing => {
             vec![
                 "Press ".into(),
@@ -297,52 +802,242 @@ pub fn render(f: &mut Frame, app: &mut App) {
                 " to submit the message.".into(),
             ]
         }
+        AppMode::Dashboard => {
+            vec![
+                "Press ".into(),
+                "Esc".bold(),
+                " to close the statistics.".into(),
+            ]
+        }
+        AppMode::Help => {
+            vec![
+                "Press ".into(),
+                "Up/Down".bold(),
+                " to scroll the help. Press ".into(),
+                "Esc".bold(),
+                " to close it.".into(),
+            ]
+        }
+        AppMode::HistoryFilter => {
+            vec![
+                "Type to show the chats with a message containing the text, press ".into(),
+                "Enter".bold(),
+                " to browse them or ".into(),
+                "Esc".bold(),
+                " to show all chats.".into(),
+            ]
+        }
+        AppMode::ChatSwitcher => {
+            vec![
+                "Type to search the chats, press ".into(),
+                "Up/Down".bold(),
+                " to select a chat, ".into(),
+                "Enter".bold(),
+                " to open it or ".into(),
+                "Esc".bold(),
+                " to close the switcher.".into(),
+            ]
+        }
+        AppMode::TemplateSelection => {
+            vec![
+                "Press ".into(),
+                "Enter".bold(),
+                " to insert the template, ".into(),
+                "a".bold(),
+                " to save the input as a template, ".into(),
+                "d".bold(),
+                " to delete the template or ".into(),
+                "Esc".bold(),
+                " to go back to editing.".into(),
+            ]
+        }
+        AppMode::TemplateNameEntry => {
+            vec![
+                "Press ".into(),
+                "Esc".bold(),
+                " to go back to the templates. Press ".into(),
+                "Enter".bold(),
+                " to save the input under this name.".into(),
+            ]
+        }
+        AppMode::Normal if app.can_retry() => {
+            vec![
+                "The request failed. Press ".into(),
+                "R".bold(),
+                " to retry or ".into(),
+                "i".bold(),
+                " to enter text.".into(),
+            ]
+        }
         _ => {
             vec![
                 "Press ".into(),
-                "Esc/q".bold(),
+                quit_keys(app).bold(),
                 " to exit. Press ".into(),
                 "i".bold(),
                 " to enter text. Press ".into(),
                 "?".bold(),
-                " for help.".into(),
+                format!(" for help. Temperature: {}.", app.temperature).into(),
             ]
         }
     };
-    let text = Text::from(Line::from(msg)).patch_style(Style::default());
+    let text = match &app.status {
+        Some(status) if status.is_error => Text::from(status.text.as_str()).style(app.theme.error),
+        Some(status) => Text::from(status.text.as_str()).style(app.theme.status),
+        None => Text::from(Line::from(msg)).patch_style(Style::default()),
+    };
     let help_message = Paragraph::new(text);
-    f.render_widget(help_message, help_area);
+    match context_limit(&app.selected_model_name) {
+        Some(limit) => {
+            let [help_area, gauge_area] =
+                Layout::horizontal([Constraint::Min(1), Constraint::Length(22)])
+                    .spacing(1)
+                    .areas(help_area);
+            f.render_widget(help_message, help_area);
+            render_context_gauge(f, app, limit, gauge_area);
+        }
+        None => f.render_widget(help_message, help_area),
+    }
 
-    #[cfg(not(target_os = "linux"))]
-    {
-        if let Some(cells) = app.selection.iter_selected_cells() {
-            for (col, row) in cells {
-                let cell = f.buffer_mut().cell_mut((col, row));
-                // Modify the cell style to show selection
-                if let Some(cell) = cell {
-                    cell.set_style(SELECTED_STYLE);
-                }
+    if let Some(cells) = app.selection.iter_selected_cells(f.area()) {
+        for (col, row) in cells {
+            let cell = f.buffer_mut().cell_mut((col, row));
+            // Modify the cell style to show selection
+            if let Some(cell) = cell {
+                cell.set_style(app.theme.selection);
             }
         }
+    }
 
-        if let Some(selected_text) = app.selection.get_selected_text(f.buffer_mut()) {
+    // Remember the selected text, it is copied when the mouse button is released
+    app.selection.text = app
+        .selection
+        .get_selected_text(f.buffer_mut())
+        .map(|selected_text| {
             // Trim whitespace from the selected text for each line
-            let selected_text: String = selected_text
+            selected_text
                 .lines()
                 .map(str::trim_end)
                 .collect::<Vec<&str>>()
-                .join("\n");
-            app.clipboard.set_text(&selected_text).unwrap();
-        }
+                .join("\n")
+        });
+}
+
+/// Number of models shown in the dashboard.
+const DASHBOARD_MODELS: usize = 5;
+
+fn render_dashboard(f: &mut Frame, area: Rect, stats: &UsageStats, theme: &Theme) {
+    let [summary_area, activity_area, models_area] = Layout::vertical([
+        Constraint::Length(4),
+        Constraint::Length(6),
+        Constraint::Min(3),
+    ])
+    .margin(1)
+    .areas(area);
+
+    let most_used_model = stats
+        .models
+        .iter()
+        .find(|usage| usage.model.is_some())
+        .map_or("-".to_string(), model_name);
+    let summary = vec![
+        Line::from(vec![
+            "Conversations: ".bold(),
+            stats.n_conversations.to_string().into(),
+            "   Messages: ".bold(),
+            stats.n_messages.to_string().into(),
+        ]),
+        Line::from(vec![
+            "Tokens: ".bold(),
+            format!("~{}", estimate_tokens(stats.n_characters)).into(),
+            "   Estimated cost of responses: ".bold(),
+            format!("${:.2}", estimate_cost(&stats.models)).into(),
+        ]),
+        Line::from(vec!["Most used model: ".bold(), most_used_model.into()]),
+    ];
+    f.render_widget(Paragraph::new(summary), summary_area);
+
+    let messages_per_day = stats
+        .messages_per_day
+        .iter()
+        .map(|(_, n)| *n as u64)
+        .collect::<Vec<_>>();
+    let activity = Sparkline::default()
+        .block(Block::bordered().title(format!(
+            "Messages per day, last {} days",
+            messages_per_day.len()
+        )))
+        .data(&messages_per_day)
+        .style(theme.chart);
+    f.render_widget(activity, activity_area);
+
+    let bars = stats
+        .models
+        .iter()
+        .take(DASHBOARD_MODELS)
+        .map(|usage| {
+            Bar::default()
+                .value(usage.n_responses as u64)
+                .label(Line::from(model_name(usage)))
+        })
+        .collect::<Vec<_>>();
+    let models = BarChart::default()
+        .block(Block::bordered().title("Responses per model"))
+        .direction(Direction::Horizontal)
+        .bar_width(1)
+        .bar_gap(0)
+        .bar_style(theme.assistant)
+        .data(BarGroup::default().bars(&bars));
+    f.render_widget(models, models_area);
+}
+
+/// Share of the context window above which the gauge turns red.
+const CONTEXT_WARNING_RATIO: f64 = 0.9;
+
+/// Renders how full the context window of the selected model is.
+fn render_context_gauge(f: &mut Frame, app: &mut App, limit: usize, area: Rect) {
+    let tokens = app.context_tokens();
+    let ratio = (tokens as f64 / limit as f64).min(1.0);
+    let style = if ratio >= CONTEXT_WARNING_RATIO {
+        app.theme.error
+    } else {
+        app.theme.chart
+    };
+    let gauge = Gauge::default()
+        .gauge_style(style)
+        .ratio(ratio)
+        .label(format!(
+            "~{}/{} tokens",
+            short_count(tokens),
+            short_count(limit)
+        ))
+        .use_unicode(true);
+    f.render_widget(gauge, area);
+}
+
+/// Formats a count with a `k` or `M` suffix, e.g. `128k`.
+fn short_count(n: usize) -> String {
+    match n {
+        0..1_000 => n.to_string(),
+        1_000..1_000_000 => format!("{}k", n / 1_000),
+        _ => format!("{}M", n / 1_000_000),
     }
 }
 
+/// Renders a message in place of a list without items.
+fn render_empty_state(f: &mut Frame, area: Rect, block: Block, message: &str, style: Style) {
+    let p = Paragraph::new(Text::from(message).style(style))
+        .wrap(Wrap { trim: true })
+        .block(block);
+    f.render_widget(p, area);
+}
+
 fn render_model_list(f: &mut Frame, area: Rect, app: &mut App) {
     let block = Block::new().padding(Padding::uniform(1));
     if app.model_list.items.is_empty() {
         let p = Paragraph::new(
             Text::from("No API keys detected, no running Ollama detected. Unable to choose model.")
-                .red(),
+                .style(app.theme.error),
         )
         .wrap(Wrap { trim: true })
         .block(block);
@@ -355,7 +1050,7 @@ fn render_model_list(f: &mut Frame, area: Rect, app: &mut App) {
     // Create a List from all list items and highlight the currently selected one
     let list = List::new(items)
         .block(block)
-        .highlight_style(SELECTED_STYLE)
+        .highlight_style(app.theme.list_highlight)
         .highlight_symbol(">")
         .highlight_spacing(HighlightSpacing::Always);
 
@@ -366,6 +1061,16 @@ fn render_model_list(f: &mut Frame, area: Rect, app: &mut App) {
 
 fn render_snippet_list(f: &mut Frame, area: Rect, app: &mut App) {
     let block = Block::new().padding(Padding::uniform(1));
+    if app.snippet_list.items.is_empty() {
+        render_empty_state(
+            f,
+            area,
+            block,
+            "No snippets in this conversation",
+            app.theme.hint,
+        );
+        return;
+    }
 
     // Iterate through all elements in the `items` and stylize them.
     let items: Vec<ListItem> = app
@@ -375,7 +1080,8 @@ fn render_snippet_list(f: &mut Frame, area: Rect, app: &mut App) {
         .enumerate()
         .map(|(i, s)| {
             ListItem::from(format!(
-                "Snippet {}: {}...",
+                "{}Snippet {}: {}...",
+                if s.pinned { "* " } else { "" },
                 i + 1,
                 s.text[..min(10, s.text.len())].to_owned()
             ))
@@ -385,7 +1091,7 @@ fn render_snippet_list(f: &mut Frame, area: Rect, app: &mut App) {
     // Create a List from all list items and highlight the currently selected one
     let list = List::new(items)
         .block(block)
-        .highlight_style(SELECTED_STYLE)
+        .highlight_style(app.theme.list_highlight)
         .highlight_symbol(">")
         .highlight_spacing(HighlightSpacing::Always);
 
@@ -394,21 +1100,166 @@ fn render_snippet_list(f: &mut Frame, area: Rect, app: &mut App) {
     f.render_stateful_widget(list, area, &mut app.snippet_list.state);
 }
 
+fn render_chat_switcher_list(f: &mut Frame, area: Rect, app: &mut App) {
+    let block = Block::new().padding(Padding::horizontal(1));
+    let area = area.inner(Margin {
+        vertical: 1,
+        horizontal: 0,
+    });
+    if app.chat_switcher.matches.is_empty() {
+        render_empty_state(f, area, block, "No matching chats", app.theme.hint);
+        return;
+    }
+    let items: Vec<ListItem> = app
+        .chat_switcher
+        .matches
+        .iter()
+        .map(|&i| {
+            let summary = &app.chat_switcher.summaries[i];
+            ListItem::new(Line::from(vec![
+                Span::raw(summary_title(summary)),
+                Span::styled(format!("  {}", summary.started_at), app.theme.muted),
+            ]))
+        })
+        .collect();
+
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(app.theme.list_highlight)
+        .highlight_symbol(">")
+        .highlight_spacing(HighlightSpacing::Always);
+    f.render_stateful_widget(list, area, &mut app.chat_switcher.state);
+}
+
+fn render_template_list(f: &mut Frame, area: Rect, app: &mut App) {
+    let block = Block::new().padding(Padding::uniform(1));
+    if app.template_list.items.is_empty() {
+        render_empty_state(
+            f,
+            area,
+            block,
+            "No templates, press a to save the input as one",
+            app.theme.hint,
+        );
+        return;
+    }
+    let items: Vec<ListItem> = app
+        .template_list
+        .items
+        .iter()
+        .map(|t| ListItem::from(t.name.as_str()))
+        .collect();
+
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(app.theme.list_highlight)
+        .highlight_symbol(">")
+        .highlight_spacing(HighlightSpacing::Always);
+    f.render_stateful_widget(list, area, &mut app.template_list.state);
+}
+
+fn render_variation_list(f: &mut Frame, area: Rect, app: &mut App) {
+    let block = Block::new().padding(Padding::uniform(1));
+
+    // Iterate through all elements in the `items` and stylize them.
+    let items: Vec<ListItem> = app
+        .variation_list
+        .items
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            let label = if v.is_error() {
+                format!("Variation {}: error", i + 1)
+            } else {
+                format!("Variation {}", i + 1)
+            };
+            ListItem::from(label)
+        })
+        .collect();
+
+    // Create a List from all list items and highlight the currently selected one
+    let list = List::new(items)
+        .block(block)
+        .highlight_style(app.theme.list_highlight)
+        .highlight_symbol(">")
+        .highlight_spacing(HighlightSpacing::Always);
+
+    f.render_stateful_widget(list, area, &mut app.variation_list.state);
+}
+
+/// Formats the messages of a chat preview, noting how many earlier messages
+/// were left out.
+fn format_history_preview(messages: &[Message], omitted: usize) -> String {
+    let mut parts = Vec::new();
+    if omitted > 0 {
+        parts.push(format!("... {} earlier message(s) not shown\n", omitted));
+    }
+    parts.extend(messages.iter().map(|m| match m {
+        Message::User(t) => format!("USER: {}\n", t),
+        Message::Assistant(t, _) => format!("ASSISTANT: {}\n", t),
+        Message::Error(t) => format!("ERROR: {}\n", t),
+        Message::Note(t) => format!("NOTE: {}\n", t),
+    }));
+    parts.join("\n")
+}
+
+/// Emphasizes the occurrences of `term` in `text`, ignoring ASCII case like
+/// the search of the chat history.
+fn highlight_matches<'a>(text: &'a str, term: &str, style: Style) -> Text<'a> {
+    if term.is_empty() {
+        return Text::from(text);
+    }
+    let term = term.to_ascii_lowercase();
+    let lines = text.lines().map(|line| {
+        // Lowercasing ASCII keeps the byte offsets of the line
+        let lowercase = line.to_ascii_lowercase();
+        let mut spans = Vec::new();
+        let mut end = 0;
+        for (start, _) in lowercase.match_indices(&term) {
+            if start < end {
+                continue;
+            }
+            spans.push(Span::raw(&line[end..start]));
+            end = start + term.len();
+            spans.push(Span::styled(&line[start..end], style));
+        }
+        spans.push(Span::raw(&line[end..]));
+        Line::from(spans)
+    });
+    Text::from(lines.collect::<Vec<Line>>())
+}
+
+/// Previews the latest `limit` messages of a chat, so that huge chats don't
+/// slow down browsing the history.
+fn history_preview(conversation_id: i64, limit: usize) -> AppResult<String> {
+    let total = count_messages(conversation_id)?;
+    let omitted = total.saturating_sub(limit);
+    let messages = list_messages_page(conversation_id, omitted, limit)?;
+    Ok(format_history_preview(&messages, omitted))
+}
+
 fn render_chat_history_list(f: &mut Frame, area: Rect, app: &mut App) {
     let block = Block::new().padding(Padding::uniform(1));
+    if app.chat_list.items.is_empty() {
+        render_empty_state(f, area, block, "No saved conversations yet", app.theme.hint);
+        return;
+    }
 
     // Iterate through all elements in the `items` and stylize them.
     let items: Vec<ListItem> = app
         .chat_list
         .items
         .iter()
-        .map(|c| ListItem::from(format!("Chat created {}", c.started_at)))
+        .map(|c| {
+            let star = if c.favorite { "★" } else { " " };
+            ListItem::from(format!("{} Chat created {}", star, c.started_at))
+        })
         .collect();
 
     // Create a List from all list items and highlight the currently selected one
     let list = List::new(items)
         .block(block)
-        .highlight_style(SELECTED_STYLE)
+        .highlight_style(app.theme.list_highlight)
         .highlight_symbol(">")
         .highlight_spacing(HighlightSpacing::Always);
 
@@ -416,3 +1267,103 @@ fn render_chat_history_list(f: &mut Frame, area: Rect, app: &mut App) {
     // same method name `render`.
     f.render_stateful_widget(list, area, &mut app.chat_list.state);
 }
+
+/// Keys that quit from normal mode.
+fn quit_keys(app: &App) -> &'static str {
+    match app.quit_key {
+        QuitKey::Disabled => "Esc",
+        _ => "Esc/q",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_highlight_matches_ignores_case() {
+        let text = highlight_matches("Rust and rust", "RUST", Theme::default().search_match);
+        let highlighted = text.lines[0]
+            .spans
+            .iter()
+            .filter(|span| span.style.bg.is_some())
+            .map(|span| span.content.as_ref())
+            .collect::<Vec<&str>>();
+        assert_eq!(highlighted, vec!["Rust", "rust"]);
+    }
+
+    #[test]
+    fn test_format_history_preview_notes_omitted_messages() {
+        let messages = [Message::User("hi".to_string())];
+        assert_eq!(format_history_preview(&messages, 0), "USER: hi\n");
+        assert_eq!(
+            format_history_preview(&messages, 3),
+            "... 3 earlier message(s) not shown\n\nUSER: hi\n"
+        );
+    }
+
+    #[test]
+    fn test_wrap_message_indents_continuation_lines() {
+        let text = "one two three\n```\nfour five six\n```";
+        assert_eq!(
+            wrap_message(text, 9, 2),
+            ["one two", "  three", "```", "four five", "six", "```"]
+        );
+        assert_eq!(wrap_message(text, 9, 0)[1], "three");
+    }
+
+    #[test]
+    fn test_line_cache_renders_changed_messages() {
+        let theme = Theme::default();
+        let layout = LineLayout {
+            width: 20,
+            compact: false,
+            plain: false,
+            show_response_info: false,
+            wrap_indent: WrapIndent::default(),
+        };
+        let mut messages = vec![Message::User("hello".to_string())];
+        let mut cache = LineCache::default();
+        cache.update(&messages, layout, &theme);
+        assert_eq!(
+            cache.len(),
+            message_line_count(&messages[0], 20, false, false, &layout.wrap_indent)
+        );
+
+        messages[0] = Message::User("a longer message that wraps".to_string());
+        cache.update(&messages, layout, &theme);
+        assert_eq!(
+            cache.len(),
+            message_line_count(&messages[0], 20, false, false, &layout.wrap_indent)
+        );
+
+        let compact = LineLayout {
+            compact: true,
+            ..layout
+        };
+        cache.update(&messages, compact, &theme);
+        assert_eq!(
+            cache.len(),
+            message_line_count(&messages[0], 20, true, false, &layout.wrap_indent)
+        );
+
+        let plain = LineLayout {
+            plain: true,
+            ..layout
+        };
+        cache.update(&messages, plain, &theme);
+        assert_eq!(
+            cache.len(),
+            message_line_count(&messages[0], 20, false, true, &layout.wrap_indent)
+        );
+        assert!(cache
+            .lines()
+            .next()
+            .unwrap()
+            .to_string()
+            .starts_with("User: "));
+
+        cache.update(&[], compact, &theme);
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -0,0 +1,65 @@
+use ratatui::{text::Text, widgets::ListState};
+
+use crate::{app::Message, diff::diff_text, theme::Theme};
+
+/// Alternative responses to the same prompt.
+#[derive(Debug, Default)]
+pub struct VariationList {
+    pub items: Vec<VariationItem>,
+    pub state: ListState,
+    /// Answer the variations replace, kept to compare them against
+    pub previous: Option<String>,
+    /// Show the preview as a diff against the previous answer
+    pub show_diff: bool,
+    /// Diff of a variation against the previous answer and its index, kept
+    /// between frames as it is costly to compute
+    diff: Option<(usize, Text<'static>)>,
+}
+
+#[derive(Debug)]
+pub struct VariationItem {
+    pub message: Message,
+}
+
+impl VariationItem {
+    pub fn is_error(&self) -> bool {
+        matches!(self.message, Message::Error(_))
+    }
+}
+
+impl VariationList {
+    pub fn clear(&mut self) {
+        self.items.clear();
+        self.state.select(None);
+        self.previous = None;
+        self.diff = None;
+    }
+
+    /// Diff of the selected variation against the previous answer, only
+    /// computed again when another variation is selected.
+    pub fn selected_diff(&mut self, theme: &Theme) -> Option<&Text<'static>> {
+        let i = self.state.selected()?;
+        let previous = self.previous.as_deref()?;
+        let item = self.items.get(i)?;
+        if self.diff.as_ref().is_none_or(|(j, _)| *j != i) {
+            self.diff = Some((i, diff_text(previous, item.message.as_ref(), theme)));
+        }
+        self.diff.as_ref().map(|(_, text)| text)
+    }
+}
+
+impl FromIterator<Message> for VariationList {
+    fn from_iter<I: IntoIterator<Item = Message>>(iter: I) -> Self {
+        let items = iter
+            .into_iter()
+            .map(|message| VariationItem { message })
+            .collect();
+        let mut state = ListState::default();
+        state.select_first();
+        Self {
+            items,
+            state,
+            ..Default::default()
+        }
+    }
+}
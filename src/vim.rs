@@ -0,0 +1,142 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use tui_textarea::{CursorMove, TextArea};
+
+/// Submode of the input area with the vim key bindings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum VimMode {
+    /// Keys are typed into the input
+    #[default]
+    Insert,
+    /// Keys move the cursor and edit the input
+    Normal,
+}
+
+/// State of the vim key bindings of the input area.
+#[derive(Debug, Clone, Default)]
+pub struct Vim {
+    pub mode: VimMode,
+    /// `d` was pressed, waiting for the second `d`
+    pending_delete: bool,
+}
+
+impl Vim {
+    /// Handles a key of the input area, returns whether it was handled.
+    ///
+    /// Keys with `Ctrl` are left to the other bindings of the input area, so
+    /// is `Esc` in normal mode, which stops editing.
+    pub fn handle_key(&mut self, textarea: &mut TextArea, key_event: KeyEvent) -> bool {
+        if key_event.modifiers.contains(KeyModifiers::CONTROL) {
+            return false;
+        }
+        match self.mode {
+            VimMode::Insert => {
+                if key_event.code != KeyCode::Esc {
+                    return false;
+                }
+                self.mode = VimMode::Normal;
+                // Like vim, the cursor goes back onto the last inserted char
+                if textarea.cursor().1 > 0 {
+                    textarea.move_cursor(CursorMove::Back);
+                }
+                true
+            }
+            VimMode::Normal => {
+                let pending_delete = std::mem::take(&mut self.pending_delete);
+                match key_event.code {
+                    KeyCode::Esc => return false,
+                    KeyCode::Char('d') if pending_delete => delete_line(textarea),
+                    KeyCode::Char('d') => self.pending_delete = true,
+                    KeyCode::Char('i') => self.mode = VimMode::Insert,
+                    KeyCode::Char('a') => {
+                        let (row, col) = textarea.cursor();
+                        if col < textarea.lines()[row].chars().count() {
+                            textarea.move_cursor(CursorMove::Forward);
+                        }
+                        self.mode = VimMode::Insert;
+                    }
+                    KeyCode::Char('A') => {
+                        textarea.move_cursor(CursorMove::End);
+                        self.mode = VimMode::Insert;
+                    }
+                    KeyCode::Char('I') => {
+                        textarea.move_cursor(CursorMove::Head);
+                        self.mode = VimMode::Insert;
+                    }
+                    KeyCode::Char('o') => {
+                        textarea.move_cursor(CursorMove::End);
+                        textarea.insert_newline();
+                        self.mode = VimMode::Insert;
+                    }
+                    KeyCode::Char('h') | KeyCode::Left => textarea.move_cursor(CursorMove::Back),
+                    KeyCode::Char('l') | KeyCode::Right => {
+                        textarea.move_cursor(CursorMove::Forward)
+                    }
+                    KeyCode::Char('j') | KeyCode::Down => textarea.move_cursor(CursorMove::Down),
+                    KeyCode::Char('k') | KeyCode::Up => textarea.move_cursor(CursorMove::Up),
+                    KeyCode::Char('w') => textarea.move_cursor(CursorMove::WordForward),
+                    KeyCode::Char('b') => textarea.move_cursor(CursorMove::WordBack),
+                    KeyCode::Char('0') | KeyCode::Home => textarea.move_cursor(CursorMove::Head),
+                    KeyCode::Char('$') | KeyCode::End => textarea.move_cursor(CursorMove::End),
+                    KeyCode::Char('x') => {
+                        textarea.delete_next_char();
+                    }
+                    KeyCode::Char('u') => {
+                        textarea.undo();
+                    }
+                    // Other keys do nothing instead of being typed
+                    _ => {}
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Deletes the line of the cursor, like `dd`.
+fn delete_line(textarea: &mut TextArea) {
+    let (row, _) = textarea.cursor();
+    let n_rows = textarea.lines().len();
+    textarea.move_cursor(CursorMove::Head);
+    // At the end of a line the line break would be deleted instead
+    if !textarea.lines()[row].is_empty() {
+        textarea.delete_line_by_end();
+    }
+    if row + 1 < n_rows {
+        textarea.delete_next_char();
+    } else if row > 0 {
+        textarea.delete_char();
+        textarea.move_cursor(CursorMove::Head);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn press(vim: &mut Vim, textarea: &mut TextArea, keys: &str) {
+        for c in keys.chars() {
+            let code = if c == '\x1b' {
+                KeyCode::Esc
+            } else {
+                KeyCode::Char(c)
+            };
+            if !vim.handle_key(textarea, KeyEvent::from(code)) {
+                textarea.input(KeyEvent::from(code));
+            }
+        }
+    }
+
+    #[test]
+    fn test_normal_mode_edits() {
+        let mut vim = Vim::default();
+        let mut textarea = TextArea::default();
+        press(&mut vim, &mut textarea, "one two\nthree\x1b");
+        assert_eq!(vim.mode, VimMode::Normal);
+
+        press(&mut vim, &mut textarea, "kddx");
+        assert_eq!(textarea.lines(), ["hree"]);
+
+        press(&mut vim, &mut textarea, "A!\x1b0ix");
+        assert_eq!(textarea.lines(), ["xhree!"]);
+    }
+}